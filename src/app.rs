@@ -1,25 +1,45 @@
-use std::collections::HashMap;
 use std::{error, fs};
-use std::fs::File;
 use std::io::ErrorKind;
-use std::net::TcpStream;
-use std::time::Duration;
-use curl::easy::Easy;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tui::widgets::ScrollbarState;
 use websocket::sync::Client;
+use websocket::sync::stream::NetworkStream;
 use websocket::ws::dataframe::DataFrame;
-use websocket::{ClientBuilder, url::Url};
+use websocket::ClientBuilder;
 use websocket::{OwnedMessage, WebSocketError, CloseData};
-use flume::{Sender, Receiver};
+use websocket::result::WebSocketOtherError;
+use flume::{Sender, Receiver, TryRecvError};
 use std::thread::{self, JoinHandle};
-use rand::Rng;
 use serde_json::{Value, json};
 use chrono::prelude::*;
-use std::io::Write;
 
+use crate::auth::{LoginForm, Tokens};
+use crate::config::{Profile, UiConfig};
+use crate::connection::{ConnectionState, backoff_delay};
+use crate::moonraker::{
+    GcodeMetadata, HistoryJob, HistoryList, JsonRpcResponse, JsonRpcServerRequest, MoonrakerClient, ObjectList, ObjectStatus, RpcError, ServerInfo, WebcamList,
+};
+use crate::demo;
+use crate::transport::{Transport, UnixTransport, WebsocketTransport};
+use crate::inspector::Inspector;
+use crate::bed_mesh::{self, MeshView, MeshPrompt};
+use crate::record::{self, Frame, Recorder};
 use crate::printer::{Printer, Heater, PrintStats, FileMetadata, Webcam};
+use crate::server::{self, Server, Unauthorized};
 use crate::ui::stateful_list::StatefulList;
 
+/// Longest time spent handling websocket messages in a single tick, shared by all the printers
+pub const TICK_BUDGET: Duration = Duration::from_millis(50);
+/// How long errors stay in the header
+const NOTICE_DURATION: Duration = Duration::from_secs(10);
+/// Quiet time before Moonraker is pinged
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+/// The connection is taken for dead when a ping gets no answer in that time
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+/// A connection up for that long is not counted as a failed attempt anymore
+const STABLE_CONNECTION: Duration = Duration::from_secs(30);
+
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Tab {
@@ -33,45 +53,54 @@ pub enum Tab {
     ConsoleHelp,
     Webcam,
     WebcamHelp,
+    Objects,
+    ObjectsHelp,
+    BedMesh,
+    BedMeshHelp,
+    Leveling,
+    LevelingHelp,
 }
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
-pub struct JsonRpcResponse {
-    jsonrpc: String,
-    pub result: Value,
-    id: String,
-}
-
-#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
-pub struct JsonRpcServerRequest {
-    jsonrpc: String,
-    pub method: String,
-    pub params: Option<Value>,
-}
+/// Objects shown whatever the tab: header, temperatures, print progress and printer rows
+const STATUS_OBJECTS: &[&str] = &[
+    "webhooks", "heaters", "print_stats", "virtual_sdcard", "motion_report", "toolhead",
+    "quad_gantry_level", "z_tilt", "fan", "system_stats", "stepper_enable",
+];
+/// Prefixes of the objects named in the config that are shown whatever the tab
+const STATUS_OBJECT_PREFIXES: &[&str] = &[
+    "extruder", "heater_bed", "heater_generic ", "temperature_fan ",
+    "filament_switch_sensor ", "filament_motion_sensor ",
+];
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
-pub struct JsonRpcClientRequest {
-    jsonrpc: String,
-    pub method: String,
-    pub params: Value,
-    id: Option<String>,
-}
-
-impl JsonRpcClientRequest {
-    fn new() -> JsonRpcClientRequest {
-        let mut rng = rand::thread_rng();
-        let id = rng.gen::<u32>();
-        JsonRpcClientRequest {
-            jsonrpc: String::from("2.0"),
-            method: String::from(""),
-            params: json!({}),
-            id: Some(format!("{:x}", id)),
+impl Tab {
+    /// Objects the tab shows on top of the status objects, `None` when it needs all of them
+    pub fn objects(&self) -> Option<&'static [&'static str]> {
+        match self {
+            Tab::Objects | Tab::ObjectsHelp => None,
+            Tab::Main | Tab::Toolhead => Some(&["gcode_move"]),
+            Tab::BedMesh => Some(&["bed_mesh"]),
+            Tab::Leveling => Some(&["screws_tilt_adjust"]),
+            _ => Some(&[]),
         }
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Eq, Hash)]
-pub struct RpcRequest {
-    pub method: String,
+/// Lengths that can be extruded or retracted at once, in mm
+pub const EXTRUDE_LENGTHS: &[f64] = &[1.0, 5.0, 10.0, 25.0, 50.0, 100.0];
+/// Feed rates for extruding and retracting, in mm/s
+pub const EXTRUDE_SPEEDS: &[f64] = &[1.0, 2.0, 5.0, 10.0];
+/// Klipper's default `min_extrude_temp`, for when `can_extrude` is not known
+const MIN_EXTRUDE_TEMP: f64 = 170.0;
+
+/// Distances moved by a jog key, in mm
+pub const JOG_STEPS: &[f64] = &[0.1, 1.0, 10.0, 50.0];
+/// Feed rates for jogging, in mm/s
+pub const JOG_SPEEDS: &[f64] = &[10.0, 50.0, 100.0, 200.0];
+
+/// Message shown to the user in the header for a few seconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notice {
+    pub message: String,
+    pub shown_at: Instant,
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TabsState {
@@ -93,6 +122,66 @@ pub enum MainTabWidget {
     Temperatures,
 }
 
+/// Print control waiting for the user to confirm it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintAction {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+impl PrintAction {
+    pub fn method(&self) -> &'static str {
+        match self {
+            PrintAction::Pause => "printer.print.pause",
+            PrintAction::Resume => "printer.print.resume",
+            PrintAction::Cancel => "printer.print.cancel",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PrintAction::Pause => "pause",
+            PrintAction::Resume => "resume",
+            PrintAction::Cancel => "cancel",
+        }
+    }
+
+    /// Whether the action makes sense in the print state `state`
+    pub fn allowed(&self, state: &str) -> bool {
+        match self {
+            PrintAction::Pause => state == "printing",
+            PrintAction::Resume => state == "paused",
+            PrintAction::Cancel => state == "printing" || state == "paused",
+        }
+    }
+}
+
+/// Toolhead command waiting for the user to confirm it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolheadAction {
+    /// Turns the steppers off, the axes need homing again afterwards
+    MotorsOff,
+    /// Makes the current position the origin of gcode coordinates, without homing
+    ResetPosition,
+}
+
+impl ToolheadAction {
+    pub fn script(&self) -> &'static str {
+        match self {
+            ToolheadAction::MotorsOff => "M84",
+            ToolheadAction::ResetPosition => "G92 X0 Y0 Z0",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToolheadAction::MotorsOff => "motors off",
+            ToolheadAction::ResetPosition => "reset position",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum InputMode {
     Normal,
@@ -108,29 +197,89 @@ pub struct InputState {
 /// Application result type.
 pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 
-/// Application.
+/// Websocket client, over plain TCP or TLS.
+pub type WsClient = Client<Box<dyn NetworkStream + Send>>;
 
+/// Outcome of a connection attempt made by [`App::generate_client`].
+pub enum Connection {
+    Client(Box<dyn Transport>),
+    /// Moonraker refused the connection because of missing or invalid credentials
+    Unauthorized,
+    /// Moonraker could not be reached
+    Failed,
+}
+
+/// Application.
 pub struct App {
     /// Is the application running?
     pub running: bool,
-    pub starting: bool,
+    pub connection: ConnectionState,
+    /// Failed connection attempts since the last connection that stayed up
+    pub connection_attempts: u32,
+    /// When the current connection was opened, until it proves stable
+    pub connected_at: Option<Instant>,
     pub data: String,
     pub printer: Printer,
     pub rx: Option<Receiver<OwnedMessage>>,
-    pub tx: Option<Sender<OwnedMessage>>,
-    pub sent_messages: HashMap<String, RpcRequest>,
+    pub moonraker: MoonrakerClient<App>,
     pub current_tab: Tab,
     pub history: StatefulList<HistoryItem>,
-    pub ws_connected: bool,
-    pub client: Option<Client<TcpStream>>,
-    pub client_rx: Option<Receiver<Client<TcpStream>>>,
+    pub client: Option<WsClient>,
+    pub client_rx: Option<Receiver<Connection>>,
     pub selected_widget: MainTabWidget,
     pub console_scroll: u16,
     pub console_scroll_state: ScrollbarState,
     pub console_input: InputState,
     pub temperature_input: InputState,
     pub selected_heater: Option<Heater>,
-    pub server_url: String,
+    pub server: Server,
+    /// Set once `server.connection.identify` was sent on the current connection
+    pub identified: bool,
+    pub tokens: Option<Tokens>,
+    /// Set while retrying with a refreshed token after a refusal
+    pub auth_retry: bool,
+    /// Failed token refreshes in a row, and when to try again
+    pub refresh_failures: u32,
+    pub refresh_retry_at: Option<Instant>,
+    pub login: Option<LoginForm>,
+    /// Last error to show in the header
+    pub notice: Option<Notice>,
+    /// Events to tell the user about, even when another printer is shown
+    pub alerts: Vec<String>,
+    /// Name of the printer profile, or its address
+    pub name: String,
+    pub filament_diameter: f64,
+    /// Index in [`EXTRUDE_LENGTHS`] of the length to extrude
+    pub extrude_length: usize,
+    /// Index in [`EXTRUDE_SPEEDS`] of the feed rate
+    pub extrude_speed: usize,
+    /// Index in [`JOG_STEPS`] of the distance to jog
+    pub jog_step: usize,
+    /// Index in [`JOG_SPEEDS`] of the jogging feed rate
+    pub jog_speed: usize,
+    /// Gcode to load and unload filament
+    pub load_macro: String,
+    pub unload_macro: String,
+    /// Name of the webcam to show, the first one when not set
+    pub webcam: Option<String>,
+    /// Number of past jobs shown in the history
+    pub history_limit: u32,
+    /// Where thumbnails and webcam snapshots are saved
+    pub cache_dir: PathBuf,
+    /// Where websocket frames are recorded, see `--record`
+    pub recorder: Option<Recorder>,
+    /// Playing a recording or a demo, there is nothing to reconnect to
+    pub offline: bool,
+    pub inspector: Inspector,
+    pub bed_mesh: MeshView,
+    /// Pause, resume or cancel shown in a confirmation dialog
+    pub print_action: Option<PrintAction>,
+    /// Motors off or reset position shown in a confirmation dialog
+    pub toolhead_action: Option<ToolheadAction>,
+    /// Objects of the printer, from `printer.objects.list`
+    pub available_objects: Vec<String>,
+    /// Objects of the current subscription
+    pub subscribed: Vec<String>,
 }
 
 
@@ -138,15 +287,15 @@ impl Default for App {
     fn default() -> Self {
         Self {
             running: true,
-            starting: true,
+            connection: ConnectionState::Connecting,
+            connection_attempts: 0,
+            connected_at: None,
             data: String::from(""),
             printer: Printer::new(),
-            tx: None,
             rx: None,
-            sent_messages: HashMap::new(),
+            moonraker: MoonrakerClient::new(),
             current_tab: Tab::Main,
             history: StatefulList::with_items(vec![]),
-            ws_connected: false,
             client: None,
             client_rx: None,
             selected_widget: MainTabWidget::History,
@@ -155,138 +304,204 @@ impl Default for App {
             console_input: InputState { mode: InputMode::Normal, value: "".to_string(), cursor_position: 0 },
             temperature_input: InputState { mode: InputMode::Editing, value: "".to_string(), cursor_position: 1 },
             selected_heater: None,
-            server_url: "".to_string(),
+            server: Server::parse("localhost", None).expect("valid default server"),
+            identified: false,
+            tokens: None,
+            auth_retry: false,
+            refresh_failures: 0,
+            refresh_retry_at: None,
+            login: None,
+            notice: None,
+            alerts: vec![],
+            name: "localhost".to_string(),
+            filament_diameter: 1.75,
+            extrude_length: 2,
+            extrude_speed: 2,
+            jog_step: 1,
+            jog_speed: 1,
+            load_macro: "LOAD_FILAMENT".to_string(),
+            unload_macro: "UNLOAD_FILAMENT".to_string(),
+            webcam: None,
+            history_limit: 100,
+            cache_dir: PathBuf::from("cache"),
+            recorder: None,
+            offline: false,
+            inspector: Inspector::default(),
+            bed_mesh: MeshView::default(),
+            print_action: None,
+            toolhead_action: None,
+            available_objects: vec![],
+            subscribed: vec![],
         }
     }
 }
 
 impl App {
     /// Constructs a new instance of [`App`].
-    pub fn new(server_url: String) -> Self {
-        let mut app = Self::default();
-        app.server_url = server_url;
-        app
+    pub fn new(server: Server) -> Self {
+        Self {
+            name: server.name(),
+            server,
+            ..Self::default()
+        }
+    }
+
+    /// Constructs the [`App`] of a printer profile
+    pub fn from_profile(name: &str, profile: &Profile, ui: &UiConfig) -> AppResult<Self> {
+        let mut server = Server::parse(profile.url.as_str(), profile.api_key.clone())?;
+        server.username = profile.user.clone();
+        Ok(Self {
+            name: name.to_string(),
+            filament_diameter: profile.filament_diameter,
+            load_macro: profile.load_macro.clone(),
+            unload_macro: profile.unload_macro.clone(),
+            webcam: profile.webcam.clone(),
+            history_limit: ui.history_limit,
+            cache_dir: ui.cache_dir.clone(),
+            ..Self::new(server)
+        })
     }
 
-    fn generate_client(&mut self, tx: Sender<Client<TcpStream>>) -> JoinHandle<()> {
-        let u = self.server_url.clone();
+    /// Makes one connection attempt in another thread, and sends the outcome to `tx`.
+    fn generate_client(&mut self, tx: Sender<Connection>) -> JoinHandle<()> {
+        let server = self.server.clone();
         
         let get_client = thread::spawn(move || {
             log::debug!("Generating client");
-            loop {
-                let server = format!("ws://{}/websocket", u);
-                let url = Url::parse(server.as_str()).unwrap();
-                let mut builder = ClientBuilder::from_url(&url);
-                match builder.connect_insecure() {
-                    Ok(c) => {
-                        // Send message to main thread
-                        let _ = tx.send(c);
-                        break;
-                    },
+            if let Some(path) = server.socket_path() {
+                // Local connections need no authentication
+                let connection = match UnixTransport::connect(&path) {
+                    Ok(t) => Connection::Client(Box::new(t)),
                     Err(e) => {
-                        log::error!("Client connect fail : {:?}", e);
-                        continue;
+                        log::error!("Could not connect to {} : {:?}", path.display(), e);
+                        Connection::Failed
                     },
                 };
+                let _ = tx.send(connection);
+                return;
             }
+            // Oneshot tokens are only valid for a few seconds, get a new one for each attempt
+            let token = match server.oneshot_token() {
+                Ok(t) => t,
+                Err(e) if e.is::<Unauthorized>() => {
+                    log::error!("API key refused");
+                    let _ = tx.send(Connection::Unauthorized);
+                    return;
+                },
+                Err(e) => {
+                    log::error!("Could not get oneshot token : {:?}", e);
+                    None
+                }
+            };
+            let url = server.websocket_url(token.as_deref());
+            let mut builder = ClientBuilder::from_url(&url);
+            let connection = match builder.connect(None) {
+                Ok(c) => {
+                    let stream = c.stream_ref().as_tcp();
+                    let _ = stream.set_read_timeout(Some(Duration::from_millis(100)));
+                    let _ = stream.set_write_timeout(Some(Duration::from_secs(5)));
+                    Connection::Client(Box::new(WebsocketTransport::client(c)))
+                },
+                Err(WebSocketError::Other(e)) if matches!(
+                    e.downcast_ref::<WebSocketOtherError>(),
+                    Some(WebSocketOtherError::StatusCodeError(status)) if matches!(status.to_u16(), 401 | 403)
+                ) => {
+                    log::error!("Websocket connection refused : {:?}", e);
+                    Connection::Unauthorized
+                },
+                Err(e) => {
+                    log::error!("Client connect fail : {:?}", e);
+                    Connection::Failed
+                },
+            };
+            // Send message to main thread
+            let _ = tx.send(connection);
         });
         get_client
     }
 
-    pub fn start(&mut self, client: Client<TcpStream>) {
-        let stream = client.stream_ref();
-        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
-        let _ = stream.set_write_timeout(Some(Duration::from_secs(5)));
-        let (mut receiver, mut sender) = client.split().unwrap();
+    pub fn start(&mut self, mut transport: Box<dyn Transport>) {
+        // TLS streams cannot be split in a reader and a writer, so a single thread
+        // owns the transport and alternates between sending and receiving.
+        // Transports keep what they read partly when a read times out.
 
-        
         let (send_tx, send_rx) = flume::unbounded();
         let (rcv_tx, rcv_rx) =flume::unbounded();
-        
-    
-        let tx_1 = send_tx.clone();
-        self.tx = Some(send_tx);
+
+        self.moonraker.tx = Some(send_tx);
         self.rx = Some(rcv_rx);
-        self.ws_connected = true;
+        let recorder = self.recorder.clone();
 
-        let _send_loop = thread::spawn(move || {
+        let _connection_loop = thread::spawn(move || {
+            let close_message = OwnedMessage::Close(Some(CloseData::new(1, "reset".to_string())));
+            let mut last_received = Instant::now();
+            // When the last ping was sent, until something is received
+            let mut ping_sent: Option<Instant> = None;
             loop {
                 // Send loop
-                match send_rx.recv() {
-                    Ok(m) => {
-                        let mut d2: Vec<u8> = vec![0, 1];
-                        d2.append(&mut "reset".to_string().into_bytes());
-                        log::debug!("sending message {:?}", m);
-                        if m.is_data() {
-                            let _ = sender.send_message(&m);
-                        } else if m.is_close() {
-                            // Tell the server to close the connection
-                            let _ = sender.send_message(&m);
-                            let pl = m.take_payload();
-                            if pl == d2 {
-                                log::debug!("exiting sending thread {:?}", pl);
-                                break;
-                            }
+                for m in send_rx.try_iter() {
+                    log::debug!("sending message {:?}", m);
+                    if m.is_data() {
+                        if let (Some(r), OwnedMessage::Text(t)) = (&recorder, &m) {
+                            r.write(true, t);
                         }
-                    },
-                    Err(_e) => {}
-                };
-            }
-        });
-
+                        let _ = transport.send(&m);
+                    } else if m.is_close() {
+                        // Tell the server to close the connection
+                        let _ = transport.send(&m);
+                        if m == close_message {
+                            log::debug!("exiting connection thread");
+                            return;
+                        }
+                    }
+                }
 
-        let _receive_loop = thread::spawn(move || {
-            // Receive loop
-            let close_message = OwnedMessage::Close(Some(CloseData::new(1, "reset".to_string())));
-            for message in receiver.incoming_messages() {
-                let message = match message {
+                // Receive loop
+                let message = match transport.recv() {
                     Ok(m) => m,
-                    Err(e) => {
-                        match e {
-                            WebSocketError::NoDataAvailable => {
-                                log::error!("Websocket error {:?}", e);
-                                // Kill sending thread
-                                let _ = tx_1.send(close_message.clone());
-                                // Tell main thread that the connection has closed
-                                let _ = rcv_tx.send(close_message);
-                                // exit loop and terminate thread
-                                log::debug!("exiting receiving thread");
-                                break;
+                    Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {
+                        // Nothing to read, ping Moonraker when it has been quiet for a while
+                        if !transport.answers_pings() {
+                            continue;
+                        }
+                        match ping_sent {
+                            None if last_received.elapsed() >= PING_INTERVAL => {
+                                let _ = transport.send(&OwnedMessage::Ping(vec![]));
+                                ping_sent = Some(Instant::now());
+                                continue;
                             },
-                            WebSocketError::IoError(err) => {
-                                log::error!("Websocket error {:?}", err);
-                                if err.kind() == ErrorKind::ConnectionReset {
-                                    // Kill sending thread
-                                    let _ = tx_1.send(close_message.clone());
-                                    // Tell main thread that the connection has closed
-                                    let _ = rcv_tx.send(close_message);
-                                    // exit loop and terminate thread
-                                    log::debug!("exiting receiving thread");
-                                    break;
-                                } else if err.kind() == ErrorKind::WouldBlock {
-                                    // Kill sending thread
-                                    let _ = tx_1.send(close_message.clone());
-                                    // Tell main thread that the connection has closed
-                                    let _ = rcv_tx.send(close_message);
-                                    // exit loop and terminate thread
-                                    log::debug!("exiting receiving thread");
-                                    break;
-                                }
-                            }
-                            _ => {
-                                log::error!("Websocket error {:?}", e);
-                            }
-                        };
-                        
-                        OwnedMessage::Close(None)
+                            Some(sent) if sent.elapsed() >= PONG_TIMEOUT => {},
+                            _ => continue,
+                        }
+                        log::error!("No pong from Moonraker {:?}", err);
+                        // Tell main thread that the connection has closed
+                        let _ = rcv_tx.send(close_message);
+                        log::debug!("exiting connection thread");
+                        break;
+                    },
+                    Err(e) if e.kind() == ErrorKind::InvalidData => {
+                        log::error!("Websocket error {:?}", e);
+                        continue;
+                    },
+                    Err(e) => {
+                        log::error!("Websocket error {:?}", e);
+                        // Tell main thread that the connection has closed
+                        let _ = rcv_tx.send(close_message);
+                        log::debug!("exiting connection thread");
+                        break;
                     }
                 };
+                last_received = Instant::now();
+                ping_sent = None;
                 match message {
-                    OwnedMessage::Text(_) => {
+                    OwnedMessage::Text(ref t) => {
+                        if let Some(r) = &recorder {
+                            r.write(false, t);
+                        }
                         let _ = rcv_tx.send(message);
                     },
-                    OwnedMessage::Ping(_) => {
-                        let _ = tx_1.send(OwnedMessage::Pong(vec![]));
+                    OwnedMessage::Ping(data) => {
+                        let _ = transport.send(&OwnedMessage::Pong(data));
                     },
                     _ => {},
                 }
@@ -295,87 +510,149 @@ impl App {
         
     }
 
+    /// Plays a recorded session instead of connecting to the printer.
+    ///
+    /// `speed` is relative to the recorded pace, 2.0 plays twice as fast.
+    pub fn replay(&mut self, frames: Vec<Frame>, speed: f64) {
+        self.start_offline(move |requests, messages| record::play(frames, speed, requests, messages));
+    }
+
+    /// Talks to a simulated printer instead of connecting to one
+    pub fn demo(&mut self) {
+        self.start_offline(demo::run);
+    }
+
+    /// Exchanges messages with `peer` in another thread, as it would with Moonraker
+    fn start_offline(&mut self, peer: impl FnOnce(Receiver<OwnedMessage>, Sender<OwnedMessage>) + Send + 'static) {
+        let (send_tx, send_rx) = flume::unbounded();
+        let (rcv_tx, rcv_rx) = flume::unbounded();
+        self.moonraker.tx = Some(send_tx);
+        self.rx = Some(rcv_rx);
+        self.offline = true;
+        self.connection = ConnectionState::KlippyNotReady;
+        thread::spawn(move || peer(send_rx, rcv_tx));
+        self.send_start_messages();
+    }
+
     /// Handles the tick event of the terminal.
     pub fn tick(&mut self) {
-        // read incoming websockets messages
-        if let Some(rx) = &self.rx {
-            let message = match rx.try_recv() {
+        self.tick_until(Instant::now() + TICK_BUDGET);
+    }
+
+    /// Handles the tick event, reading messages until `deadline` at most
+    pub fn tick_until(&mut self, deadline: Instant) {
+        let now = Instant::now();
+        if let Some(tokens) = &self.tokens {
+            let refreshing = self.moonraker.is_pending("access.refresh_jwt");
+            // After a failure, wait before asking again, even before identifying
+            let due = self.refresh_retry_at.map_or(self.identified, |at| now >= at);
+            if due && !refreshing && tokens.needs_refresh(Utc::now()) {
+                self.refresh_tokens();
+            }
+        }
+        for done in self.moonraker.expire(now) {
+            done(self);
+        }
+        if self.notice.as_ref().is_some_and(|n| now.saturating_duration_since(n.shown_at) >= NOTICE_DURATION) {
+            self.notice = None;
+        }
+        match self.connection {
+            ConnectionState::Connecting => {
+                self.try_init();
+                return;
+            },
+            ConnectionState::Backoff { retry_at, .. } => {
+                if Instant::now() >= retry_at {
+                    self.init();
+                }
+                return;
+            },
+            _ => {},
+        }
+        if self.connected_at.is_some_and(|at| now.saturating_duration_since(at) >= STABLE_CONNECTION) {
+            self.connected_at = None;
+            self.connection_attempts = 0;
+        }
+        self.update_subscription();
+
+        // read incoming websockets messages, until the queue is empty or the
+        // deadline passed so key presses are not kept waiting
+        while Instant::now() < deadline {
+            let message = match self.rx.as_ref().map(|rx| rx.try_recv()) {
+                Some(Ok(m)) => m,
+                Some(Err(TryRecvError::Disconnected)) if self.offline => {
+                    // The recording is over, there is nothing to reconnect to
+                    self.rx = None;
+                    break;
+                },
+                Some(Err(TryRecvError::Disconnected)) => {
+                    log::debug!("Connection thread is gone");
+                    self.connection_lost();
+                    break;
+                },
+                _ => break,
+            };
+            self.handle_message(message);
+        }
+    }
+
+    fn handle_message(&mut self, message: OwnedMessage) {
+        if message.is_close() {
+            // Closing message, means we lost connection and have to restart
+            let d2 = OwnedMessage::Close(Some(CloseData::new(1, "reset".to_string())));
+
+            if message == d2 {
+                log::debug!("Backing off because of close message reset");
+                self.connection_lost();
+            }
+        } else if message.is_data() {
+            let v = message.take_payload();
+            let s: String = match String::from_utf8(v) {
                 Ok(m) => m,
                 Err(_e) => {
-                    log::debug!("Receive error {:?}", _e);
+                    log::debug!("decode error {:?}", _e);
                     return;
                 }
             };
 
+            // try to parse as a response to one of our requests
+            let response: Option<JsonRpcResponse> = match serde_json::from_str(s.as_str()) {
+                Ok(m) => Some(m),
+                Err(_e) => {
+                    log::debug!("not a response error {:?}", _e);
+                    None
+                }
+            };
 
-            if message.is_close() {
-                // todo make sure 
-                // Closing message, means we lost connection and have to restart
-                
-                let d2 = OwnedMessage::Close(Some(CloseData::new(1, "reset".to_string())));
-
-                if message.clone() == d2{
-                    self.ws_connected = false;
-                    self.starting = true;
-                    self.printer.connected = false;
-                    // self.printer.status.state = "error".to_string();
-                    log::debug!("Doing init because of close message reset");
-                    self.init();
-                    return;
-                } 
-            } else if message.is_data() {
-                let v = message.take_payload();
-                let s: String = match String::from_utf8(v) {
-                    Ok(m) => m,
-                    Err(_e) => {
-                        log::debug!("decode error {:?}", _e);
-                        return;
-                    }
-                };
-    
-                // try to parse as a response to one of our requests
-                let response: Option<JsonRpcResponse> = match serde_json::from_str(s.as_str()) {
+            if let Some(response) = response {
+                // This is a response to one of our requests, handle it
+                self.handle_response(response);
+            } else {
+                let request: Option<JsonRpcServerRequest> = match serde_json::from_str(s.as_str()) {
                     Ok(m) => Some(m),
                     Err(_e) => {
-                        log::debug!("not a response error {:?}", _e);
+                        log::debug!("not a server request error {:?}", _e);
                         None
                     }
                 };
-    
-                if response.is_some() {
-                    // This is a response to one of our requests, handle it
-                    self.handle_response(response.unwrap());
-                } else {
-                    let request: Option<JsonRpcServerRequest> = match serde_json::from_str(s.as_str()) {
-                        Ok(m) => Some(m),
-                        Err(_e) => {
-                            log::debug!("not a server request error {:?}", _e);
-                            None
-                        }
-                    };
-                    if request.is_some() {
-                        self.handle_request(request.unwrap());
-                    }
+                if let Some(request) = request {
+                    self.handle_request(request);
                 }
             }
-        } else {
-            if self.starting == true {
-                self.try_init();
-                return;
-            }
         }
-        
-        
-        //println!("ws message: {:?}", message);
     }
 
     pub fn init(&mut self) {
         log::debug!("App init start");
-        self.printer.connected = false;
+        self.connection = ConnectionState::Connecting;
         // self.printer.status.state = "error".to_string();
 
         self.rx = None;
-        self.tx = None;
+        self.identified = false;
+        // Responses to pending requests can't arrive on a new connection, tell the user they failed
+        for done in self.moonraker.disconnect() {
+            done(self);
+        }
 
         let (client_tx, client_rx) = flume::unbounded();
         self.client_rx = Some(client_rx);
@@ -387,283 +664,326 @@ impl App {
 
     fn try_init(&mut self) {
         match self.client_rx.as_ref().expect("No client").try_recv() {
-            Ok(c) => {
+            Ok(Connection::Client(c)) => {
                 log::debug!("Client connected");
+                // Klippy state is unknown until server.info answers
+                self.connection = ConnectionState::KlippyNotReady;
+                // Servers may accept and close right away, attempts only reset once the connection lasts
+                self.connected_at = Some(Instant::now());
                 self.start(c);
 
                 self.send_start_messages();
-                self.starting = false;
             },
+            Ok(Connection::Unauthorized) => {
+                self.connection = ConnectionState::AuthFailed;
+            },
+            Ok(Connection::Failed) => self.back_off(),
             Err(e) => {
                 log::debug!("Client connection error {:?}", e);
             },
         };
     }
 
+    /// Waits before the next connection attempt, a bit longer after each failure
+    fn back_off(&mut self) {
+        self.connection_attempts = self.connection_attempts.saturating_add(1);
+        self.connection = ConnectionState::Backoff {
+            attempt: self.connection_attempts,
+            retry_at: Instant::now() + backoff_delay(self.connection_attempts),
+        };
+    }
+
+    /// Stops reading the lost connection, and reconnects once backed off
+    fn connection_lost(&mut self) {
+        self.connected_at = None;
+        self.rx = None;
+        self.moonraker.tx = None;
+        self.identified = false;
+        self.back_off();
+    }
+
     fn send_start_messages(&mut self) {
-        self.send_message(String::from("server.connection.identify"), json!({
+        if let Some(tokens) = &self.tokens {
+            if tokens.needs_refresh(Utc::now()) {
+                // Identify once we have a valid token
+                self.refresh_tokens();
+                return;
+            }
+        }
+        let mut identify = json!({
             "client_name": "Krui",
             "version":	env!("CARGO_PKG_VERSION"),
             "type":	"desktop",
             "url":	"https://github.com/jfoucher/krui"
-        }));
-        self.send_message(String::from("server.info"), serde_json::Value::Object(serde_json::Map::new()));
-        self.send_message(String::from("printer.objects.list"), serde_json::Value::Object(serde_json::Map::new()));
-        self.send_message(String::from("server.history.list"), json!({
-            "limit": 100,
-            "start": 0,
-            "since": 0,
-            "order": "desc"
-        }));
-        self.send_message(String::from("server.webcams.list"), json!({}));
+        });
+        if let Some(key) = &self.server.api_key {
+            identify["api_key"] = json!(key);
+        }
+        if let Some(tokens) = &self.tokens {
+            identify["access_token"] = json!(tokens.access_token);
+        }
+        self.send_message(String::from("server.connection.identify"), identify);
+        self.identified = true;
+        self.available_objects.clear();
+        self.moonraker.server_info().then(on_success("server.info", App::on_server_info));
+        self.moonraker.objects_list().then(on_success("printer.objects.list", App::on_objects_list));
+        self.moonraker.history_list(self.history_limit).then(on_success("server.history.list", App::on_history_list));
+        self.moonraker.webcams_list().then(on_success("server.webcams.list", App::on_webcams_list));
     }
 
     pub fn handle_response(&mut self, response: JsonRpcResponse) {
-        // find the original method id from our hashmap
-        
-        let method = match self.sent_messages.get(&response.id) {
-            Some(m) => {
-                m.method.clone()
-            }
-            None => "".to_string()
+        if let Some(done) = self.moonraker.resolve(response) {
+            done(self);
+        }
+    }
+
+    /// Tells the user about a failed request, unless a new login fixes it
+    fn request_failed(&mut self, method: &str, error: RpcError) {
+        log::error!("{} failed : {}", method, error);
+        if error.code() == Some(401) {
+            self.handle_unauthorized(method);
+        } else {
+            self.report_error(method, error.to_string().as_str());
+        }
+    }
+
+    fn on_login(&mut self, result: Result<Value, RpcError>) {
+        let tokens = match result {
+            Ok(r) => Tokens::from_login(&r).ok_or_else(|| "Unexpected login response".to_string()),
+            Err(e) => Err(e.to_string()),
         };
-        
-        if method.len() > 0 {
-            // Remove from hashmap
-            self.sent_messages.remove(&response.id);
-
-            match method.as_str() {
-                "server.info"=> {
-                    log::debug!("server.info {:?}", response.result);
-                    self.printer.connected = false;
-                    if let Some(klc) = response.result.get("klippy_connected") {
-                        if klc.as_bool().unwrap() {
-                            if let Some(kls) = response.result.get("klippy_state") {
-                                if kls.as_str().unwrap() == "ready" {
-                                    self.printer.connected = true;
-                                }
-                            }
-                        }
-                    }
-                }
-                "server.files.metadata" => {
-                    log::info!("server.files.metadata {}", serde_json::to_string(&response.result).unwrap());
-                    let mut current_print = PrintStats::new();
-                
-                    if let Some(cp) = &self.printer.current_print {
-                        current_print = cp.clone();
-                    }
-                    current_print.file = FileMetadata {
-                        size: response.result["size"].as_u64().unwrap(),
-                        slicer: response.result["slicer"].as_str().unwrap().to_string(),
-                        layer_height: response.result["layer_height"].as_f64().unwrap(),
-                        first_layer_height: response.result["first_layer_height"].as_f64().unwrap(),
-                        object_height: response.result["object_height"].as_f64().unwrap(),
-                        filament_total: response.result["filament_total"].as_f64().unwrap(),
-                        estimated_time: response.result["estimated_time"].as_f64().unwrap(),
-                    };
-                    let mut easy = Easy::new();
-                    let res = response.result;
-                    let u = self.server_url.clone();
-                    if let Some(thumbnails) = res.get("thumbnails") {
-                        let ths = thumbnails.as_array().unwrap();
-                        // Get last thumbnail as it seems to be the biggest one
-                        if let Some(thumbnail) = thumbnails.get(ths.len()-1) {
-                            log::info!("Thumbnail {:?}", thumbnail);
-                            if let Some(path) = thumbnail.get("relative_path") {
-                                let np = path.as_str().unwrap().clone();
-                                log::info!("Downloading thumbnail {:?}", format!("http://{}/server/files/gcodes/{}", u, np.clone()).as_str());
-                                easy.url(format!("http://{}/server/files/gcodes/{}", u, np.clone()).as_str()).unwrap();
-                                let filename = np.clone().to_string();
-                                let _ = fs::create_dir_all("cache/.thumbs/");
-                                let filepath = format!("cache/{}", filename);
-                                current_print.image = filepath.clone();
-                                let file = match File::create(filepath.clone()) {
-                                    Ok(f) => Some(f),
-                                    Err(e) => {
-                                        log::error!("Error creating file {:?}", e);
-                                        None
-                                    }
-                                };
-                                if let Some(mut handle) = file {
-                                    let mut transfer = easy.transfer();
-                                    transfer.write_function(|data| {
-                                        // write data to a temporary file
-
-                                        handle.write_all(data).unwrap();
-                                        
-                                        Ok(data.len())
-                                    }).unwrap();
-                                    transfer.perform().unwrap();
-                                }
-                            }
-                        }
-                    }
-                    
-                    self.printer.current_print = Some(current_print);
+        match tokens {
+            Ok(tokens) => {
+                self.server.access_token = Some(tokens.access_token.clone());
+                self.server.username = Some(tokens.username.clone());
+                self.tokens = Some(tokens);
+                self.login = None;
+                self.auth_retry = false;
+                // Start over on a connection identified with the new token
+                self.reconnect();
+            },
+            Err(e) => {
+                log::error!("access.login failed : {}", e);
+                if let Some(form) = &mut self.login {
+                    form.error = Some(e);
                 }
-                "printer.objects.list" => {
-                    let json_objects: Vec<String> = serde_json::from_value(response.result["objects"].clone()).unwrap();
-                    let mut params = serde_json::Map::new();
-                    let mut objects = serde_json::Map::new();
-                    for ob in json_objects.iter() {
-                        objects.insert(ob.clone(), serde_json::Value::Null);
-                    }
-                    params.insert("objects".to_string(), objects.into());
+            },
+        }
+    }
 
-                    self.send_message("printer.objects.query".to_string(), serde_json::Value::Object(params.clone()));
-                    self.send_message("printer.objects.subscribe".to_string(), serde_json::Value::Object(params));
-                }
-                "printer.emergency_stop" => {
-                    // self.printer.connected = false;
-                    // self.printer.stats.state = "shutdown".to_string();
+    fn on_refresh_jwt(&mut self, result: Result<Value, RpcError>) {
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                if e.code() != Some(401) {
+                    self.refresh_failures += 1;
+                    self.refresh_retry_at = Some(Instant::now() + backoff_delay(self.refresh_failures));
                 }
-                "printer.objects.query" => {
-                    let data = response.result.get("status").unwrap().clone();
-                    self.printer.update(data.clone());
-
-                    if let Some(print_stats) = data.get("print_stats") {
-                        
-                        if let Some(state) = print_stats.get("state") {
-                            
-                            if state.as_str().unwrap() == "printing" {
-                                if let Some(filename) = print_stats.get("filename") {
-                                    log::info!("filename {:?}", filename.as_str().unwrap());
-                                    // If status is printing, get metadata
-                                    self.send_message("server.files.metadata".to_string(), json!({"filename": filename.as_str().unwrap()}));
-               
-                                }
-                            }
-                        }
-                    }
-                },
-                "server.history.list" => {
-                    if let Some(jobs) = response.result.get("jobs") {
+                self.request_failed("access.refresh_jwt", e);
+                return;
+            },
+        };
+        self.refresh_failures = 0;
+        self.refresh_retry_at = None;
+        if let Some(tokens) = &mut self.tokens {
+            if tokens.refresh(&result) {
+                self.server.access_token = Some(tokens.access_token.clone());
+            }
+        }
+        if !self.identified {
+            self.send_start_messages();
+        }
+    }
 
-                        if jobs.is_array() {
-                            for job in jobs.as_array().unwrap() {
-                                // println!("{:?}", job);
-                                self.add_job(job);
-                            }
-                            self.history.state.select(Some(0));
-                        }
+    fn on_server_info(&mut self, info: ServerInfo) {
+        // Only answered when we are authorized
+        self.auth_retry = false;
+        log::debug!("server.info {:?}", info);
+        if info.is_ready() {
+            self.connection = ConnectionState::Connected;
+        } else {
+            self.connection = ConnectionState::KlippyNotReady;
+            // webhooks can't tell us while Klippy is away
+            self.printer.status.state = info.klippy_state;
+        }
+    }
+
+    /// Asks for the metadata of `filename`, to show it with the current print
+    pub fn fetch_file_metadata(&mut self, filename: &str) {
+        self.moonraker.files_metadata(filename).then(on_success("server.files.metadata", App::on_file_metadata));
+    }
+
+    fn on_file_metadata(&mut self, metadata: GcodeMetadata) {
+        log::info!("server.files.metadata {:?}", metadata);
+        let mut current_print = PrintStats::new();
+    
+        if let Some(cp) = &self.printer.current_print {
+            current_print = cp.clone();
+        }
+        current_print.file = FileMetadata {
+            size: metadata.size,
+            slicer: metadata.slicer,
+            layer_height: metadata.layer_height,
+            first_layer_height: metadata.first_layer_height,
+            object_height: metadata.object_height,
+            filament_total: metadata.filament_total,
+            estimated_time: metadata.estimated_time,
+        };
+        // Get last thumbnail as it seems to be the biggest one
+        if let Some(thumbnail) = metadata.thumbnails.last() {
+            log::info!("Thumbnail {:?}", thumbnail);
+            let np = thumbnail.relative_path.as_str();
+            // The path comes from the server, it must not write outside of the cache
+            match server::relative_path(np) {
+                Some(relative) => {
+                    let filepath = self.cache_dir.join(relative);
+                    if let Some(dir) = filepath.parent() {
+                        let _ = fs::create_dir_all(dir);
                     }
-                },
-                "server.webcams.list" => {
-                    if let Some(webcams) = response.result.get("webcams") {
-                        
-                        if webcams.is_array() {
-                            for cam in webcams.as_array().unwrap() {
-                                let mut webcam = Webcam {
-                                    name: "".to_string(),
-                                    stream_url: "".to_string(),
-                                    snapshot_url: "".to_string(),
-                                    flip_horizontal: false,
-                                    flip_vertical: false,
-                                    render: 0,
-                                };
-                                log::info!("cam {:?}", cam);
-                                if let Some(name) = cam.get("name") {
-                                    webcam.name = name.as_str().unwrap().to_string();
-                                }
-                                if let Some(stream_url) = cam.get("stream_url") {
-                                    webcam.stream_url = stream_url.as_str().unwrap().to_string();
-                                }
-                                if let Some(snapshot_url) = cam.get("snapshot_url") {
-                                    webcam.snapshot_url = snapshot_url.as_str().unwrap().to_string();
-                                }
-                                if let Some(flip_horizontal) = cam.get("flip_horizontal") {
-                                    webcam.flip_horizontal = flip_horizontal.as_bool().unwrap();
-                                }
-                                if let Some(flip_vertical) = cam.get("flip_vertical") {
-                                    webcam.flip_vertical = flip_vertical.as_bool().unwrap();
-                                }
-                                self.printer.webcams.push(webcam);
-                            }
-                        }
+                    current_print.image = filepath.to_string_lossy().to_string();
+                    if let Err(e) = self.server.download(format!("server/files/gcodes/{}", np).as_str(), &filepath) {
+                        log::error!("Error downloading thumbnail {:?}", e);
                     }
-                }
-
-                _ => {}
+                },
+                None => log::error!("Ignoring thumbnail outside of the gcodes {:?}", np),
             }
         }
+        
+        self.printer.current_print = Some(current_print);
     }
 
-    pub fn add_job(&mut self, job: &Value) {
-        if let Some(filename) = job.get("filename") {
-            let filename = filename.as_str().unwrap().to_string();
-            if let Some(_) = self.history.items.iter().find(|i| {i.filename == filename}) {
-                return;
-            }
-            let mut h = HistoryItem {
-                filename: filename.clone(),
-                status: "".to_string(),
-                end_time: 0.0,
-                estimated_time: 0.0,
-                total_duration: 0.0,
-                filament_used: 0.0,
-            };
-            if let Some(status) = job.get("status") {
-                if let Some(s) = status.as_str() {
-                    h.status = s.to_string();
-                }
-            }
-            if let Some(end_time) = job.get("end_time") {
-                if let Some(s) = end_time.as_f64() {
-                    h.end_time = s;
-                }
-            }
-            if let Some(total_duration) = job.get("total_duration") {
-                if let Some(s) = total_duration.as_f64() {
-                    h.total_duration = s;
-                }
-            }
-            if let Some(filament_used) = job.get("filament_used") {
-                if let Some(s) = filament_used.as_f64() {
-                    h.filament_used = s;
-                }
-            }
+    fn on_objects_list(&mut self, list: ObjectList) {
+        self.printer.objects.clear();
+        self.available_objects = list.objects;
+        self.subscribed = self.subscription();
+        // The subscription result holds the current state of the objects
+        let objects = self.subscribed.clone();
+        self.moonraker.objects_subscribe(&objects).then(on_success("printer.objects.subscribe", App::on_objects_subscribe));
+    }
 
-            if let Some(metadata) = job.get("metadata") {
-                if let Some(estimated_time) = metadata.get("estimated_time") {
-                    if let Some(s) = estimated_time.as_f64() {
-                        h.estimated_time = s;
-                    }
+    /// Objects needed by the current tab, among the ones the printer has
+    pub fn subscription(&self) -> Vec<String> {
+        let extra = self.current_tab.objects();
+        self.available_objects.iter()
+            .filter(|name| match extra {
+                None => true,
+                Some(extra) => STATUS_OBJECTS.contains(&name.as_str())
+                    || extra.contains(&name.as_str())
+                    || STATUS_OBJECT_PREFIXES.iter().any(|p| name.starts_with(p)),
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Subscribes again when the current tab needs other objects
+    fn update_subscription(&mut self) {
+        if !self.is_ready() || self.available_objects.is_empty() {
+            return;
+        }
+        let objects = self.subscription();
+        if objects == self.subscribed {
+            return;
+        }
+        log::info!("subscribing to {} objects", objects.len());
+        self.subscribed = objects.clone();
+        self.moonraker.objects_subscribe(&objects).then(on_success("printer.objects.subscribe", |app: &mut App, result: ObjectStatus| {
+            app.printer.update(result.status);
+        }));
+    }
+
+    fn on_objects_subscribe(&mut self, result: ObjectStatus) {
+        let data = result.status;
+        self.printer.update(data.clone());
+
+        if let Some(print_stats) = data.get("print_stats") {
+            if matches!(print_stats.get("state").and_then(|s| s.as_str()), Some("printing" | "paused")) {
+                if let Some(filename) = print_stats.get("filename").and_then(|f| f.as_str()) {
+                    log::info!("filename {:?}", filename);
+                    // If status is printing, get metadata
+                    self.fetch_file_metadata(filename);
                 }
             }
-            self.history.add(h);
         }
     }
 
+    fn on_history_list(&mut self, list: HistoryList) {
+        for job in list.jobs.iter() {
+            self.add_job(job);
+        }
+        self.history.state.select(Some(0));
+    }
+
+    fn on_webcams_list(&mut self, list: WebcamList) {
+        for cam in list.webcams {
+            log::info!("cam {:?}", cam);
+            self.printer.webcams.push(Webcam {
+                name: cam.name,
+                stream_url: cam.stream_url,
+                snapshot_url: cam.snapshot_url,
+                flip_horizontal: cam.flip_horizontal,
+                flip_vertical: cam.flip_vertical,
+                render: 0,
+            });
+        }
+    }
+
+    pub fn add_job(&mut self, job: &HistoryJob) {
+        if job.filename.is_empty() || self.history.items.iter().any(|i| i.filename == job.filename) {
+            return;
+        }
+        self.history.add(HistoryItem {
+            filename: job.filename.clone(),
+            status: job.status.clone(),
+            end_time: job.end_time.unwrap_or_default(),
+            estimated_time: job.metadata.estimated_time.unwrap_or_default(),
+            total_duration: job.total_duration,
+            filament_used: job.filament_used,
+        });
+    }
+
     pub fn handle_request(&mut self, request: JsonRpcServerRequest) {
         let method = request.method.as_str();
 
         match method {
             "notify_klippy_shutdown" => {
                 log::debug!("notify_klippy_shutdown");
-                self.printer.connected = false;
+                self.connection = ConnectionState::KlippyNotReady;
+                self.alerts.push("Klippy shutdown".to_string());
+            },
+            "notify_klippy_disconnected" => {
+                log::debug!("notify_klippy_disconnected");
+                self.connection = ConnectionState::KlippyNotReady;
+                self.alerts.push("Klippy disconnected".to_string());
             },
             "notify_history_changed" => {
                 if let Some(params) = request.params {
-                    if let Some(action) = params.get("action") {
-                        if action.as_str().unwrap() == "added" {
-                            if let Some(job) = params.get("job") {
-                                self.add_job(job);
-                                self.history.state.select(Some(0));
-                            }
+                    // Sent as a list with a single object
+                    let change = params.get(0).unwrap_or(&params);
+                    if change.get("action").and_then(|a| a.as_str()) == Some("added") {
+                        if let Some(Ok(job)) = change.get("job").map(|j| serde_json::from_value::<HistoryJob>(j.clone())) {
+                            self.add_job(&job);
+                            self.history.state.select(Some(0));
                         }
                     }
                 }
             },
             "notify_klippy_ready" => {
                 log::debug!("notify_klippy_ready");
-                self.printer.connected = true;
+                self.connection = ConnectionState::Connected;
                 self.send_start_messages();
             },
             "notify_proc_stat_update" => {},
             "notify_status_update" => {
                 if let Some(params) = request.params {
-                    self.printer.update(params.get(0).unwrap().clone());
+                    let was_printing = self.printer.is_printing();
+                    let filename = self.printer.current_print.as_ref().map(|p| p.filename.clone()).unwrap_or_default();
+                    if let Some(status) = params.get(0) {
+                        self.printer.update(status.clone());
+                    }
+                    let state = self.printer.status.print_state.as_str();
+                    if was_printing && matches!(state, "complete" | "cancelled" | "error") {
+                        self.alerts.push(format!("print {} {}", state, filename));
+                    }
                 }
             },
             "notify_gcode_response" => {
@@ -691,37 +1011,328 @@ impl App {
         }
     }
 
-    pub fn emergency_stop(&mut self) {
-        self.send_message("printer.emergency_stop".to_string(), serde_json::Value::Object(serde_json::Map::new()));
-        self.printer.status.state = "error".to_string();
-        self.printer.current_print = None;
-        self.printer.connected = false;
-        if let Some(tx) = &self.tx {
+    /// Reacts to Moonraker refusing a request because we are not logged in
+    fn handle_unauthorized(&mut self, method: &str) {
+        if let Some(tokens) = &mut self.tokens {
+            if method != "access.refresh_jwt" && !self.auth_retry {
+                // The access token was refused, try once more with a fresh one
+                self.auth_retry = true;
+                tokens.expires_at = Some(Utc::now());
+                self.reconnect();
+                return;
+            }
+        }
+        self.tokens = None;
+        self.server.access_token = None;
+        self.connection = ConnectionState::AuthFailed;
+        if self.login.is_none() {
+            self.login = Some(LoginForm::new(self.server.username.clone().unwrap_or_default()));
+        }
+        if !self.identified {
+            self.send_start_messages();
+        }
+    }
+
+    fn refresh_tokens(&mut self) {
+        if let Some(tokens) = &self.tokens {
+            let refresh_token = tokens.refresh_token.clone();
+            self.moonraker.call::<Value>("access.refresh_jwt", json!({"refresh_token": refresh_token}))
+                .then(App::on_refresh_jwt);
+        }
+    }
+
+    /// Sends the credentials entered in the login dialog
+    pub fn login(&mut self) {
+        if let Some(form) = &mut self.login {
+            form.error = None;
+            let params = json!({
+                "username": form.username.value,
+                "password": form.password.value,
+                "source": "moonraker",
+            });
+            self.moonraker.call::<Value>("access.login", params).then(App::on_login);
+        }
+    }
+
+    /// Closes the current connection and opens a new one
+    pub fn reconnect(&mut self) {
+        if self.offline {
+            return;
+        }
+        if let Some(tx) = &self.moonraker.tx {
             let _ = tx.send(OwnedMessage::Close(Some(CloseData::new(1, "reset".to_string()))));
         }
+        self.init();
     }
 
-    pub fn send_message(&mut self, method: String, params: Value ) {
-        let mut message = JsonRpcClientRequest::new();
-        message.method = method.clone();
-        message.params = params;
-        let m = match serde_json::to_string(&message) {
-            Ok(m) => m,
-            Err(_e) => {
+    /// Asks for confirmation of `action`, if it applies to the current print
+    pub fn request_print_action(&mut self, action: PrintAction) {
+        if action.allowed(self.printer.status.print_state.as_str()) {
+            self.print_action = Some(action);
+        }
+    }
+
+    /// Sends the confirmed print action, unless the print state changed meanwhile
+    pub fn confirm_print_action(&mut self) {
+        if let Some(action) = self.print_action.take() {
+            if action.allowed(self.printer.status.print_state.as_str()) {
+                self.send_message(action.method().to_string(), json!({}));
+            }
+        }
+    }
+
+    /// Changes the speed factor by `delta` percents
+    pub fn adjust_speed(&mut self, delta: f64) {
+        let factor = self.printer.gcode_move().speed_factor.unwrap_or(1.0);
+        let percent = (factor * 100.0 + delta).round().max(10.0);
+        self.send_message("printer.gcode.script".to_string(), json!({"script": format!("M220 S{}", percent)}));
+    }
+
+    /// Changes the flow factor by `delta` percents
+    pub fn adjust_flow(&mut self, delta: f64) {
+        let factor = self.printer.gcode_move().extrude_factor.unwrap_or(1.0);
+        let percent = (factor * 100.0 + delta).round().max(10.0);
+        self.send_message("printer.gcode.script".to_string(), json!({"script": format!("M221 S{}", percent)}));
+    }
+
+    /// Moves the nozzle by `z` millimeters while printing
+    pub fn babystep(&mut self, z: f64) {
+        self.send_message("printer.gcode.script".to_string(), json!({"script": format!("SET_GCODE_OFFSET Z_ADJUST={:+.3} MOVE=1", z)}));
+    }
+
+    /// Stores the current Z offset in the probe or endstop config, to be saved with `SAVE_CONFIG`
+    pub fn save_z_offset(&mut self) {
+        let probe = self.available_objects.iter()
+            .any(|o| o == "probe" || o == "bltouch" || o == "smart_effector" || o.starts_with("probe_eddy_current "));
+        let script = if probe { "Z_OFFSET_APPLY_PROBE" } else { "Z_OFFSET_APPLY_ENDSTOP" };
+        self.send_message("printer.gcode.script".to_string(), json!({"script": script}));
+    }
+
+    /// Homes `axes`, like "X" or "XY", or all of them when empty
+    pub fn home(&mut self, axes: &str) {
+        if self.refuse_while_printing("Can't move the toolhead while printing") {
+            return;
+        }
+        let script = if axes.is_empty() { "G28".to_string() } else { format!("G28 {}", axes) };
+        self.send_message("printer.gcode.script".to_string(), json!({"script": script}));
+    }
+
+    /// Moves `axis` (0 for X, 1 for Y, 2 for Z) by the jog step, towards `direction`,
+    /// without going past the axis limits
+    pub fn jog(&mut self, axis: usize, direction: f64) {
+        if self.refuse_while_printing("Can't move the toolhead while printing") {
+            return;
+        }
+        let name = ["X", "Y", "Z"][axis];
+        let homed = &self.printer.toolhead.homed;
+        if ![homed.x, homed.y, homed.z][axis] {
+            self.notify(format!("Home {} before jogging", name));
+            return;
+        }
+        let position = &self.printer.toolhead.position;
+        let current = [position.x, position.y, position.z][axis];
+        let step = JOG_STEPS[self.jog_step.min(JOG_STEPS.len() - 1)];
+        let mut target = current + step * direction;
+        let toolhead = self.printer.objects.parse::<crate::klipper::Toolhead>("toolhead").unwrap_or_default();
+        if let Some(min) = toolhead.axis_minimum.as_ref().and_then(|m| m.get(axis)) {
+            target = target.max(*min);
+        }
+        if let Some(max) = toolhead.axis_maximum.as_ref().and_then(|m| m.get(axis)) {
+            target = target.min(*max);
+        }
+        let distance = target - current;
+        if distance.abs() < 0.001 {
+            self.notify(format!("{} is at its limit", name));
+            return;
+        }
+        let speed = JOG_SPEEDS[self.jog_speed.min(JOG_SPEEDS.len() - 1)];
+        let script = format!("G91\nG0 {}{:.3} F{}\nG90", name, distance, speed * 60.0);
+        self.send_message("printer.gcode.script".to_string(), json!({"script": script}));
+    }
+
+    /// Asks for confirmation of `action`, unless printing
+    pub fn request_toolhead_action(&mut self, action: ToolheadAction) {
+        if self.refuse_while_printing("Can't move the toolhead while printing") {
+            return;
+        }
+        self.toolhead_action = Some(action);
+    }
+
+    /// Sends the confirmed toolhead action, unless a print started meanwhile
+    pub fn confirm_toolhead_action(&mut self) {
+        if let Some(action) = self.toolhead_action.take() {
+            if !self.printer.is_printing() {
+                self.send_message("printer.gcode.script".to_string(), json!({"script": action.script()}));
+            }
+        }
+    }
+
+    /// Whether the extruder is hot enough to move filament
+    pub fn can_extrude(&self) -> bool {
+        let extruder = self.printer.extruder();
+        extruder.can_extrude.unwrap_or(extruder.temperature.unwrap_or(0.0) >= MIN_EXTRUDE_TEMP)
+    }
+
+    /// Extrudes the selected length of filament, or retracts it when `retract` is set
+    pub fn extrude(&mut self, retract: bool) {
+        if self.refuse_while_printing("Can't move filament while printing") {
+            return;
+        }
+        if !self.can_extrude() {
+            self.notify("Extruder too cold, heat it up first");
+            return;
+        }
+        let length = EXTRUDE_LENGTHS[self.extrude_length.min(EXTRUDE_LENGTHS.len() - 1)];
+        let speed = EXTRUDE_SPEEDS[self.extrude_speed.min(EXTRUDE_SPEEDS.len() - 1)];
+        // Keep the extrusion mode of the printer, relative extrusion is only for this move
+        let script = format!(
+            "SAVE_GCODE_STATE NAME=krui_extrude\nM83\nG1 E{} F{}\nRESTORE_GCODE_STATE NAME=krui_extrude",
+            if retract { -length } else { length }, speed * 60.0,
+        );
+        self.send_message("printer.gcode.script".to_string(), json!({"script": script}));
+    }
+
+    /// Runs the load or unload macro of the profile, unless printing
+    pub fn load_filament(&mut self, unload: bool) {
+        if self.refuse_while_printing("Can't move filament while printing") {
+            return;
+        }
+        let script = if unload { self.unload_macro.clone() } else { self.load_macro.clone() };
+        self.send_message("printer.gcode.script".to_string(), json!({"script": script}));
+    }
+
+    /// Probes the bed for a new mesh, unless printing
+    pub fn calibrate_mesh(&mut self) {
+        if self.refuse_while_printing("Can't probe the bed while printing") {
+            return;
+        }
+        self.send_message("printer.gcode.script".to_string(), json!({"script": "BED_MESH_CALIBRATE"}));
+    }
+
+    /// Stops applying the current mesh, unless printing
+    pub fn clear_mesh(&mut self) {
+        if self.refuse_while_printing("Can't change the mesh while printing") {
+            return;
+        }
+        self.send_message("printer.gcode.script".to_string(), json!({"script": "BED_MESH_CLEAR"}));
+    }
+
+    /// Loads the selected saved profile, unless printing
+    pub fn load_mesh_profile(&mut self) {
+        if self.refuse_while_printing("Can't change the mesh while printing") {
+            return;
+        }
+        if let Some(name) = self.bed_mesh.selected_profile(&self.printer.bed_mesh()) {
+            if !bed_mesh::valid_profile_name(&name) {
+                self.notify(format!("Can't load the profile \"{}\" from here", name));
                 return;
             }
+            self.send_message("printer.gcode.script".to_string(), json!({"script": format!("BED_MESH_PROFILE LOAD=\"{}\"", name)}));
+        }
+    }
+
+    /// Asks for the name to save the current mesh under, the name of its profile by default
+    pub fn request_mesh_save(&mut self) {
+        let name = self.printer.bed_mesh().profile_name.filter(|n| bed_mesh::valid_profile_name(n)).unwrap_or_else(|| "default".to_string());
+        self.bed_mesh.prompt = Some(MeshPrompt::Save(name));
+    }
+
+    /// Asks to confirm the removal of the selected saved profile
+    pub fn request_mesh_removal(&mut self) {
+        if let Some(name) = self.bed_mesh.selected_profile(&self.printer.bed_mesh()) {
+            self.bed_mesh.prompt = Some(MeshPrompt::Remove(name));
+        }
+    }
+
+    /// Saves or removes the profile of the prompt. A name that can't be quoted keeps the prompt open.
+    pub fn confirm_mesh_prompt(&mut self) {
+        let script = match self.bed_mesh.prompt.take() {
+            Some(MeshPrompt::Save(name)) if bed_mesh::valid_profile_name(name.trim()) => format!("BED_MESH_PROFILE SAVE=\"{}\"", name.trim()),
+            Some(MeshPrompt::Remove(name)) if bed_mesh::valid_profile_name(&name) => format!("BED_MESH_PROFILE REMOVE=\"{}\"", name),
+            Some(MeshPrompt::Save(name)) if !name.trim().is_empty() => {
+                self.notify("Profile names can't have spaces or quotes");
+                self.bed_mesh.prompt = Some(MeshPrompt::Save(name));
+                return;
+            },
+            Some(MeshPrompt::Remove(name)) => {
+                self.notify(format!("Can't remove the profile \"{}\" from here", name));
+                return;
+            },
+            _ => return,
         };
+        self.send_message("printer.gcode.script".to_string(), json!({"script": script}));
+    }
 
+    /// Levels the gantry with `QUAD_GANTRY_LEVEL` or `Z_TILT_ADJUST`, unless printing
+    pub fn level_gantry(&mut self) {
+        let Some(leveling) = self.printer.leveling() else {
+            self.notify("The printer has no gantry leveling");
+            return;
+        };
+        if self.refuse_while_printing("Can't probe the bed while printing") {
+            return;
+        }
+        self.send_message("printer.gcode.script".to_string(), json!({"script": leveling.command()}));
+    }
 
-        if message.id.is_some() {
-            let q: RpcRequest = RpcRequest { method };
-            self.sent_messages.insert(message.id.unwrap(), q);
+    /// Probes the bed screws to tell how to turn them, unless printing
+    pub fn calculate_screws_tilt(&mut self) {
+        if !self.available_objects.iter().any(|o| o == "screws_tilt_adjust") {
+            self.notify("The printer config has no [screws_tilt_adjust] section");
+            return;
         }
-        
+        if self.refuse_while_printing("Can't probe the bed while printing") {
+            return;
+        }
+        self.send_message("printer.gcode.script".to_string(), json!({"script": "SCREWS_TILT_CALCULATE"}));
+    }
 
-        if let Some(tx) = &self.tx {
-            let _ = tx.send(OwnedMessage::Text(m));
+    pub fn emergency_stop(&mut self) {
+        // The connection is reset right after, the response would never arrive
+        self.moonraker.call::<Value>("printer.emergency_stop", serde_json::Value::Object(serde_json::Map::new()));
+        self.printer.status.state = "error".to_string();
+        self.printer.current_print = None;
+        self.reconnect();
+    }
+
+    /// Sends a request whose result is not needed, reporting failures to the user
+    pub fn send_message(&mut self, method: String, params: Value ) {
+        self.moonraker.call::<Value>(method.as_str(), params).then(move |app, result| {
+            if let Err(e) = result {
+                app.request_failed(method.as_str(), e);
+            }
+        });
+    }
+
+    /// Shows a failed request in the header and the console
+    fn report_error(&mut self, method: &str, message: &str) {
+        let message = format!("{} failed: {}", method, message);
+        // Klipper already writes gcode errors to the console
+        if method != "printer.gcode.script" {
+            self.printer.status.gcodes.push(crate::printer::GCodeLine {
+                timestamp: Local::now(),
+                content: format!("!! {}", message),
+            });
         }
+        self.notify(message);
+    }
+
+    /// Shows `message` in the header for a few seconds
+    pub fn notify(&mut self, message: impl Into<String>) {
+        self.notice = Some(Notice { message: message.into(), shown_at: Instant::now() });
+    }
+
+    /// Tells the user why the toolhead or bed can't be used, when a print is running or paused
+    pub fn refuse_while_printing(&mut self, message: &str) -> bool {
+        if self.printer.is_printing() {
+            self.notify(message);
+        }
+        self.printer.is_printing()
+    }
+
+    /// Whether Klippy is connected and ready to accept commands
+    pub fn is_ready(&self) -> bool {
+        self.connection == ConnectionState::Connected
     }
 
     /// Set running to false to quit the application.
@@ -730,6 +1341,416 @@ impl App {
     }
 
     pub fn get_data(&mut self) -> &str {
-        return self.data.as_str();
+        self.data.as_str()
+    }
+}
+
+/// Continuation running `f` with the result of `method`, or reporting its failure to the user
+fn on_success<R: 'static>(method: &'static str, f: impl FnOnce(&mut App, R) + 'static) -> impl FnOnce(&mut App, Result<R, RpcError>) + 'static {
+    move |app, result| match result {
+        Ok(r) => f(app, r),
+        Err(e) => app.request_failed(method, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moonraker::REQUEST_TIMEOUT;
+
+    #[test]
+    fn test_tick_handles_all_pending_messages() {
+        let (tx, rx) = flume::unbounded();
+        let mut app = App {
+            connection: ConnectionState::KlippyNotReady,
+            rx: Some(rx),
+            ..App::default()
+        };
+        for i in 0..500 {
+            let notification = json!({"jsonrpc": "2.0", "method": "notify_gcode_response", "params": [format!("// line {}", i)]});
+            tx.send(OwnedMessage::Text(notification.to_string())).unwrap();
+        }
+        app.tick();
+        assert_eq!(app.printer.status.gcodes.len(), 500);
+        assert!(tx.is_empty());
+    }
+
+    #[test]
+    fn test_tick_stops_at_the_deadline() {
+        let (tx, rx) = flume::unbounded();
+        let mut app = App {
+            connection: ConnectionState::KlippyNotReady,
+            rx: Some(rx),
+            ..App::default()
+        };
+        let notification = json!({"jsonrpc": "2.0", "method": "notify_gcode_response", "params": ["// line"]});
+        tx.send(OwnedMessage::Text(notification.to_string())).unwrap();
+        app.tick_until(Instant::now());
+        assert_eq!(tx.len(), 1);
+        app.tick();
+        assert!(tx.is_empty());
+    }
+
+    #[test]
+    fn test_lost_connection_thread_backs_off() {
+        let (tx, rx) = flume::unbounded::<OwnedMessage>();
+        let mut app = App {
+            connection: ConnectionState::Connected,
+            rx: Some(rx),
+            ..App::default()
+        };
+        drop(tx);
+        app.tick();
+        assert!(app.rx.is_none());
+        assert!(matches!(app.connection, ConnectionState::Backoff { attempt: 1, .. }));
+    }
+
+    #[test]
+    fn test_attempts_reset_once_the_connection_lasts() {
+        let (tx, rx) = flume::unbounded();
+        let now = Instant::now();
+        let mut app = App {
+            connection: ConnectionState::Connected,
+            connection_attempts: 3,
+            connected_at: Some(now),
+            rx: Some(rx),
+            ..App::default()
+        };
+        // Closed right after it was accepted
+        tx.send(OwnedMessage::Close(Some(CloseData::new(1, "reset".to_string())))).unwrap();
+        app.tick();
+        assert!(matches!(app.connection, ConnectionState::Backoff { attempt: 4, .. }));
+
+        let (_tx, rx) = flume::unbounded();
+        app.connection = ConnectionState::Connected;
+        app.rx = Some(rx);
+        app.connected_at = Some(now - STABLE_CONNECTION);
+        app.tick();
+        assert_eq!(app.connection_attempts, 0);
+        assert_eq!(app.connected_at, None);
+    }
+
+    fn connected_app() -> (App, Receiver<OwnedMessage>) {
+        let (tx, rx) = flume::unbounded();
+        let mut app = App {
+            connection: ConnectionState::Connected,
+            ..App::default()
+        };
+        app.moonraker.tx = Some(tx);
+        (app, rx)
+    }
+
+    fn sent_id(rx: &Receiver<OwnedMessage>) -> String {
+        match rx.try_recv() {
+            Ok(OwnedMessage::Text(t)) => serde_json::from_str::<Value>(t.as_str()).unwrap()["id"].as_str().unwrap().to_string(),
+            m => panic!("unexpected message {:?}", m),
+        }
+    }
+
+    /// Gcode scripts of the messages sent so far
+    fn sent_scripts(rx: &Receiver<OwnedMessage>) -> Vec<Value> {
+        rx.drain().map(|m| match m {
+            OwnedMessage::Text(t) => serde_json::from_str::<Value>(t.as_str()).unwrap()["params"]["script"].clone(),
+            m => panic!("unexpected message {:?}", m),
+        }).collect()
+    }
+
+    #[test]
+    fn test_error_response_is_reported() {
+        let (mut app, rx) = connected_app();
+        app.send_message("printer.print.start".to_string(), json!({"filename": "missing.gcode"}));
+        let id = sent_id(&rx);
+        let response: JsonRpcResponse = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "error": {"code": 400, "message": "File missing.gcode does not exist"},
+            "id": id,
+        })).unwrap();
+        app.handle_response(response);
+        assert_eq!(app.moonraker.pending_count(), 0);
+        assert_eq!(app.notice.unwrap().message, "printer.print.start failed: File missing.gcode does not exist");
+        assert_eq!(app.printer.status.gcodes.last().unwrap().content, "!! printer.print.start failed: File missing.gcode does not exist");
+    }
+
+    #[test]
+    fn test_failed_refresh_waits_before_retrying() {
+        let (mut app, rx) = connected_app();
+        app.identified = true;
+        app.tokens = Some(Tokens {
+            username: "admin".to_string(),
+            access_token: "old".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_at: Some(Utc::now()),
+        });
+        app.tick();
+        let id = sent_id(&rx);
+        let response: JsonRpcResponse = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "error": {"code": 503, "message": "Service unavailable"},
+            "id": id,
+        })).unwrap();
+        app.handle_response(response);
+        assert_eq!(app.refresh_failures, 1);
+        assert!(app.tokens.is_some());
+        app.tick();
+        assert!(rx.is_empty());
+
+        app.refresh_retry_at = Some(Instant::now());
+        app.tick();
+        let id = sent_id(&rx);
+        let response: JsonRpcResponse = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "result": {"token": "new"},
+            "id": id,
+        })).unwrap();
+        app.handle_response(response);
+        assert_eq!((app.refresh_failures, app.refresh_retry_at), (0, None));
+        assert_eq!(app.server.access_token.as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn test_thumbnail_stays_in_the_cache() {
+        let (mut app, _rx) = connected_app();
+        let metadata: GcodeMetadata = serde_json::from_value(json!({
+            "filename": "cube.gcode",
+            "thumbnails": [{"width": 300, "height": 300, "size": 1000, "relative_path": "../../.bashrc"}],
+        })).unwrap();
+        app.on_file_metadata(metadata);
+        assert_eq!(app.printer.current_print.unwrap().image, "");
+    }
+
+    #[test]
+    fn test_print_actions_need_confirmation() {
+        let (mut app, rx) = connected_app();
+        app.printer.status.print_state = "printing".to_string();
+        app.request_print_action(PrintAction::Resume);
+        assert_eq!(app.print_action, None);
+        app.request_print_action(PrintAction::Pause);
+        assert_eq!(app.print_action, Some(PrintAction::Pause));
+        assert!(rx.is_empty());
+
+        app.confirm_print_action();
+        match rx.try_recv() {
+            Ok(OwnedMessage::Text(t)) => assert_eq!(serde_json::from_str::<Value>(t.as_str()).unwrap()["method"], "printer.print.pause"),
+            m => panic!("unexpected message {:?}", m),
+        }
+        assert_eq!(app.print_action, None);
+
+        // The print ended while the dialog was open
+        app.request_print_action(PrintAction::Cancel);
+        app.printer.status.print_state = "complete".to_string();
+        app.confirm_print_action();
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn test_tuning_commands() {
+        let (mut app, rx) = connected_app();
+        app.printer.update(json!({"gcode_move": {"speed_factor": 1.0, "extrude_factor": 0.95, "homing_origin": [0.0, 0.0, 0.02, 0.0]}}));
+        app.available_objects = vec!["probe".to_string()];
+        app.adjust_speed(5.0);
+        app.adjust_flow(-1.0);
+        app.babystep(-0.01);
+        app.save_z_offset();
+        assert_eq!(sent_scripts(&rx), vec!["M220 S105", "M221 S94", "SET_GCODE_OFFSET Z_ADJUST=-0.010 MOVE=1", "Z_OFFSET_APPLY_PROBE"]);
+        assert_eq!(app.printer.gcode_move().z_offset(), 0.02);
+    }
+
+    #[test]
+    fn test_extrude_needs_a_hot_extruder() {
+        let (mut app, rx) = connected_app();
+        app.printer.update(json!({"extruder": {"temperature": 25.0, "can_extrude": false}}));
+        app.extrude(false);
+        assert!(rx.is_empty());
+        assert!(app.notice.is_some());
+
+        app.printer.update(json!({"extruder": {"temperature": 215.0, "can_extrude": true}}));
+        app.extrude_length = 3;
+        app.extrude_speed = 1;
+        app.extrude(true);
+        assert_eq!(sent_scripts(&rx), vec!["SAVE_GCODE_STATE NAME=krui_extrude\nM83\nG1 E-25 F120\nRESTORE_GCODE_STATE NAME=krui_extrude"]);
+    }
+
+    #[test]
+    fn test_filament_is_left_alone_while_printing() {
+        let (mut app, rx) = connected_app();
+        app.printer.update(json!({"extruder": {"temperature": 215.0, "can_extrude": true}}));
+        app.load_filament(false);
+        assert_eq!(sent_scripts(&rx), vec!["LOAD_FILAMENT"]);
+
+        app.printer.status.print_state = "printing".to_string();
+        app.extrude(false);
+        app.extrude(true);
+        app.load_filament(true);
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Can't move filament while printing");
+    }
+
+    #[test]
+    fn test_jogging_stays_within_limits() {
+        let (mut app, rx) = connected_app();
+        app.printer.update(json!({
+            "toolhead": {"homed_axes": "xy", "axis_minimum": [0.0, -5.0, -2.0, 0.0], "axis_maximum": [300.0, 300.0, 250.0, 0.0]},
+            "motion_report": {"live_position": [295.0, 0.0, 10.0, 0.0]},
+        }));
+
+        app.jog_step = 2;
+        app.jog(0, 1.0);
+        app.jog(1, -1.0);
+        assert_eq!(sent_scripts(&rx), vec!["G91\nG0 X5.000 F3000\nG90", "G91\nG0 Y-5.000 F3000\nG90"]);
+
+        // Z is not homed
+        app.jog(2, 1.0);
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Home Z before jogging");
+
+        app.printer.update(json!({"motion_report": {"live_position": [300.0, 0.0, 10.0, 0.0]}}));
+        app.jog(0, 1.0);
+        assert!(rx.is_empty());
+
+        app.printer.status.print_state = "paused".to_string();
+        app.jog(1, 1.0);
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Can't move the toolhead while printing");
+    }
+
+    #[test]
+    fn test_homing_is_refused_while_printing() {
+        let (mut app, rx) = connected_app();
+        app.home("X");
+        app.home("");
+        assert_eq!(sent_scripts(&rx), vec!["G28 X", "G28"]);
+
+        app.printer.status.print_state = "printing".to_string();
+        app.home("Z");
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Can't move the toolhead while printing");
+    }
+
+    #[test]
+    fn test_toolhead_actions_need_confirmation() {
+        let (mut app, rx) = connected_app();
+        app.request_toolhead_action(ToolheadAction::ResetPosition);
+        assert_eq!(app.toolhead_action, Some(ToolheadAction::ResetPosition));
+        assert!(rx.is_empty());
+        app.confirm_toolhead_action();
+        match rx.try_recv() {
+            Ok(OwnedMessage::Text(t)) => assert_eq!(serde_json::from_str::<Value>(t.as_str()).unwrap()["params"]["script"], "G92 X0 Y0 Z0"),
+            m => panic!("unexpected message {:?}", m),
+        }
+        assert_eq!(app.toolhead_action, None);
+
+        // A print started while the dialog was open
+        app.request_toolhead_action(ToolheadAction::MotorsOff);
+        app.printer.status.print_state = "printing".to_string();
+        app.confirm_toolhead_action();
+        assert!(rx.is_empty());
+
+        app.request_toolhead_action(ToolheadAction::MotorsOff);
+        assert_eq!(app.toolhead_action, None);
+        assert_eq!(app.notice.as_ref().unwrap().message, "Can't move the toolhead while printing");
+    }
+
+    #[test]
+    fn test_mesh_profiles() {
+        let (mut app, rx) = connected_app();
+        app.printer.update(json!({"bed_mesh": {"profile_name": "", "profiles": {"default": {}, "pla": {}}}}));
+
+        app.bed_mesh.next_profile(&app.printer.bed_mesh());
+        app.load_mesh_profile();
+        assert_eq!(sent_scripts(&rx), vec!["BED_MESH_PROFILE LOAD=\"pla\""]);
+
+        // Removing needs a confirmation
+        app.request_mesh_removal();
+        assert!(rx.is_empty());
+        app.confirm_mesh_prompt();
+        assert_eq!(sent_scripts(&rx), vec!["BED_MESH_PROFILE REMOVE=\"pla\""]);
+
+        app.request_mesh_save();
+        assert_eq!(app.bed_mesh.prompt, Some(MeshPrompt::Save("default".to_string())));
+        app.bed_mesh.prompt = Some(MeshPrompt::Save(" ".to_string()));
+        app.confirm_mesh_prompt();
+        assert!(rx.is_empty());
+
+        // The name goes between quotes in the command
+        app.bed_mesh.prompt = Some(MeshPrompt::Save("pla\" LOAD=\"x".to_string()));
+        app.confirm_mesh_prompt();
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Profile names can't have spaces or quotes");
+        assert!(app.bed_mesh.prompt.is_some());
+        app.bed_mesh.prompt = Some(MeshPrompt::Remove("my mesh".to_string()));
+        app.confirm_mesh_prompt();
+        assert!(rx.is_empty());
+
+        app.printer.status.print_state = "printing".to_string();
+        app.calibrate_mesh();
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Can't probe the bed while printing");
+        app.clear_mesh();
+        app.load_mesh_profile();
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Can't change the mesh while printing");
+    }
+
+    #[test]
+    fn test_leveling_commands_need_the_printer_objects() {
+        let (mut app, rx) = connected_app();
+        app.level_gantry();
+        app.calculate_screws_tilt();
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "The printer config has no [screws_tilt_adjust] section");
+
+        app.available_objects = vec!["z_tilt".to_string(), "screws_tilt_adjust".to_string()];
+        app.printer.update(json!({"z_tilt": {"applied": false}}));
+        app.level_gantry();
+        app.calculate_screws_tilt();
+        assert_eq!(sent_scripts(&rx), vec!["Z_TILT_ADJUST", "SCREWS_TILT_CALCULATE"]);
+
+        app.printer.status.print_state = "printing".to_string();
+        app.level_gantry();
+        assert!(rx.is_empty());
+        assert_eq!(app.notice.as_ref().unwrap().message, "Can't probe the bed while printing");
+    }
+
+    #[test]
+    fn test_unanswered_requests_expire() {
+        let (mut app, _rx) = connected_app();
+        app.send_message("server.files.metadata".to_string(), json!({"filename": "a.gcode"}));
+        app.send_message("printer.gcode.script".to_string(), json!({"script": "G28"}));
+        let now = Instant::now();
+        assert!(app.moonraker.expire(now + Duration::from_secs(1)).is_empty());
+        assert_eq!(app.moonraker.pending_count(), 2);
+        for done in app.moonraker.expire(now + REQUEST_TIMEOUT + Duration::from_secs(1)) {
+            done(&mut app);
+        }
+        assert_eq!(app.moonraker.pending_count(), 1);
+        assert_eq!(app.notice.unwrap().message, "server.files.metadata failed: no response from Moonraker");
+    }
+
+    #[test]
+    fn test_finished_print_raises_an_alert() {
+        let (mut app, _rx) = connected_app();
+        let update = |state: &str| serde_json::from_value::<JsonRpcServerRequest>(json!({
+            "jsonrpc": "2.0",
+            "method": "notify_status_update",
+            "params": [{"print_stats": {"state": state, "filename": "benchy.gcode"}}, 1.0],
+        })).unwrap();
+        app.handle_request(update("printing"));
+        assert!(app.alerts.is_empty());
+        app.handle_request(update("complete"));
+        assert_eq!(app.alerts, vec!["print complete benchy.gcode"]);
+    }
+
+    #[test]
+    fn test_server_info_sets_connection_state() {
+        let (mut app, rx) = connected_app();
+        app.moonraker.server_info().then(on_success("server.info", App::on_server_info));
+        let id = sent_id(&rx);
+        let response: JsonRpcResponse = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "result": {"klippy_connected": true, "klippy_state": "startup"},
+            "id": id,
+        })).unwrap();
+        app.handle_response(response);
+        assert_eq!(app.connection, ConnectionState::KlippyNotReady);
     }
 }
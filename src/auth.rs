@@ -0,0 +1,130 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::Value;
+
+use crate::app::{InputMode, InputState};
+
+/// Tokens returned by Moonraker's `access.login` and `access.refresh_jwt`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tokens {
+    pub username: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Expiry of the access token, read from its `exp` claim
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Tokens {
+    /// Builds the tokens from the result of an `access.login` request
+    pub fn from_login(result: &Value) -> Option<Tokens> {
+        let access_token = result.get("token")?.as_str()?.to_string();
+        let refresh_token = result.get("refresh_token")?.as_str()?.to_string();
+        let username = result.get("username").and_then(|u| u.as_str()).unwrap_or_default().to_string();
+        Some(Tokens {
+            username,
+            expires_at: jwt_expiry(access_token.as_str()),
+            access_token,
+            refresh_token,
+        })
+    }
+
+    /// Stores the new access token from the result of an `access.refresh_jwt` request
+    pub fn refresh(&mut self, result: &Value) -> bool {
+        if let Some(token) = result.get("token").and_then(|t| t.as_str()) {
+            self.access_token = token.to_string();
+            self.expires_at = jwt_expiry(token);
+            return true;
+        }
+        false
+    }
+
+    /// Whether the access token expires in less than five minutes
+    pub fn needs_refresh(&self, now: DateTime<Utc>) -> bool {
+        match self.expires_at {
+            Some(exp) => exp - now < Duration::minutes(5),
+            None => false,
+        }
+    }
+}
+
+/// Reads the expiry date from the payload of a JWT, without checking its signature
+pub fn jwt_expiry(token: &str) -> Option<DateTime<Utc>> {
+    let payload = token.split('.').nth(1)?;
+    let decoded = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Value = serde_json::from_slice(&decoded).ok()?;
+    Utc.timestamp_opt(claims.get("exp")?.as_i64()?, 0).single()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginField {
+    Username,
+    Password,
+}
+
+/// State of the login dialog
+pub struct LoginForm {
+    pub username: InputState,
+    pub password: InputState,
+    pub field: LoginField,
+    /// Reason of the last failed login, if any
+    pub error: Option<String>,
+}
+
+impl LoginForm {
+    pub fn new(username: String) -> LoginForm {
+        let field = if username.is_empty() { LoginField::Username } else { LoginField::Password };
+        LoginForm {
+            username: InputState { mode: InputMode::Editing, cursor_position: username.chars().count() as u16, value: username },
+            password: InputState { mode: InputMode::Editing, value: "".to_string(), cursor_position: 0 },
+            field,
+            error: None,
+        }
+    }
+
+    /// Input of the field currently being edited
+    pub fn input(&mut self) -> &mut InputState {
+        match self.field {
+            LoginField::Username => &mut self.username,
+            LoginField::Password => &mut self.password,
+        }
+    }
+
+    pub fn next_field(&mut self) {
+        self.field = match self.field {
+            LoginField::Username => LoginField::Password,
+            LoginField::Password => LoginField::Username,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // {"alg":"HS256"}.{"username":"krui","exp":1700000000}.signature
+    const TOKEN: &str = "eyJhbGciOiJIUzI1NiJ9.eyJ1c2VybmFtZSI6ImtydWkiLCJleHAiOjE3MDAwMDAwMDB9.c2ln";
+
+    #[test]
+    fn test_jwt_expiry_is_read_from_payload() {
+        assert_eq!(jwt_expiry(TOKEN), Utc.timestamp_opt(1700000000, 0).single());
+        assert_eq!(jwt_expiry("not a token"), None);
+    }
+
+    #[test]
+    fn test_login_result_gives_tokens() {
+        let t = Tokens::from_login(&serde_json::json!({
+            "username": "krui",
+            "token": TOKEN,
+            "refresh_token": "refresh",
+            "action": "user_logged_in",
+        })).unwrap();
+        assert_eq!(t.username, "krui");
+        assert_eq!(t.refresh_token, "refresh");
+        assert!(t.needs_refresh(Utc.timestamp_opt(1700000000 - 60, 0).unwrap()));
+        assert!(!t.needs_refresh(Utc.timestamp_opt(1700000000 - 3600, 0).unwrap()));
+    }
+
+    #[test]
+    fn test_login_result_without_token_is_rejected() {
+        assert_eq!(Tokens::from_login(&serde_json::json!({"username": "krui"})), None);
+    }
+}
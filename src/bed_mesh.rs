@@ -0,0 +1,165 @@
+use crate::klipper::BedMesh;
+
+/// Change to the mesh profiles waiting for the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshPrompt {
+    /// Saving the current mesh under the name being typed
+    Save(String),
+    /// Removing a saved profile, once confirmed
+    Remove(String),
+}
+
+/// Whether `name` can be quoted in a `BED_MESH_PROFILE` command
+pub fn valid_profile_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| c == '"' || c.is_whitespace())
+}
+
+/// State of the bed mesh tab
+#[derive(Debug, Clone, Default)]
+pub struct MeshView {
+    /// Column and row of the point under the cursor
+    pub cursor: (usize, usize),
+    /// Shows the interpolated mesh instead of the probed points
+    pub interpolated: bool,
+    /// Index of the selected saved profile
+    pub profile: usize,
+    pub prompt: Option<MeshPrompt>,
+}
+
+/// Lowest, highest and mean Z of a mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl MeshStats {
+    pub fn of(matrix: &[Vec<f64>]) -> Option<MeshStats> {
+        let values: Vec<f64> = matrix.iter().flatten().copied().collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(MeshStats {
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+        })
+    }
+
+    /// Difference between the highest and the lowest points
+    pub fn range(&self) -> f64 {
+        self.max - self.min
+    }
+}
+
+impl MeshView {
+    /// The points shown, one row per Y. Empty when no mesh is loaded, which Klipper reports as `[[]]`.
+    pub fn matrix<'a>(&self, mesh: &'a BedMesh) -> &'a [Vec<f64>] {
+        let matrix = if self.interpolated { &mesh.mesh_matrix } else { &mesh.probed_matrix };
+        match matrix {
+            Some(m) if m.iter().any(|row| !row.is_empty()) => m,
+            _ => &[],
+        }
+    }
+
+    /// The cursor, kept on the mesh when it got smaller
+    pub fn cursor(&self, matrix: &[Vec<f64>]) -> (usize, usize) {
+        let rows = matrix.len().max(1);
+        let row = self.cursor.1.min(rows - 1);
+        let columns = matrix.get(row).map_or(1, |r| r.len().max(1));
+        (self.cursor.0.min(columns - 1), row)
+    }
+
+    /// Moves the cursor by `dx` columns and `dy` rows
+    pub fn move_cursor(&mut self, dx: isize, dy: isize, matrix: &[Vec<f64>]) {
+        let (x, y) = self.cursor(matrix);
+        self.cursor = (x.saturating_add_signed(dx), y.saturating_add_signed(dy));
+        self.cursor = self.cursor(matrix);
+    }
+
+    /// X and Y on the bed, and Z of the point under the cursor
+    pub fn selected_point(&self, mesh: &BedMesh) -> Option<(f64, f64, f64)> {
+        let matrix = self.matrix(mesh);
+        let (column, row) = self.cursor(matrix);
+        let z = *matrix.get(row)?.get(column)?;
+        let min = mesh.mesh_min.as_ref()?;
+        let max = mesh.mesh_max.as_ref()?;
+        let along = |i: usize, count: usize, axis: usize| -> Option<f64> {
+            let (min, max) = (*min.get(axis)?, *max.get(axis)?);
+            Some(if count > 1 { min + (max - min) * i as f64 / (count - 1) as f64 } else { min })
+        };
+        Some((along(column, matrix[row].len(), 0)?, along(row, matrix.len(), 1)?, z))
+    }
+
+    /// Name of the selected saved profile
+    pub fn selected_profile(&self, mesh: &BedMesh) -> Option<String> {
+        let profiles = mesh.profiles.as_ref()?;
+        profiles.keys().nth(self.profile.min(profiles.len().saturating_sub(1))).cloned()
+    }
+
+    pub fn next_profile(&mut self, mesh: &BedMesh) {
+        let count = mesh.profiles.as_ref().map_or(0, |p| p.len());
+        self.profile = (self.profile + 1).min(count.saturating_sub(1));
+    }
+
+    pub fn previous_profile(&mut self) {
+        self.profile = self.profile.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mesh() -> BedMesh {
+        serde_json::from_value(json!({
+            "profile_name": "default",
+            "mesh_min": [20.0, 30.0],
+            "mesh_max": [220.0, 230.0],
+            "probed_matrix": [[0.1, -0.05, 0.0], [0.2, 0.0, -0.1]],
+            "mesh_matrix": [[]],
+            "profiles": {"pla": {}, "default": {}},
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_stats_of_the_probed_points() {
+        let mesh = mesh();
+        let view = MeshView::default();
+        let stats = MeshStats::of(view.matrix(&mesh)).unwrap();
+        assert_eq!((stats.min, stats.max), (-0.1, 0.2));
+        assert!((stats.range() - 0.3).abs() < 1e-9);
+        assert!((stats.mean - 0.025).abs() < 1e-9);
+
+        // Klipper reports a cleared mesh as an empty row
+        let interpolated = MeshView { interpolated: true, ..MeshView::default() };
+        assert!(interpolated.matrix(&mesh).is_empty());
+        assert_eq!(MeshStats::of(interpolated.matrix(&mesh)), None);
+    }
+
+    #[test]
+    fn test_cursor_stays_on_the_mesh() {
+        let mesh = mesh();
+        let mut view = MeshView::default();
+        assert_eq!(view.selected_point(&mesh), Some((20.0, 30.0, 0.1)));
+        view.move_cursor(5, 1, view.matrix(&mesh));
+        assert_eq!(view.cursor, (2, 1));
+        assert_eq!(view.selected_point(&mesh), Some((220.0, 230.0, -0.1)));
+        view.move_cursor(-1, -3, view.matrix(&mesh));
+        assert_eq!(view.selected_point(&mesh), Some((120.0, 30.0, -0.05)));
+
+        view.next_profile(&mesh);
+        view.next_profile(&mesh);
+        assert_eq!(view.selected_profile(&mesh).as_deref(), Some("pla"));
+    }
+
+    #[test]
+    fn test_profile_names_are_safe_to_quote() {
+        assert!(valid_profile_name("pla_0.4"));
+        assert!(!valid_profile_name(""));
+        assert!(!valid_profile_name("my mesh"));
+        assert!(!valid_profile_name("pla\" LOAD=\"x"));
+    }
+}
@@ -0,0 +1,275 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+use crate::app::{App, AppResult};
+use crate::config::Args;
+use crate::connection::ConnectionState;
+use crate::moonraker::RpcError;
+
+/// Subcommands, given after the printer: `krui voron status`
+pub const COMMANDS: &[&str] = &["status", "print", "gcode", "pause", "resume", "cancel", "wait"];
+
+pub const EXIT_OK: i32 = 0;
+/// The printer refused the command, or the print did not end as expected
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+/// Moonraker or Klippy could not be reached
+pub const EXIT_UNREACHABLE: i32 = 3;
+pub const EXIT_TIMEOUT: i32 = 4;
+
+/// Time given to Moonraker and Klippy to get ready
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Print states after which `wait` stops
+const END_STATES: &[&str] = &["complete", "cancelled", "error"];
+
+/// Command to run without the user interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Status { json: bool },
+    Print(String),
+    Gcode(String),
+    Pause,
+    Resume,
+    Cancel,
+    /// Waits for `print_stats` to reach the `until` state
+    Wait { until: String, timeout: Option<Duration> },
+}
+
+impl Command {
+    /// Command given on the command line, if any
+    pub fn parse(args: &Args) -> AppResult<Option<Command>> {
+        let (name, rest) = match args.command.split_first() {
+            Some((name, rest)) => (name.as_str(), rest),
+            None => return Ok(None),
+        };
+        let argument = || match rest {
+            [a] => Ok(a.clone()),
+            _ => Err(format!("{} takes exactly one argument", name)),
+        };
+        if !matches!(name, "print" | "gcode") && !rest.is_empty() {
+            return Err(format!("{} takes no argument", name).into());
+        }
+        let command = match name {
+            "status" => Command::Status { json: args.json },
+            "print" => Command::Print(argument()?),
+            "gcode" => Command::Gcode(argument()?),
+            "pause" => Command::Pause,
+            "resume" => Command::Resume,
+            "cancel" => Command::Cancel,
+            "wait" => Command::Wait {
+                until: args.until.clone().unwrap_or_else(|| "complete".to_string()),
+                timeout: args.timeout.map(Duration::from_secs),
+            },
+            c => return Err(format!("unknown command {}", c).into()),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Runs `command` on the printer of `app`, and returns the exit code
+pub fn run(app: &mut App, command: &Command) -> i32 {
+    app.init();
+    if let Err(code) = connect(app, matches!(command, Command::Status { .. })) {
+        return code;
+    }
+    match command {
+        Command::Status { json } => {
+            if *json {
+                println!("{}", status_json(app));
+            } else {
+                println!("{}", status_text(app));
+            }
+            EXIT_OK
+        },
+        Command::Print(filename) => request(app, "printer.print.start", json!({"filename": filename})),
+        Command::Gcode(script) => {
+            let before = app.printer.status.gcodes.len();
+            let code = request(app, "printer.gcode.script", json!({"script": script}));
+            // Klipper answers with lines on the console, errors included
+            for line in app.printer.status.gcodes.iter().skip(before) {
+                println!("{}", line.content);
+            }
+            code
+        },
+        Command::Pause => request(app, "printer.print.pause", json!({})),
+        Command::Resume => request(app, "printer.print.resume", json!({})),
+        Command::Cancel => request(app, "printer.print.cancel", json!({})),
+        Command::Wait { until, timeout } => wait(app, until.as_str(), *timeout),
+    }
+}
+
+/// Ticks `app` until Klippy is ready and the printer state is known.
+///
+/// With `allow_not_ready`, returns as soon as Moonraker reports that Klippy is not ready.
+fn connect(app: &mut App, allow_not_ready: bool) -> Result<(), i32> {
+    let start = Instant::now();
+    loop {
+        match app.connection {
+            ConnectionState::Connected if app.printer.status.print_state != "unknown" => return Ok(()),
+            ConnectionState::KlippyNotReady if app.printer.status.state != "unknown" && start.elapsed() > Duration::from_secs(2) => {
+                // Klippy may be starting, give it a couple of seconds before giving up
+                if allow_not_ready {
+                    return Ok(());
+                }
+                eprintln!("{}: Klippy is not ready ({})", app.name, app.printer.status.state);
+                return Err(EXIT_UNREACHABLE);
+            },
+            ConnectionState::AuthFailed => {
+                eprintln!("{}: unauthorized, check the API key", app.name);
+                return Err(EXIT_UNREACHABLE);
+            },
+            _ => {},
+        }
+        if start.elapsed() > CONNECT_TIMEOUT {
+            eprintln!("{}: could not connect to Moonraker", app.name);
+            return Err(EXIT_UNREACHABLE);
+        }
+        tick(app);
+    }
+}
+
+fn tick(app: &mut App) {
+    app.tick();
+    thread::sleep(Duration::from_millis(20));
+}
+
+/// Sends a request and ticks `app` until its outcome is known
+fn call(app: &mut App, method: &str, params: Value) -> Result<Value, RpcError> {
+    let outcome: Rc<RefCell<Option<Result<Value, RpcError>>>> = Rc::new(RefCell::new(None));
+    let o = outcome.clone();
+    let id = app.moonraker.call::<Value>(method, params).then(move |_, r| *o.borrow_mut() = Some(r));
+    loop {
+        if let Some(r) = outcome.borrow_mut().take() {
+            return r;
+        }
+        // Requests are forgotten when the connection drops
+        if !app.moonraker.is_waiting(id.as_str()) {
+            return Err(RpcError::NotConnected);
+        }
+        tick(app);
+    }
+}
+
+/// Sends a request, and reports its failure
+fn request(app: &mut App, method: &str, params: Value) -> i32 {
+    match call(app, method, params) {
+        Ok(_) => EXIT_OK,
+        Err(e) => {
+            eprintln!("{}: {} failed: {}", app.name, method, e);
+            match e {
+                RpcError::Timeout => EXIT_TIMEOUT,
+                RpcError::NotConnected => EXIT_UNREACHABLE,
+                _ => EXIT_FAILED,
+            }
+        },
+    }
+}
+
+fn wait(app: &mut App, until: &str, timeout: Option<Duration>) -> i32 {
+    let start = Instant::now();
+    loop {
+        let state = app.printer.status.print_state.as_str();
+        if state == until {
+            return EXIT_OK;
+        }
+        if END_STATES.contains(&until) && END_STATES.contains(&state) {
+            eprintln!("{}: print ended with state {}", app.name, state);
+            return EXIT_FAILED;
+        }
+        if timeout.is_some_and(|t| start.elapsed() > t) {
+            eprintln!("{}: still {} after {}s", app.name, state, start.elapsed().as_secs());
+            return EXIT_TIMEOUT;
+        }
+        tick(app);
+    }
+}
+
+/// State of the printer, for scripts
+pub fn status_json(app: &App) -> Value {
+    let status = &app.printer.status;
+    let heaters: Vec<Value> = status.heaters.items.iter().map(|h| json!({
+        "name": h.name,
+        "temperature": h.temperature,
+        "target": h.target,
+        "power": h.power,
+    })).collect();
+    let print = app.printer.current_print.as_ref().map(|p| json!({
+        "filename": p.filename,
+        "progress": p.progress,
+        "print_duration": p.print_duration,
+        "time_left": p.time_left(),
+        "current_layer": p.current_layer,
+        "total_layers": p.total_layers,
+    }));
+    let position = &app.printer.toolhead.position;
+    json!({
+        "name": app.name,
+        "state": status.state,
+        "print_state": status.print_state,
+        "state_message": status.state_message,
+        "print": print,
+        "heaters": heaters,
+        "position": [position.x, position.y, position.z],
+    })
+}
+
+/// State of the printer, for humans
+pub fn status_text(app: &App) -> String {
+    let status = &app.printer.status;
+    let mut lines = vec![format!("{}: {}, {}", app.name, status.state, status.print_state)];
+    if let Some(p) = &app.printer.current_print {
+        lines.push(format!("{} {:.0}%, {:.0} min left", p.filename, p.progress * 100.0, p.time_left() / 60.0));
+    }
+    for h in status.heaters.items.iter() {
+        lines.push(format!("{} {:.1}/{:.0}", h.name, h.temperature, h.target));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::{Heater, HeaterType, PrintStats};
+
+    fn command(a: &[&str]) -> AppResult<Option<Command>> {
+        Command::parse(&Args::parse(a.iter().map(|s| s.to_string()))?)
+    }
+
+    #[test]
+    fn test_commands_follow_the_printer() {
+        assert_eq!(command(&["voron"]).unwrap(), None);
+        assert_eq!(command(&["voron", "ender"]).unwrap(), None);
+        assert_eq!(command(&["voron", "status", "--json"]).unwrap(), Some(Command::Status { json: true }));
+        assert_eq!(command(&["voron", "gcode", "G28 X"]).unwrap(), Some(Command::Gcode("G28 X".to_string())));
+        assert_eq!(command(&["voron", "wait", "--timeout", "60"]).unwrap(), Some(Command::Wait {
+            until: "complete".to_string(),
+            timeout: Some(Duration::from_secs(60)),
+        }));
+        assert!(command(&["voron", "print"]).is_err());
+        assert!(command(&["voron", "pause", "now"]).is_err());
+    }
+
+    #[test]
+    fn test_status_json() {
+        let mut app = App::default();
+        app.printer.status.print_state = "printing".to_string();
+        app.printer.status.heaters.items.push(Heater {
+            name: "extruder".to_string(),
+            temperature: 214.6,
+            target: 215.0,
+            power: 0.4,
+            heater_type: HeaterType::Heater,
+        });
+        app.printer.current_print = Some(PrintStats { filename: "benchy.gcode".to_string(), progress: 0.5, print_duration: 600.0, ..PrintStats::new() });
+        let status = status_json(&app);
+        assert_eq!(status["print_state"], "printing");
+        assert_eq!(status["print"]["filename"], "benchy.gcode");
+        assert_eq!(status["print"]["time_left"], 600.0);
+        assert_eq!(status["heaters"][0]["target"], 215.0);
+        assert!(status_text(&app).contains("benchy.gcode 50%, 10 min left"));
+    }
+}
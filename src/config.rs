@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::app::AppResult;
+use crate::cli::COMMANDS;
+
+/// Contents of `config.toml`.
+///
+/// ```toml
+/// [ui]
+/// tick_rate = 100
+/// history_limit = 50
+///
+/// [printers.voron]
+/// url = "https://farm.lan/voron"
+/// api_key = "6a1e..."
+/// filament_diameter = 1.75
+/// webcam = "nozzle"
+/// load_macro = "LOAD_FILAMENT"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub ui: UiConfig,
+    /// Printer profiles, by name
+    pub printers: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// Time between two screen refreshes, in milliseconds
+    pub tick_rate: u64,
+    /// Number of past jobs shown in the history
+    pub history_limit: u32,
+    pub log_file: PathBuf,
+    /// Where thumbnails and webcam snapshots are saved
+    pub cache_dir: PathBuf,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        UiConfig {
+            tick_rate: 100,
+            history_limit: 100,
+            log_file: xdg_dir("XDG_STATE_HOME", ".local/state").join("krui.log"),
+            cache_dir: xdg_dir("XDG_CACHE_HOME", ".cache"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct Profile {
+    /// Address of Moonraker, as accepted by [`crate::server::Server::parse`]
+    pub url: String,
+    pub api_key: Option<String>,
+    /// User to log in as when Moonraker asks for a login
+    pub user: Option<String>,
+    /// Used to compute the flow
+    #[serde(default = "default_filament_diameter")]
+    pub filament_diameter: f64,
+    /// Name of the webcam to show, the first one when not set
+    pub webcam: Option<String>,
+    /// Gcode run by the load key of the Extruder tab
+    #[serde(default = "default_load_macro")]
+    pub load_macro: String,
+    /// Gcode run by the unload key of the Extruder tab
+    #[serde(default = "default_unload_macro")]
+    pub unload_macro: String,
+}
+
+fn default_filament_diameter() -> f64 {
+    1.75
+}
+
+fn default_load_macro() -> String {
+    "LOAD_FILAMENT".to_string()
+}
+
+fn default_unload_macro() -> String {
+    "UNLOAD_FILAMENT".to_string()
+}
+
+impl Profile {
+    /// Profile for a printer given by its address on the command line
+    pub fn from_url(url: &str) -> Profile {
+        Profile {
+            url: url.to_string(),
+            api_key: None,
+            user: None,
+            filament_diameter: default_filament_diameter(),
+            webcam: None,
+            load_macro: default_load_macro(),
+            unload_macro: default_unload_macro(),
+        }
+    }
+}
+
+/// Command line arguments, which take precedence over the config file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Args {
+    /// Profile names or server addresses
+    pub printers: Vec<String>,
+    pub api_key: Option<String>,
+    pub user: Option<String>,
+    pub config: Option<PathBuf>,
+    pub tick_rate: Option<u64>,
+    pub log_file: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+    pub help: bool,
+    /// Subcommand and its arguments, see [`crate::cli::Command`]
+    pub command: Vec<String>,
+    pub json: bool,
+    pub until: Option<String>,
+    /// Seconds
+    pub timeout: Option<u64>,
+    /// File to record the websocket frames to
+    pub record: Option<PathBuf>,
+    /// Recording to play instead of connecting
+    pub replay: Option<PathBuf>,
+    /// Replay speed, relative to the recorded pace
+    pub speed: Option<f64>,
+    /// Show a simulated printer
+    pub demo: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> AppResult<Args> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--api-key" => parsed.api_key = Some(value()?),
+                "--user" => parsed.user = Some(value()?),
+                "--config" => parsed.config = Some(value()?.into()),
+                "--tick-rate" => parsed.tick_rate = Some(value()?.parse()?),
+                "--log" => parsed.log_file = Some(value()?.into()),
+                "--cache-dir" => parsed.cache_dir = Some(value()?.into()),
+                "--json" => parsed.json = true,
+                "--until" => parsed.until = Some(value()?),
+                "--timeout" => parsed.timeout = Some(value()?.parse()?),
+                "--record" => parsed.record = Some(value()?.into()),
+                "--replay" => parsed.replay = Some(value()?.into()),
+                "--speed" => parsed.speed = Some(value()?.parse()?),
+                "--demo" => parsed.demo = true,
+                "-h" | "--help" => parsed.help = true,
+                a if a.starts_with("--") => return Err(format!("unknown option {}", a).into()),
+                // Everything after a subcommand is for that subcommand
+                _ if !parsed.command.is_empty() => parsed.command.push(arg),
+                a if !parsed.printers.is_empty() && COMMANDS.contains(&a) => parsed.command.push(arg),
+                _ => parsed.printers.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl Config {
+    /// Reads the config file at `path`, an absent file gives the default config
+    pub fn load(path: &Path) -> AppResult<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = fs::read_to_string(path)?;
+        toml::from_str(text.as_str()).map_err(|e| format!("invalid config file {}: {}", path.display(), e).into())
+    }
+
+    /// Overrides the UI preferences with the ones given on the command line
+    pub fn apply(&mut self, args: &Args) {
+        if let Some(t) = args.tick_rate {
+            self.ui.tick_rate = t;
+        }
+        if let Some(l) = &args.log_file {
+            self.ui.log_file = l.clone();
+        }
+        if let Some(c) = &args.cache_dir {
+            self.ui.cache_dir = c.clone();
+        }
+    }
+
+    /// Printers to connect to, with their name.
+    ///
+    /// Each argument is the name of a profile, or else the address of a printer.
+    /// Without arguments, all the profiles are used.
+    pub fn profiles(&self, args: &Args) -> Vec<(String, Profile)> {
+        let mut profiles: Vec<(String, Profile)> = if args.printers.is_empty() {
+            self.printers.iter().map(|(name, p)| (name.clone(), p.clone())).collect()
+        } else {
+            args.printers.iter().map(|name| match self.printers.get(name) {
+                Some(p) => (name.clone(), p.clone()),
+                None => (name.clone(), Profile::from_url(name)),
+            }).collect()
+        };
+        for (_, profile) in profiles.iter_mut() {
+            if args.api_key.is_some() {
+                profile.api_key = args.api_key.clone();
+            }
+            if args.user.is_some() {
+                profile.user = args.user.clone();
+            }
+        }
+        profiles
+    }
+}
+
+/// Default location of the config file
+pub fn config_path() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("config.toml")
+}
+
+/// krui's directory in the XDG base directory `var`, which defaults to `~/<fallback>`
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    let base = env::var_os(var)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(fallback)))
+        .unwrap_or_default();
+    base.join("krui")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [ui]
+        history_limit = 20
+
+        [printers.voron]
+        url = "https://farm.lan/voron"
+        api_key = "secret"
+        webcam = "nozzle"
+
+        [printers.ender]
+        url = "192.168.1.121"
+        filament_diameter = 2.85
+        unload_macro = "M702"
+    "#;
+
+    fn args(a: &[&str]) -> Args {
+        Args::parse(a.iter().map(|s| s.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_config_file_is_parsed() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(config.ui.history_limit, 20);
+        assert_eq!(config.ui.tick_rate, 100);
+        assert_eq!(config.printers["voron"].api_key.as_deref(), Some("secret"));
+        assert_eq!(config.printers["voron"].filament_diameter, 1.75);
+        assert_eq!(config.printers["ender"].filament_diameter, 2.85);
+        assert_eq!(config.printers["voron"].unload_macro, "UNLOAD_FILAMENT");
+        assert_eq!(config.printers["ender"].unload_macro, "M702");
+        assert!(toml::from_str::<Config>("[printers.voron]\napi_key = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_profiles_are_selected_by_name() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let all = config.profiles(&args(&[]));
+        assert_eq!(all.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>(), vec!["ender", "voron"]);
+
+        let some = config.profiles(&args(&["voron", "printer.local", "--api-key", "other"]));
+        assert_eq!(some.len(), 2);
+        assert_eq!(some[0].1.url, "https://farm.lan/voron");
+        assert_eq!(some[0].1.webcam.as_deref(), Some("nozzle"));
+        assert_eq!(some[1], ("printer.local".to_string(), Profile { api_key: Some("other".to_string()), ..Profile::from_url("printer.local") }));
+        assert_eq!(some[0].1.api_key.as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn test_command_line_overrides_preferences() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        config.apply(&args(&["--tick-rate", "250", "--cache-dir", "/tmp/krui"]));
+        assert_eq!(config.ui.tick_rate, 250);
+        assert_eq!(config.ui.cache_dir, PathBuf::from("/tmp/krui"));
+        assert_eq!(config.ui.history_limit, 20);
+        assert!(Args::parse(vec!["--tick-rate".to_string()]).is_err());
+        assert!(Args::parse(vec!["--verbose".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+/// Delay before the first retry
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+/// Longest delay between two connection attempts
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// State of the connection to Moonraker and, through it, to Klippy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A connection attempt is in progress
+    Connecting,
+    /// Websocket open and Klippy ready
+    Connected,
+    /// Websocket open, but Klippy is starting, shut down or disconnected
+    KlippyNotReady,
+    /// Waiting for `retry_at` before making connection attempt number `attempt`
+    Backoff { attempt: u32, retry_at: Instant },
+    /// Moonraker refused our credentials, retrying will not help
+    AuthFailed,
+}
+
+impl ConnectionState {
+    /// Whether the websocket is open
+    pub fn is_open(&self) -> bool {
+        matches!(self, ConnectionState::Connected | ConnectionState::KlippyNotReady)
+    }
+
+    /// Time left before the next attempt, when backing off
+    pub fn retry_in(&self, now: Instant) -> Option<Duration> {
+        match self {
+            ConnectionState::Backoff { retry_at, .. } => Some(retry_at.saturating_duration_since(now)),
+            _ => None,
+        }
+    }
+}
+
+/// Delay before connection attempt number `attempt`.
+///
+/// Doubles with each failed attempt up to [`BACKOFF_MAX`], and picks a random
+/// value in the upper half of that window so several clients do not retry in lockstep.
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let max = BACKOFF_BASE
+        .checked_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .unwrap_or(BACKOFF_MAX)
+        .min(BACKOFF_MAX);
+    let half = max / 2;
+    half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay_grows() {
+        for _ in 0..20 {
+            let d = backoff_delay(1);
+            assert!(d >= Duration::from_millis(500) && d <= Duration::from_secs(1));
+            let d = backoff_delay(4);
+            assert!(d >= Duration::from_secs(4) && d <= Duration::from_secs(8));
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay_is_capped() {
+        for attempt in [7, 30, 1000, u32::MAX] {
+            let d = backoff_delay(attempt);
+            assert!(d >= BACKOFF_MAX / 2 && d <= BACKOFF_MAX);
+        }
+    }
+
+    #[test]
+    fn test_retry_countdown() {
+        let now = Instant::now();
+        let state = ConnectionState::Backoff { attempt: 2, retry_at: now + Duration::from_secs(3) };
+        assert_eq!(state.retry_in(now), Some(Duration::from_secs(3)));
+        assert_eq!(ConnectionState::Connecting.retry_in(now), None);
+        assert!(!state.is_open());
+        assert!(ConnectionState::KlippyNotReady.is_open());
+    }
+}
@@ -0,0 +1,805 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use flume::{Receiver, RecvTimeoutError, Sender};
+use serde_json::{Map, Value, json};
+use websocket::OwnedMessage;
+
+/// Time between two status updates of the simulated printer
+const STEP: Duration = Duration::from_millis(250);
+const AMBIENT: f64 = 22.0;
+/// File printed by the simulated printer
+pub const DEMO_FILE: &str = "demo_benchy.gcode";
+/// Printing time of the demo file, in seconds
+const PRINT_TIME: f64 = 900.0;
+const LAYERS: i64 = 240;
+const LAYER_HEIGHT: f64 = 0.2;
+/// Filament needed for the demo file, in mm
+const FILAMENT: f64 = 4500.0;
+/// Travel of the X, Y, Z and E axes, a 350mm Voron
+const AXIS_MINIMUM: [f64; 4] = [0.0, 0.0, -5.0, -10000.0];
+const AXIS_MAXIMUM: [f64; 4] = [350.0, 350.0, 340.0, 10000.0];
+/// First and last probe points of the bed mesh
+const MESH_MIN: [f64; 2] = [20.0, 20.0];
+const MESH_MAX: [f64; 2] = [330.0, 330.0];
+/// Probe points along X and Y
+const MESH_POINTS: usize = 5;
+/// Interpolated points between two probe points
+const MESH_PPS: usize = 2;
+/// Bed screws by name, with the probe point of the mesh above them
+const SCREWS: [(&str, usize, usize); 4] = [
+    ("front left screw", 0, 0),
+    ("front right screw", MESH_POINTS - 1, 0),
+    ("rear right screw", MESH_POINTS - 1, MESH_POINTS - 1),
+    ("rear left screw", 0, MESH_POINTS - 1),
+];
+/// Height change of a full turn of an M3 screw, in mm
+const SCREW_PITCH: f64 = 0.5;
+
+/// Heater reaching its target like a first order system
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimHeater {
+    pub temperature: f64,
+    pub target: f64,
+    pub power: f64,
+    /// Seconds to get about two thirds of the way to the target
+    time_constant: f64,
+}
+
+impl SimHeater {
+    fn new(time_constant: f64) -> SimHeater {
+        SimHeater { temperature: AMBIENT, target: 0.0, power: 0.0, time_constant }
+    }
+
+    fn step(&mut self, dt: f64) {
+        let goal = if self.target > 0.0 { self.target } else { AMBIENT };
+        self.temperature += (goal - self.temperature) * (1.0 - (-dt / self.time_constant).exp());
+        self.power = if self.target > 0.0 {
+            ((self.target - self.temperature) / 10.0 + 0.3).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+    }
+
+    /// Whether printing can go on
+    fn is_at_target(&self) -> bool {
+        self.target <= 0.0 || self.temperature >= self.target - 3.0
+    }
+
+    fn status(&self) -> Value {
+        json!({"temperature": self.temperature, "target": self.target, "power": self.power})
+    }
+}
+
+/// Klipper and Moonraker as seen through the websocket, without a printer.
+pub struct Simulator {
+    /// Seconds since the simulated Klipper started
+    pub eventtime: f64,
+    pub klippy_state: String,
+    pub extruder: SimHeater,
+    pub bed: SimHeater,
+    /// Temperature fan cooling the electronics
+    pub chamber: SimHeater,
+    pub print_state: String,
+    pub filename: String,
+    pub progress: f64,
+    print_duration: f64,
+    total_duration: f64,
+    pub position: [f64; 4],
+    pub homed_axes: String,
+    qgl_applied: bool,
+    fan_speed: f64,
+    speed_factor: f64,
+    extrude_factor: f64,
+    z_offset: f64,
+    /// Set by `G91`, cleared by `G90`
+    relative: bool,
+    /// Origin of gcode coordinates, set by `G92`
+    gcode_origin: [f64; 4],
+    /// Profile and probed points of the current bed mesh
+    mesh: Option<(String, Vec<Vec<f64>>)>,
+    mesh_profiles: BTreeMap<String, Vec<Vec<f64>>>,
+    /// Results of the last `SCREWS_TILT_CALCULATE`
+    screws: Map<String, Value>,
+    /// Finished jobs, oldest first
+    jobs: Vec<Value>,
+    /// JSON-RPC notifications waiting to be sent
+    notifications: Vec<Value>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    /// Printer a third of the way through a print, so there is something to look at
+    pub fn new() -> Simulator {
+        let mut sim = Simulator {
+            eventtime: 0.0,
+            klippy_state: "ready".to_string(),
+            extruder: SimHeater::new(6.0),
+            bed: SimHeater::new(25.0),
+            chamber: SimHeater::new(60.0),
+            print_state: "standby".to_string(),
+            filename: "".to_string(),
+            progress: 0.0,
+            print_duration: 0.0,
+            total_duration: 0.0,
+            position: [0.0; 4],
+            homed_axes: "".to_string(),
+            qgl_applied: false,
+            fan_speed: 0.0,
+            speed_factor: 1.0,
+            extrude_factor: 1.0,
+            z_offset: 0.0,
+            relative: false,
+            gcode_origin: [0.0; 4],
+            mesh: None,
+            mesh_profiles: BTreeMap::from([
+                ("default".to_string(), probe_mesh(0.0)),
+                ("pla_60c".to_string(), probe_mesh(1.0)),
+            ]),
+            screws: Map::new(),
+            jobs: vec![
+                job("calibration_cube.gcode", "completed", 1_700_000_000.0, 1800.0),
+                job("phone_stand.gcode", "cancelled", 1_700_050_000.0, 700.0),
+            ],
+            notifications: vec![],
+        };
+        sim.start_print(DEMO_FILE);
+        sim.homed_axes = "xyz".to_string();
+        sim.qgl_applied = true;
+        sim.fan_speed = 1.0;
+        sim.progress = 0.3;
+        sim.print_duration = 0.3 * PRINT_TIME;
+        sim.total_duration = sim.print_duration + 120.0;
+        for heater in [&mut sim.extruder, &mut sim.bed] {
+            heater.temperature = heater.target;
+        }
+        sim.chamber.target = 40.0;
+        sim.mesh = Some(("default".to_string(), probe_mesh(0.0)));
+        sim.notifications.clear();
+        sim
+    }
+
+    /// Advances the simulation by `dt` seconds
+    pub fn step(&mut self, dt: f64) {
+        self.eventtime += dt;
+        self.extruder.step(dt);
+        self.bed.step(dt);
+        self.chamber.step(dt);
+        if self.print_state != "printing" {
+            return;
+        }
+        self.total_duration += dt;
+        // Like PRINT_START, wait for the heaters before printing
+        if !self.extruder.is_at_target() || !self.bed.is_at_target() {
+            return;
+        }
+        self.print_duration += dt;
+        self.progress = (self.progress + dt / PRINT_TIME).min(1.0);
+        // Go round the outline of the current layer
+        let angle = self.print_duration * 1.5;
+        self.position = [
+            175.0 + 30.0 * angle.cos(),
+            175.0 + 20.0 * angle.sin(),
+            self.current_layer() as f64 * LAYER_HEIGHT,
+            self.progress * FILAMENT,
+        ];
+        if self.progress >= 1.0 {
+            self.end_print("complete");
+        }
+    }
+
+    fn current_layer(&self) -> i64 {
+        ((self.progress * LAYERS as f64).ceil() as i64).clamp(1, LAYERS)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.notifications.push(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+
+    /// Notifications produced since the last call
+    pub fn take_notifications(&mut self) -> Vec<Value> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    fn start_print(&mut self, filename: &str) {
+        self.print_state = "printing".to_string();
+        self.filename = filename.to_string();
+        self.progress = 0.0;
+        self.print_duration = 0.0;
+        self.total_duration = 0.0;
+        self.extruder.target = 215.0;
+        self.bed.target = 60.0;
+    }
+
+    fn end_print(&mut self, state: &str) {
+        self.print_state = state.to_string();
+        self.extruder.target = 0.0;
+        self.bed.target = 0.0;
+        self.fan_speed = 0.0;
+        let status = if state == "complete" { "completed" } else { state };
+        let j = job(self.filename.as_str(), status, 1_700_100_000.0 + self.eventtime, self.total_duration);
+        self.jobs.push(j.clone());
+        self.notify("notify_history_changed", json!([{"action": "added", "job": j}]));
+    }
+
+    /// All the Klipper objects
+    pub fn status(&self) -> Value {
+        let message = match self.klippy_state.as_str() {
+            "ready" => "Printer is ready",
+            "shutdown" => "Emergency stop",
+            _ => "Printer is not ready",
+        };
+        let filament_used = if self.print_state == "standby" { 0.0 } else { self.progress * FILAMENT };
+        let printing = self.print_state == "printing";
+        let velocity = if printing && self.extruder.is_at_target() { 120.0 } else { 0.0 };
+        json!({
+            "webhooks": {"state": self.klippy_state, "state_message": message},
+            "heaters": {
+                "available_heaters": ["extruder", "heater_bed"],
+                "available_sensors": ["extruder", "heater_bed", "temperature_fan chamber"],
+            },
+            "extruder": self.extruder_status(),
+            "heater_bed": self.bed.status(),
+            "temperature_fan chamber": {"temperature": self.chamber.temperature, "target": self.chamber.target, "speed": self.chamber.power},
+            "print_stats": {
+                "state": self.print_state,
+                "filename": self.filename,
+                "print_duration": self.print_duration,
+                "total_duration": self.total_duration,
+                "filament_used": filament_used,
+                "info": {"total_layer": LAYERS, "current_layer": if self.print_state == "standby" { 0 } else { self.current_layer() }},
+            },
+            "virtual_sdcard": {"progress": self.progress, "is_active": printing, "file_path": format!("/home/pi/printer_data/gcodes/{}", self.filename)},
+            "display_status": {"progress": self.progress, "message": null},
+            "toolhead": {"homed_axes": self.homed_axes, "position": self.position, "axis_minimum": AXIS_MINIMUM, "axis_maximum": AXIS_MAXIMUM},
+            "gcode_move": {"position": self.position, "gcode_position": self.gcode_position(), "speed_factor": self.speed_factor, "extrude_factor": self.extrude_factor, "homing_origin": [0.0, 0.0, self.z_offset, 0.0]},
+            "motion_report": {"live_position": self.position, "live_velocity": velocity, "live_extruder_velocity": velocity / 50.0},
+            "fan": {"speed": self.fan_speed},
+            "quad_gantry_level": {"applied": self.qgl_applied},
+            "bed_mesh": self.bed_mesh_status(),
+            "screws_tilt_adjust": {"error": false, "max_deviation": null, "results": self.screws},
+            "stepper_enable": {"steppers": {"stepper_x": !self.homed_axes.is_empty(), "stepper_y": !self.homed_axes.is_empty(), "stepper_z": !self.homed_axes.is_empty()}},
+            "system_stats": {"sysload": 0.3 + 0.1 * (self.eventtime / 10.0).sin(), "cputime": self.eventtime / 20.0, "memavail": 3_000_000},
+        })
+    }
+
+    /// Result of a JSON-RPC request, or its error code and message
+    pub fn answer(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let shutdown = self.klippy_state != "ready";
+        match method {
+            "server.connection.identify" => Ok(json!({"connection_id": 1})),
+            "server.info" => Ok(json!({
+                "klippy_connected": true,
+                "klippy_state": self.klippy_state,
+                "components": ["history"],
+                "warnings": [],
+                "moonraker_version": "demo",
+            })),
+            "printer.objects.list" => Ok(json!({"objects": self.status().as_object().map(|o| o.keys().cloned().collect::<Vec<_>>())})),
+            "printer.objects.query" | "printer.objects.subscribe" => {
+                let all = self.status();
+                let mut status = Map::new();
+                if let Some(objects) = params.get("objects").and_then(|o| o.as_object()) {
+                    for name in objects.keys() {
+                        if let Some(object) = all.get(name) {
+                            status.insert(name.clone(), object.clone());
+                        }
+                    }
+                }
+                Ok(json!({"eventtime": self.eventtime, "status": status}))
+            },
+            "server.history.list" => {
+                let jobs: Vec<&Value> = self.jobs.iter().rev().collect();
+                Ok(json!({"count": jobs.len(), "jobs": jobs}))
+            },
+            "server.webcams.list" => Ok(json!({"webcams": []})),
+            "server.files.metadata" => match params.get("filename").and_then(|f| f.as_str()) {
+                Some(DEMO_FILE) => Ok(json!({
+                    "filename": DEMO_FILE,
+                    "size": 3_145_728,
+                    "slicer": "PrusaSlicer",
+                    "layer_height": LAYER_HEIGHT,
+                    "first_layer_height": LAYER_HEIGHT,
+                    "object_height": LAYERS as f64 * LAYER_HEIGHT,
+                    "filament_total": FILAMENT,
+                    "estimated_time": PRINT_TIME,
+                })),
+                f => Err((404, format!("Metadata not available for <{}>", f.unwrap_or_default()))),
+            },
+            "printer.gcode.script" => {
+                let script = params.get("script").and_then(|s| s.as_str()).unwrap_or_default().to_string();
+                self.run_script(script.as_str()).map(|_| json!("ok")).map_err(|e| (400, e))
+            },
+            "printer.emergency_stop" => {
+                self.shutdown();
+                Ok(json!("ok"))
+            },
+            "printer.restart" | "printer.firmware_restart" => {
+                self.restart();
+                Ok(json!("ok"))
+            },
+            m if m.starts_with("printer.print.") && shutdown => Err((503, "Klippy not ready".to_string())),
+            "printer.print.start" => {
+                if matches!(self.print_state.as_str(), "printing" | "paused") {
+                    return Err((400, "Printer is busy".to_string()));
+                }
+                let filename = params.get("filename").and_then(|f| f.as_str()).unwrap_or(DEMO_FILE);
+                self.start_print(filename);
+                Ok(json!("ok"))
+            },
+            "printer.print.pause" => self.run_script("PAUSE").map(|_| json!("ok")).map_err(|e| (400, e)),
+            "printer.print.resume" => self.run_script("RESUME").map(|_| json!("ok")).map_err(|e| (400, e)),
+            "printer.print.cancel" => self.run_script("CANCEL_PRINT").map(|_| json!("ok")).map_err(|e| (400, e)),
+            _ => Err((-32601, "Method not found".to_string())),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.klippy_state = "shutdown".to_string();
+        self.extruder.target = 0.0;
+        self.bed.target = 0.0;
+        if matches!(self.print_state.as_str(), "printing" | "paused") {
+            self.print_state = "error".to_string();
+        }
+        self.notify("notify_klippy_shutdown", json!([]));
+    }
+
+    fn restart(&mut self) {
+        self.klippy_state = "ready".to_string();
+        self.homed_axes = "".to_string();
+        self.qgl_applied = false;
+        if self.print_state != "printing" {
+            self.print_state = "standby".to_string();
+        }
+        self.notify("notify_klippy_ready", json!([]));
+    }
+
+    /// Runs each line of `script`, echoing it and its response to the console
+    fn run_script(&mut self, script: &str) -> Result<(), String> {
+        for line in script.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            self.notify("notify_gcode_response", json!([line]));
+            match self.gcode(line) {
+                Ok(Some(response)) => self.notify("notify_gcode_response", json!([response])),
+                Ok(None) => {},
+                Err(e) => {
+                    // Klipper writes errors to the console too
+                    self.notify("notify_gcode_response", json!([format!("!! {}", e)]));
+                    return Err(e);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Position in gcode coordinates, from the origin set by `G92`
+    fn gcode_position(&self) -> [f64; 4] {
+        let mut position = self.position;
+        for (p, o) in position.iter_mut().zip(self.gcode_origin) {
+            *p -= o;
+        }
+        position
+    }
+
+    fn bed_mesh_status(&self) -> Value {
+        let (name, probed, mesh) = match &self.mesh {
+            Some((name, probed)) => (name.as_str(), probed.clone(), interpolate(probed, MESH_PPS)),
+            None => ("", vec![vec![]], vec![vec![]]),
+        };
+        let profiles: Map<String, Value> = self.mesh_profiles.iter().map(|(name, points)| (name.clone(), json!({
+            "points": points,
+            "mesh_params": {"min_x": MESH_MIN[0], "max_x": MESH_MAX[0], "min_y": MESH_MIN[1], "max_y": MESH_MAX[1], "x_count": MESH_POINTS, "y_count": MESH_POINTS},
+        }))).collect();
+        json!({
+            "profile_name": name,
+            "mesh_min": MESH_MIN,
+            "mesh_max": MESH_MAX,
+            "probed_matrix": probed,
+            "mesh_matrix": mesh,
+            "profiles": profiles,
+        })
+    }
+
+    fn extruder_status(&self) -> Value {
+        let mut status = self.extruder.status();
+        status["pressure_advance"] = json!(0.04);
+        status["smooth_time"] = json!(0.04);
+        status["can_extrude"] = json!(self.extruder.temperature >= 170.0);
+        status
+    }
+
+    /// Runs a gcode command, and gives its response if there is one
+    fn gcode(&mut self, line: &str) -> Result<Option<String>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = words[0].to_uppercase();
+        if self.klippy_state != "ready" && !matches!(command.as_str(), "FIRMWARE_RESTART" | "RESTART" | "STATUS") {
+            return Err("Printer is not ready".to_string());
+        }
+        let value = |key: char| words.iter().skip(1)
+            .find(|w| w.to_uppercase().starts_with(key))
+            .and_then(|w| w[1..].parse::<f64>().ok());
+        let named = |key: &str| words.iter().skip(1)
+            .filter_map(|w| w.split_once('='))
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.to_string());
+        match command.as_str() {
+            "G28" => {
+                let axes: String = words.iter().skip(1).map(|w| w.to_lowercase()).collect();
+                let axes = if axes.is_empty() { "xyz".to_string() } else { axes };
+                for axis in axes.chars().filter(|c| "xyz".contains(*c)) {
+                    if !self.homed_axes.contains(axis) {
+                        self.homed_axes.push(axis);
+                    }
+                    self.position["xyz".find(axis).unwrap_or_default()] = 0.0;
+                }
+                let mut homed: Vec<char> = self.homed_axes.chars().collect();
+                homed.sort();
+                self.homed_axes = homed.into_iter().collect();
+                Ok(None)
+            },
+            "G0" | "G1" => {
+                let moves: Vec<(usize, f64)> = ['X', 'Y', 'Z', 'E'].iter().enumerate()
+                    .filter_map(|(i, k)| value(*k).map(|v| (i, v)))
+                    .collect();
+                for (i, _) in moves.iter() {
+                    if *i < 3 && !self.homed_axes.contains(['x', 'y', 'z'][*i]) {
+                        let p = self.position;
+                        return Err(format!("Must home axis first: {:.3} {:.3} {:.3} [{:.3}]", p[0], p[1], p[2], p[3]));
+                    }
+                }
+                for (i, v) in moves {
+                    let target = if self.relative { self.position[i] + v } else { v + self.gcode_origin[i] };
+                    if i < 3 && !(AXIS_MINIMUM[i]..=AXIS_MAXIMUM[i]).contains(&target) {
+                        return Err(format!("Move out of range: {:.3}", target));
+                    }
+                    self.position[i] = target;
+                }
+                Ok(None)
+            },
+            "G90" => {
+                self.relative = false;
+                Ok(None)
+            },
+            "G91" => {
+                self.relative = true;
+                Ok(None)
+            },
+            "G92" => {
+                for (i, k) in ['X', 'Y', 'Z', 'E'].iter().enumerate() {
+                    if let Some(v) = value(*k) {
+                        self.gcode_origin[i] = self.position[i] - v;
+                    }
+                }
+                Ok(None)
+            },
+            "M84" | "M18" => {
+                self.homed_axes.clear();
+                Ok(None)
+            },
+            "M104" | "M109" => {
+                self.extruder.target = value('S').unwrap_or(0.0);
+                Ok(None)
+            },
+            "M140" | "M190" => {
+                self.bed.target = value('S').unwrap_or(0.0);
+                Ok(None)
+            },
+            "SET_HEATER_TEMPERATURE" => {
+                let target = named("TARGET").and_then(|t| t.parse().ok()).unwrap_or(0.0);
+                match named("HEATER").as_deref() {
+                    Some("extruder") => self.extruder.target = target,
+                    Some("heater_bed") => self.bed.target = target,
+                    h => return Err(format!("Unknown heater '{}'", h.unwrap_or_default())),
+                }
+                Ok(None)
+            },
+            "SET_TEMPERATURE_FAN_TARGET" => {
+                self.chamber.target = named("TARGET").and_then(|t| t.parse().ok()).unwrap_or(0.0);
+                Ok(None)
+            },
+            "M106" => {
+                self.fan_speed = (value('S').unwrap_or(255.0) / 255.0).clamp(0.0, 1.0);
+                Ok(None)
+            },
+            "M107" => {
+                self.fan_speed = 0.0;
+                Ok(None)
+            },
+            "M114" => {
+                let p = self.position;
+                Ok(Some(format!("X:{:.3} Y:{:.3} Z:{:.3} E:{:.3}", p[0], p[1], p[2], p[3])))
+            },
+            "M117" | "M82" | "M83" | "SAVE_GCODE_STATE" | "RESTORE_GCODE_STATE" => Ok(None),
+            "LOAD_FILAMENT" | "UNLOAD_FILAMENT" if self.extruder.temperature < 170.0 => Err("Extrude below minimum temp".to_string()),
+            "LOAD_FILAMENT" => Ok(Some("// Filament loaded".to_string())),
+            "UNLOAD_FILAMENT" => Ok(Some("// Filament unloaded".to_string())),
+            "M220" => {
+                self.speed_factor = value('S').unwrap_or(100.0) / 100.0;
+                Ok(None)
+            },
+            "M221" => {
+                self.extrude_factor = value('S').unwrap_or(100.0) / 100.0;
+                Ok(None)
+            },
+            "SET_GCODE_OFFSET" => {
+                if let Some(z) = named("Z").and_then(|z| z.parse::<f64>().ok()) {
+                    self.z_offset = z;
+                }
+                if let Some(z) = named("Z_ADJUST").and_then(|z| z.parse::<f64>().ok()) {
+                    self.z_offset += z;
+                }
+                Ok(None)
+            },
+            "Z_OFFSET_APPLY_ENDSTOP" => Ok(Some(format!(
+                "// stepper_z: position_endstop: {:.3}\n// The SAVE_CONFIG command will update the printer config file\n// with the above and restart the printer.", -self.z_offset,
+            ))),
+            "M118" | "RESPOND" => Ok(Some(format!("echo: {}", words[1..].join(" ")))),
+            "QUAD_GANTRY_LEVEL" => {
+                if self.homed_axes != "xyz" {
+                    return Err("Must home axes first".to_string());
+                }
+                self.qgl_applied = true;
+                Ok(Some("// Retries: 0/5 Probed points range: 0.003400 tolerance: 0.007500".to_string()))
+            },
+            "BED_MESH_CALIBRATE" => {
+                if self.homed_axes != "xyz" {
+                    return Err("Must home axes first".to_string());
+                }
+                let points = probe_mesh(self.eventtime);
+                self.mesh_profiles.insert("default".to_string(), points.clone());
+                self.mesh = Some(("default".to_string(), points));
+                Ok(Some("// Mesh Bed Leveling Complete\n// Bed Mesh state has been saved to profile [default]".to_string()))
+            },
+            "BED_MESH_CLEAR" => {
+                self.mesh = None;
+                Ok(None)
+            },
+            "BED_MESH_PROFILE" => {
+                let profile = |key: &str| named(key).map(|n| n.trim_matches('"').to_string());
+                if let Some(name) = profile("LOAD") {
+                    let points = self.mesh_profiles.get(&name).ok_or(format!("bed_mesh: Unknown profile [{}]", name))?;
+                    self.mesh = Some((name, points.clone()));
+                    Ok(None)
+                } else if let Some(name) = profile("SAVE") {
+                    let (_, points) = self.mesh.clone().ok_or("Bed has not been probed")?;
+                    self.mesh_profiles.insert(name.clone(), points.clone());
+                    self.mesh = Some((name.clone(), points));
+                    Ok(Some(format!("// Bed Mesh state has been saved to profile [{}]\n// The SAVE_CONFIG command will update the printer config file", name)))
+                } else if let Some(name) = profile("REMOVE") {
+                    self.mesh_profiles.remove(&name).ok_or(format!("bed_mesh: Unknown profile [{}]", name))?;
+                    Ok(Some(format!("// Profile [{}] removed from storage for this session.\n// The SAVE_CONFIG command will update the printer\n// configuration and restart the printer", name)))
+                } else {
+                    Err("Invalid syntax 'BED_MESH_PROFILE'".to_string())
+                }
+            },
+            "SCREWS_TILT_CALCULATE" => {
+                if self.homed_axes != "xyz" {
+                    return Err("Must home axes first".to_string());
+                }
+                let points = probe_mesh(self.eventtime);
+                let mut lines = vec!["// 01:20 means 1 full turn and 20 minutes, CW=clockwise, CCW=counter-clockwise".to_string()];
+                let base = points[SCREWS[0].2][SCREWS[0].1] + 2.5;
+                self.screws.clear();
+                for (i, (name, x, y)) in SCREWS.iter().enumerate() {
+                    let z = points[*y][*x] + 2.5;
+                    // Same sign as Klipper for clockwise threads
+                    let turns = (base - z) / SCREW_PITCH;
+                    let sign = if turns >= 0.0 { "CW" } else { "CCW" };
+                    let minutes = (turns.abs() * 60.0).round() as u32;
+                    let adjust = format!("{:02}:{:02}", minutes / 60, minutes % 60);
+                    lines.push(if i == 0 {
+                        format!("// {} (base) : z={:.5}", name, z)
+                    } else {
+                        format!("// {} : z={:.5} : adjust {} {}", name, z, sign, adjust)
+                    });
+                    self.screws.insert(format!("screw{}", i + 1), json!({"name": name, "z": z, "sign": sign, "adjust": adjust, "is_base": i == 0}));
+                }
+                Ok(Some(lines.join("\n")))
+            },
+            "PAUSE" if self.print_state == "printing" => {
+                self.print_state = "paused".to_string();
+                Ok(None)
+            },
+            "RESUME" if self.print_state == "paused" => {
+                self.print_state = "printing".to_string();
+                Ok(None)
+            },
+            "PAUSE" => Err("Print is not printing".to_string()),
+            "RESUME" => Err("Print is not paused, resume aborted".to_string()),
+            "CANCEL_PRINT" if matches!(self.print_state.as_str(), "printing" | "paused") => {
+                self.end_print("cancelled");
+                Ok(None)
+            },
+            "CANCEL_PRINT" => Ok(None),
+            "FIRMWARE_RESTART" | "RESTART" => {
+                self.restart();
+                Ok(None)
+            },
+            "STATUS" => Ok(Some(format!("// Klipper state: {}", self.klippy_state))),
+            "HELP" => Ok(Some("// Available extended commands:\n// G28, G0, G1, M104, M109, M140, M190, M106, M107, M114, M118, M220, M221, M82, M83\n// G90, G91, G92, M84\n// SET_HEATER_TEMPERATURE, SET_TEMPERATURE_FAN_TARGET, QUAD_GANTRY_LEVEL\n// SET_GCODE_OFFSET, Z_OFFSET_APPLY_ENDSTOP, PAUSE, RESUME, CANCEL_PRINT, FIRMWARE_RESTART, STATUS\n// LOAD_FILAMENT, UNLOAD_FILAMENT, BED_MESH_CALIBRATE, BED_MESH_CLEAR, BED_MESH_PROFILE\n// SCREWS_TILT_CALCULATE, SAVE_GCODE_STATE, RESTORE_GCODE_STATE".to_string())),
+            _ => Err(format!("Unknown command:\"{}\"", command)),
+        }
+    }
+}
+
+/// A slightly tilted and warped bed, `seed` changing the noise of each probe
+fn probe_mesh(seed: f64) -> Vec<Vec<f64>> {
+    let center = (MESH_POINTS - 1) as f64 / 2.0;
+    (0..MESH_POINTS).map(|y| (0..MESH_POINTS).map(|x| {
+        let (dx, dy) = ((x as f64 - center) / center, (y as f64 - center) / center);
+        let z = 0.06 * dx - 0.04 * dy + 0.05 * (dx * dx + dy * dy) - 0.04 + 0.008 * (seed + x as f64 * 3.0 + y as f64 * 7.0).sin();
+        (z * 10000.0).round() / 10000.0
+    }).collect()).collect()
+}
+
+/// Adds `pps` points between two probe points, like Klipper's bilinear interpolation
+fn interpolate(points: &[Vec<f64>], pps: usize) -> Vec<Vec<f64>> {
+    let rows = points.len();
+    let columns = points.first().map_or(0, |r| r.len());
+    if rows < 2 || columns < 2 {
+        return points.to_vec();
+    }
+    let size = |n: usize| (n - 1) * (pps + 1) + 1;
+    let at = |i: usize, n: usize| {
+        let t = i as f64 / (pps + 1) as f64;
+        let low = (t.floor() as usize).min(n - 2);
+        (low, t - low as f64)
+    };
+    (0..size(rows)).map(|y| {
+        let (row, ty) = at(y, rows);
+        (0..size(columns)).map(|x| {
+            let (column, tx) = at(x, columns);
+            let front = points[row][column] * (1.0 - tx) + points[row][column + 1] * tx;
+            let back = points[row + 1][column] * (1.0 - tx) + points[row + 1][column + 1] * tx;
+            front * (1.0 - ty) + back * ty
+        }).collect()
+    }).collect()
+}
+
+fn job(filename: &str, status: &str, end_time: f64, total_duration: f64) -> Value {
+    json!({
+        "job_id": format!("{:06X}", end_time as u64 % 0xFFFFFF),
+        "filename": filename,
+        "status": status,
+        "start_time": end_time - total_duration,
+        "end_time": end_time,
+        "total_duration": total_duration,
+        "print_duration": total_duration * 0.9,
+        "filament_used": FILAMENT * total_duration / PRINT_TIME,
+        "metadata": {"estimated_time": PRINT_TIME},
+    })
+}
+
+/// Answers the requests sent to `requests` and sends status updates to `messages`,
+/// until the application stops listening
+pub fn run(requests: Receiver<OwnedMessage>, messages: Sender<OwnedMessage>) {
+    let mut sim = Simulator::new();
+    let mut last_step = Instant::now();
+    loop {
+        let timeout = STEP.saturating_sub(last_step.elapsed());
+        match requests.recv_timeout(timeout) {
+            Ok(OwnedMessage::Text(text)) => {
+                let request: Value = match serde_json::from_str(text.as_str()) {
+                    Ok(r) => r,
+                    Err(_) => continue,
+                };
+                let params = request.get("params").cloned().unwrap_or(Value::Null);
+                let mut response = json!({"jsonrpc": "2.0", "id": request["id"]});
+                match sim.answer(request["method"].as_str().unwrap_or_default(), &params) {
+                    Ok(result) => response["result"] = result,
+                    Err((code, message)) => response["error"] = json!({"code": code, "message": message}),
+                }
+                if messages.send(OwnedMessage::Text(response.to_string())).is_err() {
+                    return;
+                }
+            },
+            Ok(_) => {},
+            Err(RecvTimeoutError::Timeout) => {
+                let dt = last_step.elapsed().as_secs_f64();
+                last_step = Instant::now();
+                sim.step(dt);
+                let update = json!({"jsonrpc": "2.0", "method": "notify_status_update", "params": [sim.status(), sim.eventtime]});
+                if messages.send(OwnedMessage::Text(update.to_string())).is_err() {
+                    return;
+                }
+            },
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        for notification in sim.take_notifications() {
+            if messages.send(OwnedMessage::Text(notification.to_string())).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::App;
+
+    #[test]
+    fn test_heaters_ramp_and_print_completes() {
+        let mut sim = Simulator::new();
+        sim.answer("printer.print.cancel", &json!({})).unwrap();
+        assert_eq!(sim.print_state, "cancelled");
+        sim.step(600.0);
+        assert!((sim.extruder.temperature - AMBIENT).abs() < 1.0);
+
+        sim.answer("printer.print.start", &json!({"filename": DEMO_FILE})).unwrap();
+        sim.step(1.0);
+        assert!(sim.extruder.temperature > AMBIENT && sim.extruder.temperature < 215.0);
+        assert_eq!(sim.progress, 0.0);
+        for _ in 0..(PRINT_TIME as usize + 200) {
+            sim.step(1.0);
+        }
+        assert_eq!(sim.print_state, "complete");
+        assert_eq!(sim.status()["virtual_sdcard"]["progress"], 1.0);
+        assert_eq!(sim.jobs.len(), 4);
+    }
+
+    #[test]
+    fn test_console_commands() {
+        let mut sim = Simulator::new();
+        sim.restart();
+        assert!(sim.answer("printer.gcode.script", &json!({"script": "G1 X10"})).is_err());
+        sim.answer("printer.gcode.script", &json!({"script": "G28\nG1 X10 Y20\nM114"})).unwrap();
+        let lines: Vec<Value> = sim.take_notifications().into_iter()
+            .filter(|n| n["method"] == "notify_gcode_response")
+            .map(|n| n["params"][0].clone())
+            .collect();
+        assert_eq!(lines[0], "G1 X10");
+        assert_eq!(lines[1], "!! Must home axis first: 0.000 0.000 0.000 [0.000]");
+        assert_eq!(lines.last().unwrap(), "X:10.000 Y:20.000 Z:0.000 E:0.000");
+        sim.answer("printer.gcode.script", &json!({"script": "SET_HEATER_TEMPERATURE HEATER=heater_bed TARGET=70"})).unwrap();
+        assert_eq!(sim.bed.target, 70.0);
+        sim.answer("printer.gcode.script", &json!({"script": "M220 S120\nSET_GCODE_OFFSET Z_ADJUST=-0.010 MOVE=1"})).unwrap();
+        assert_eq!(sim.status()["gcode_move"]["speed_factor"], 1.2);
+        assert_eq!(sim.status()["gcode_move"]["homing_origin"][2], -0.01);
+        sim.answer("printer.gcode.script", &json!({"script": "G91\nG0 X5 F3000\nG90\nG92 X0"})).unwrap();
+        assert_eq!(sim.position[0], 15.0);
+        assert_eq!(sim.status()["gcode_move"]["gcode_position"][0], 0.0);
+        assert!(sim.answer("printer.gcode.script", &json!({"script": "G1 X400"})).is_err());
+    }
+
+    #[test]
+    fn test_bed_mesh_profiles() {
+        let mut sim = Simulator::new();
+        assert_eq!(sim.status()["bed_mesh"]["profile_name"], "default");
+        assert_eq!(sim.status()["bed_mesh"]["mesh_matrix"].as_array().unwrap().len(), 13);
+        sim.answer("printer.gcode.script", &json!({"script": "BED_MESH_CLEAR"})).unwrap();
+        assert_eq!(sim.status()["bed_mesh"]["probed_matrix"], json!([[]]));
+        assert!(sim.answer("printer.gcode.script", &json!({"script": "BED_MESH_PROFILE SAVE=\"abs\""})).is_err());
+
+        sim.answer("printer.gcode.script", &json!({"script": "BED_MESH_PROFILE LOAD=\"pla_60c\"\nBED_MESH_PROFILE SAVE=abs\nBED_MESH_PROFILE REMOVE=pla_60c"})).unwrap();
+        let mesh = sim.status()["bed_mesh"].clone();
+        assert_eq!(mesh["profile_name"], "abs");
+        assert_eq!(mesh["profiles"].as_object().unwrap().keys().collect::<Vec<_>>(), vec!["abs", "default"]);
+        assert_eq!(mesh["mesh_matrix"][0][3], mesh["probed_matrix"][0][1]);
+
+        sim.answer("printer.gcode.script", &json!({"script": "SCREWS_TILT_CALCULATE"})).unwrap();
+        let screws = &sim.status()["screws_tilt_adjust"]["results"];
+        assert_eq!(screws.as_object().unwrap().len(), 4);
+        assert_eq!(screws["screw1"]["adjust"], "00:00");
+        assert_eq!(screws["screw3"]["name"], "rear right screw");
+    }
+
+    #[test]
+    fn test_app_shows_the_simulated_printer() {
+        let mut app = App::default();
+        app.demo();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) && !(app.is_ready() && app.printer.current_print.is_some()) {
+            app.tick();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(app.is_ready());
+        assert_eq!(app.printer.status.print_state, "printing");
+        assert_eq!(app.printer.current_print.as_ref().unwrap().filename, DEMO_FILE);
+        assert_eq!(app.printer.status.heaters.items.len(), 3);
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::Instant;
+
+use crate::app::{App, Tab, TICK_BUDGET};
+
+/// All the printers krui is connected to, one of them shown at a time.
+pub struct Farm {
+    /// One application state, with its own connection, per printer
+    pub apps: Vec<App>,
+    /// Index of the printer being shown
+    pub current: usize,
+    /// Whether the overview of all printers is shown instead of the current one
+    pub overview: bool,
+}
+
+impl Farm {
+    pub fn new(apps: Vec<App>) -> Farm {
+        // Printer farms start on the overview, there is nothing to choose with one printer
+        let overview = apps.len() > 1;
+        Farm { apps, current: 0, overview }
+    }
+
+    /// Starts connecting to every printer
+    pub fn init(&mut self) {
+        for app in self.apps.iter_mut() {
+            app.init();
+        }
+    }
+
+    /// Whether the user did not ask to quit
+    pub fn is_running(&self) -> bool {
+        self.apps.iter().all(|a| a.running)
+    }
+
+    /// Printer being shown
+    pub fn current(&mut self) -> &mut App {
+        &mut self.apps[self.current]
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.apps.len();
+    }
+
+    pub fn previous(&mut self) {
+        self.current = (self.current + self.apps.len() - 1) % self.apps.len();
+    }
+
+    /// Leaves the overview for the main tab of the current printer
+    pub fn open(&mut self) {
+        self.overview = false;
+        self.current().current_tab = Tab::Main;
+    }
+
+    /// Forgets what changed on every printer, once a frame was drawn
+    pub fn end_frame(&mut self) {
+        for app in self.apps.iter_mut() {
+            app.printer.objects.end_frame();
+        }
+    }
+
+    /// Ticks every printer within one budget, and shows their alerts on the current one
+    pub fn tick(&mut self) {
+        let deadline = Instant::now() + TICK_BUDGET;
+        let mut alerts: Vec<String> = vec![];
+        let count = self.apps.len() as u32;
+        for (i, app) in self.apps.iter_mut().enumerate() {
+            // Each printer gets an equal share of what the previous ones left
+            let share = deadline.saturating_duration_since(Instant::now()) / (count - i as u32);
+            app.tick_until(Instant::now() + share);
+            let name = app.name.clone();
+            alerts.extend(app.alerts.drain(..).map(|a| format!("{}: {}", name, a)));
+        }
+        if !alerts.is_empty() {
+            self.current().notify(alerts.join(" | "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionState;
+    use crate::server::Server;
+
+    fn farm(hosts: &[&str]) -> Farm {
+        Farm::new(hosts.iter().map(|h| App {
+            // Already connected, so ticking does not try to connect
+            connection: ConnectionState::Connected,
+            ..App::new(Server::parse(h, None).unwrap())
+        }).collect())
+    }
+
+    #[test]
+    fn test_switching_wraps_around() {
+        let mut f = farm(&["a", "b", "c"]);
+        f.previous();
+        assert_eq!(f.current, 2);
+        f.next();
+        f.next();
+        assert_eq!(f.current().name, "b");
+    }
+
+    #[test]
+    fn test_opening_a_printer_from_the_overview() {
+        let mut f = farm(&["a", "b"]);
+        assert!(f.overview);
+        f.apps[1].current_tab = Tab::Console;
+        f.next();
+        f.open();
+        assert!(!f.overview);
+        assert_eq!(f.apps[1].current_tab, Tab::Main);
+        assert!(!farm(&["a"]).overview);
+    }
+
+    #[test]
+    fn test_background_alerts_reach_the_current_printer() {
+        let mut f = farm(&["a", "b"]);
+        f.apps[1].alerts.push("Klippy shutdown".to_string());
+        f.tick();
+        assert_eq!(f.apps[0].notice.as_ref().unwrap().message, "b: Klippy shutdown");
+        assert!(f.apps[1].alerts.is_empty());
+        assert!(f.apps[1].notice.is_none());
+    }
+
+    #[test]
+    fn test_every_printer_reads_its_messages() {
+        let mut f = farm(&["a", "b", "c"]);
+        let senders: Vec<_> = f.apps.iter_mut().map(|app| {
+            let (tx, rx) = flume::unbounded();
+            app.rx = Some(rx);
+            for i in 0..50 {
+                let notification = serde_json::json!({"jsonrpc": "2.0", "method": "notify_gcode_response", "params": [format!("// line {}", i)]});
+                tx.send(websocket::OwnedMessage::Text(notification.to_string())).unwrap();
+            }
+            tx
+        }).collect();
+        f.tick();
+        assert!(senders.iter().all(|tx| tx.is_empty()));
+    }
+
+    #[test]
+    fn test_changes_are_per_frame_on_every_printer() {
+        let mut f = farm(&["a", "b"]);
+        f.apps[1].printer.update(serde_json::json!({"gcode_move": {"speed_factor": 1.0}}));
+        f.end_frame();
+        f.apps[0].printer.update(serde_json::json!({"gcode_move": {"speed_factor": 1.2}}));
+        f.next();
+        assert!(!f.current().printer.objects.changed("gcode_move"));
+        f.previous();
+        assert!(f.current().printer.objects.changed("gcode_move"));
+        f.end_frame();
+        assert!(!f.current().printer.objects.changed("gcode_move"));
+    }
+}
@@ -1,12 +1,46 @@
-use crate::{app::{App, AppResult, Tab, MainTabWidget, InputMode}, printer::HeaterType};
+use crate::{bed_mesh::MeshPrompt, app::{App, AppResult, Tab, MainTabWidget, InputMode, PrintAction, ToolheadAction, EXTRUDE_LENGTHS, EXTRUDE_SPEEDS, JOG_STEPS, JOG_SPEEDS}, auth::LoginField, farm::Farm, printer::HeaterType};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use serde_json::json;
 
-/// Handles the key events and updates the state of [`App`].
-pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+/// Handles the key events and updates the state of [`Farm`].
+pub fn handle_key_events(key_event: KeyEvent, farm: &mut Farm) -> AppResult<()> {
+    if farm.overview {
+        return handle_overview_key_events(key_event, farm);
+    }
+    match key_event.code {
+        KeyCode::F(7) => farm.previous(),
+        KeyCode::F(8) => farm.next(),
+        KeyCode::F(9) => farm.overview = true,
+        _ => handle_printer_key_events(key_event, farm.current())?,
+    }
+    Ok(())
+}
+
+/// Handles the key events of the farm overview.
+fn handle_overview_key_events(key_event: KeyEvent, farm: &mut Farm) -> AppResult<()> {
+    match key_event.code {
+        KeyCode::Up | KeyCode::F(7) => farm.previous(),
+        KeyCode::Down | KeyCode::F(8) => farm.next(),
+        KeyCode::Enter => farm.open(),
+        KeyCode::Esc | KeyCode::F(9) => farm.overview = false,
+        KeyCode::F(2) => farm.current().quit(),
+        KeyCode::Char('c') if key_event.modifiers == KeyModifiers::CONTROL => farm.current().quit(),
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Handles the key events for the printer being shown.
+fn handle_printer_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    if app.login.is_some() {
+        return handle_login_key_events(key_event, app);
+    }
     match key_event.code {
         KeyCode::Esc => {
             match app.current_tab {
+                Tab::Main if app.print_action.is_some() => {
+                    app.print_action = None;
+                },
                 Tab::Main => {
                     match app.selected_widget {
                         MainTabWidget::Temperatures => {
@@ -18,10 +52,21 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                         },
                     }
                 },
-                Tab::Console => {
-                    if app.console_input.mode == InputMode::Editing {
-                        app.console_input.mode = InputMode::Normal;
-                    }
+                Tab::Console if app.console_input.mode == InputMode::Editing => {
+                    app.console_input.mode = InputMode::Normal;
+                },
+                Tab::Objects if app.inspector.searching => {
+                    app.inspector.searching = false;
+                },
+                Tab::Objects => {
+                    app.inspector.search.clear();
+                    app.inspector.state.select(Some(0));
+                },
+                Tab::BedMesh => {
+                    app.bed_mesh.prompt = None;
+                },
+                Tab::Toolhead => {
+                    app.toolhead_action = None;
                 },
                 _ => {},
             }
@@ -40,6 +85,12 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                 Tab::ConsoleHelp => Tab::Console,
                 Tab::Webcam => Tab::WebcamHelp,
                 Tab::WebcamHelp => Tab::Webcam,
+                Tab::Objects => Tab::ObjectsHelp,
+                Tab::ObjectsHelp => Tab::Objects,
+                Tab::BedMesh => Tab::BedMeshHelp,
+                Tab::BedMeshHelp => Tab::BedMesh,
+                Tab::Leveling => Tab::LevelingHelp,
+                Tab::LevelingHelp => Tab::Leveling,
             }
         }
         KeyCode::F(2) => {
@@ -72,8 +123,23 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                 _ => Tab::Webcam,
             }
         }
+        KeyCode::F(11) => {
+            app.current_tab = match app.current_tab {
+                Tab::Objects => Tab::Main,
+                _ => Tab::Objects,
+            }
+        }
+        KeyCode::F(12) => {
+            app.current_tab = match app.current_tab {
+                Tab::BedMesh => Tab::Main,
+                _ => Tab::BedMesh,
+            }
+        }
         KeyCode::F(10) => {
-            if app.printer.connected {
+            if !app.connection.is_open() {
+                // Retry now instead of waiting for the next attempt
+                app.reconnect();
+            } else if app.is_ready() {
                 app.emergency_stop();
             } else {
                 app.send_message("printer.gcode.script".to_string(), json!({"script": "FIRMWARE_RESTART"}));
@@ -92,13 +158,23 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                         },
                     }
                 },
-                Tab::Console => {
-                    if app.console_input.mode == InputMode::Normal {
-                        app.console_scroll= app.console_scroll.saturating_add(1);
-                        app.console_scroll_state = app
-                            .console_scroll_state
-                            .position(app.console_scroll as u16);
-                    }
+                Tab::Console if app.console_input.mode == InputMode::Normal => {
+                    app.console_scroll= app.console_scroll.saturating_add(1);
+                    app.console_scroll_state = app
+                        .console_scroll_state
+                        .position(app.console_scroll);
+                },
+                Tab::Objects => {
+                    let count = app.inspector.rows(&app.printer.objects).len();
+                    app.inspector.next(count);
+                },
+                Tab::Extruder => {
+                    app.extrude_speed = app.extrude_speed.saturating_sub(1);
+                },
+                Tab::Toolhead => app.jog(1, -1.0),
+                Tab::BedMesh => {
+                    let mesh = app.printer.bed_mesh();
+                    app.bed_mesh.move_cursor(0, -1, app.bed_mesh.matrix(&mesh));
                 },
                 _ => {},
             }
@@ -116,17 +192,44 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                         },
                     }
                 },
-                Tab::Console => {
-                    if app.console_input.mode == InputMode::Normal {
-                        app.console_scroll = app.console_scroll.saturating_sub(1);
-                        app.console_scroll_state = app
-                            .console_scroll_state
-                            .position(app.console_scroll as u16);
-                    }
+                Tab::Console if app.console_input.mode == InputMode::Normal => {
+                    app.console_scroll = app.console_scroll.saturating_sub(1);
+                    app.console_scroll_state = app
+                        .console_scroll_state
+                        .position(app.console_scroll);
+                },
+                Tab::Objects => app.inspector.previous(),
+                Tab::Extruder => {
+                    app.extrude_speed = (app.extrude_speed + 1).min(EXTRUDE_SPEEDS.len() - 1);
+                },
+                Tab::Toolhead => app.jog(1, 1.0),
+                Tab::BedMesh => {
+                    let mesh = app.printer.bed_mesh();
+                    app.bed_mesh.move_cursor(0, 1, app.bed_mesh.matrix(&mesh));
                 },
                 _ => {},
             }
         },
+        KeyCode::Left | KeyCode::Right if app.current_tab == Tab::Objects => {
+            app.inspector.toggle(&app.printer.objects);
+        },
+        KeyCode::Left if app.current_tab == Tab::Extruder => {
+            app.extrude_length = app.extrude_length.saturating_sub(1);
+        },
+        KeyCode::Right if app.current_tab == Tab::Extruder => {
+            app.extrude_length = (app.extrude_length + 1).min(EXTRUDE_LENGTHS.len() - 1);
+        },
+        KeyCode::Left if app.current_tab == Tab::Toolhead => app.jog(0, -1.0),
+        KeyCode::Right if app.current_tab == Tab::Toolhead => app.jog(0, 1.0),
+        KeyCode::PageUp if app.current_tab == Tab::Toolhead => app.jog(2, 1.0),
+        KeyCode::PageDown if app.current_tab == Tab::Toolhead => app.jog(2, -1.0),
+        KeyCode::Left | KeyCode::Right if app.current_tab == Tab::BedMesh => {
+            let mesh = app.printer.bed_mesh();
+            let dx = if key_event.code == KeyCode::Left { -1 } else { 1 };
+            app.bed_mesh.move_cursor(dx, 0, app.bed_mesh.matrix(&mesh));
+        },
+        KeyCode::PageUp if app.current_tab == Tab::BedMesh => app.bed_mesh.previous_profile(),
+        KeyCode::PageDown if app.current_tab == Tab::BedMesh => app.bed_mesh.next_profile(&app.printer.bed_mesh()),
         KeyCode::Tab => {
             match app.current_tab {
                 Tab::Main => {
@@ -160,6 +263,7 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
         },
         KeyCode::Enter => {
             match app.current_tab {
+                Tab::Main if app.print_action.is_some() => app.confirm_print_action(),
                 Tab::Main => {
                     match app.selected_widget {
                         MainTabWidget::Temperatures => {
@@ -201,20 +305,24 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                                 let will_print_file = app.printer.will_print_file.clone();
                                 if let Some(file) = will_print_file {
                                     app.send_message("printer.print.start".to_string(), json!({"filename": file.filename}));
-                                    app.send_message("server.files.metadata".to_string(), json!({"filename": file.filename}));
+                                    app.fetch_file_metadata(file.filename.as_str());
                                     app.printer.will_print_file = None;
                                 }
                             }
                         },
                     }
                 },
-                Tab::Console => {
-                    if app.console_input.mode == InputMode::Editing {
-                        app.send_message("printer.gcode.script".to_string(), json!({"script": app.console_input.value}));
-                        app.console_input.value = "".to_string();
-                        app.console_input.cursor_position = 0;
-                    }
+                Tab::Console if app.console_input.mode == InputMode::Editing => {
+                    app.send_message("printer.gcode.script".to_string(), json!({"script": app.console_input.value}));
+                    app.console_input.value = "".to_string();
+                    app.console_input.cursor_position = 0;
                 },
+                Tab::Objects if app.inspector.searching => {
+                    app.inspector.searching = false;
+                },
+                Tab::Objects => app.inspector.toggle(&app.printer.objects),
+                Tab::BedMesh => app.confirm_mesh_prompt(),
+                Tab::Toolhead => app.confirm_toolhead_action(),
                 _ => {},
             }
             
@@ -235,49 +343,88 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                             app.console_scroll= app.console_scroll.saturating_add(1);
                             app.console_scroll_state = app
                                 .console_scroll_state
-                                .position(app.console_scroll as u16);
+                                .position(app.console_scroll);
                         } else if c == 'k' {
                             app.console_scroll= app.console_scroll.saturating_sub(1);
                             app.console_scroll_state = app
                                 .console_scroll_state
-                                .position(app.console_scroll as u16);
+                                .position(app.console_scroll);
                         }
                     }
                 },
-                Tab::Main => {
-                    match app.selected_widget {
-                        MainTabWidget::Temperatures => {
-                            if let Some(_) = &app.selected_heater {
-                                app.temperature_input.value.push(c);
-                                let cursor_moved_right = app.temperature_input.cursor_position.saturating_add(1);
-                                app.temperature_input.cursor_position = cursor_moved_right.clamp(0, 1 + app.temperature_input.value.len() as u16);
-                                
-                            }
-                        },
+                Tab::Objects if app.inspector.searching => {
+                    app.inspector.search.push(c);
+                    app.inspector.state.select(Some(0));
+                },
+                Tab::Objects if c == '/' => {
+                    app.inspector.searching = true;
+                },
+                Tab::BedMesh if app.bed_mesh.prompt.is_some() => {
+                    if let Some(MeshPrompt::Save(name)) = &mut app.bed_mesh.prompt {
+                        name.push(c);
+                    }
+                },
+                Tab::BedMesh if key_event.modifiers != KeyModifiers::CONTROL => {
+                    match c {
+                        'c' | 'C' => app.calibrate_mesh(),
+                        'x' | 'X' => app.clear_mesh(),
+                        'l' | 'L' => app.load_mesh_profile(),
+                        's' | 'S' => app.request_mesh_save(),
+                        'd' | 'D' => app.request_mesh_removal(),
+                        'i' | 'I' => app.bed_mesh.interpolated = !app.bed_mesh.interpolated,
+                        't' | 'T' => app.current_tab = Tab::Leveling,
+                        _ => {},
+                    }
+                },
+                Tab::Leveling if key_event.modifiers != KeyModifiers::CONTROL => {
+                    match c {
+                        'c' | 'C' => app.calculate_screws_tilt(),
+                        'q' | 'Q' => app.level_gantry(),
+                        _ => {},
+                    }
+                },
+                Tab::Main if app.selected_widget == MainTabWidget::Temperatures && app.selected_heater.is_some() => {
+                    app.temperature_input.value.push(c);
+                    let cursor_moved_right = app.temperature_input.cursor_position.saturating_add(1);
+                    app.temperature_input.cursor_position = cursor_moved_right.clamp(0, 1 + app.temperature_input.value.len() as u16);
+                },
+                Tab::Main if key_event.modifiers != KeyModifiers::CONTROL => {
+                    match c {
+                        'p' | 'P' => app.request_print_action(PrintAction::Pause),
+                        'r' | 'R' => app.request_print_action(PrintAction::Resume),
+                        'c' | 'C' => app.request_print_action(PrintAction::Cancel),
+                        _ if !app.printer.is_printing() => {},
+                        '[' => app.adjust_speed(-5.0),
+                        ']' => app.adjust_speed(5.0),
+                        '{' => app.adjust_flow(-1.0),
+                        '}' => app.adjust_flow(1.0),
+                        '-' => app.babystep(-0.01),
+                        '+' | '=' => app.babystep(0.01),
+                        'o' | 'O' => app.save_z_offset(),
+                        _ => {},
+                    }
+                },
+                Tab::Extruder if key_event.modifiers != KeyModifiers::CONTROL => {
+                    match c {
+                        'e' | 'E' => app.extrude(false),
+                        'r' | 'R' => app.extrude(true),
+                        'l' | 'L' => app.load_filament(false),
+                        'u' | 'U' => app.load_filament(true),
                         _ => {},
                     }
                 },
                 Tab::Toolhead => {
-                    let msg = match c {
-                        'x' | 'X' => {
-                            "G28 X"
-                        },
-                        'y' | 'Y' => {
-                            "G28 Y"
-                        },
-                        'z' | 'Z' => {
-                            "G28 Z"
-                        },
-                        'a' | 'A' => {
-                            "G28"
-                        },
-                        'q' | 'Q' => {
-                            "QUAD_GANTRY_LEVEL"
-                        },
-                        _ => {""}
-                    };
-                    if msg.len() > 0 {
-                        app.send_message("printer.gcode.script".to_string(), json!({"script": msg}));
+                    match c {
+                        'x' | 'X' => app.home("X"),
+                        'y' | 'Y' => app.home("Y"),
+                        'z' | 'Z' => app.home("Z"),
+                        'a' | 'A' => app.home(""),
+                        'q' | 'Q' => app.level_gantry(),
+                        'm' | 'M' => app.request_toolhead_action(ToolheadAction::MotorsOff),
+                        'p' | 'P' => app.request_toolhead_action(ToolheadAction::ResetPosition),
+                        's' | 'S' => app.jog_step = (app.jog_step + 1) % JOG_STEPS.len(),
+                        'f' | 'F' => app.jog_speed = (app.jog_speed + 1) % JOG_SPEEDS.len(),
+                        _ => {},
                     }
                 }
                 _ => {
@@ -300,21 +447,28 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                         app.console_input.cursor_position = cursor_moved_left.clamp(0, app.console_input.value.len() as u16);
                     }
                 },
-                Tab::Main => {
-                    if let Some(_) = app.selected_heater {
-                        let is_not_cursor_leftmost = app.temperature_input.cursor_position != 1;
-                        if is_not_cursor_leftmost {
-                            let current_index = app.temperature_input.cursor_position as usize;
-                            let from_left_to_current_index = current_index -2;
-                            let before_char_to_delete = app.temperature_input.value.chars().take(from_left_to_current_index);
-                            let after_char_to_delete = app.temperature_input.value.chars().skip(current_index);
-                            app.temperature_input.value = before_char_to_delete.chain(after_char_to_delete).collect();
-                            let cursor_moved_left = app.temperature_input.cursor_position.saturating_sub(1);
+                Tab::Main if app.selected_heater.is_some() => {
+                    let is_not_cursor_leftmost = app.temperature_input.cursor_position != 1;
+                    if is_not_cursor_leftmost {
+                        let current_index = app.temperature_input.cursor_position as usize;
+                        let from_left_to_current_index = current_index -2;
+                        let before_char_to_delete = app.temperature_input.value.chars().take(from_left_to_current_index);
+                        let after_char_to_delete = app.temperature_input.value.chars().skip(current_index);
+                        app.temperature_input.value = before_char_to_delete.chain(after_char_to_delete).collect();
+                        let cursor_moved_left = app.temperature_input.cursor_position.saturating_sub(1);
 
-                            app.temperature_input.cursor_position = cursor_moved_left.clamp(1, 1 + app.temperature_input.value.len() as u16);
-                        }
+                        app.temperature_input.cursor_position = cursor_moved_left.clamp(1, 1 + app.temperature_input.value.len() as u16);
                     }
-                }
+                },
+                Tab::Objects if app.inspector.searching => {
+                    app.inspector.search.pop();
+                    app.inspector.state.select(Some(0));
+                },
+                Tab::BedMesh => {
+                    if let Some(MeshPrompt::Save(name)) = &mut app.bed_mesh.prompt {
+                        name.pop();
+                    }
+                },
                 _ => {},
             }
         },
@@ -323,3 +477,40 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
     }
     Ok(())
 }
+
+/// Handles the key events while the login dialog is open.
+fn handle_login_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    let form = match &mut app.login {
+        Some(form) => form,
+        None => return Ok(()),
+    };
+    match key_event.code {
+        KeyCode::F(2) => app.quit(),
+        KeyCode::Char('c') if key_event.modifiers == KeyModifiers::CONTROL => app.quit(),
+        KeyCode::Tab => form.next_field(),
+        KeyCode::Enter => {
+            if form.field == LoginField::Username {
+                form.next_field();
+            } else {
+                app.login();
+            }
+        },
+        KeyCode::Esc => {
+            let input = form.input();
+            input.value = "".to_string();
+            input.cursor_position = 0;
+        },
+        KeyCode::Char(c) => {
+            let input = form.input();
+            input.value.push(c);
+            input.cursor_position = input.value.chars().count() as u16;
+        },
+        KeyCode::Backspace => {
+            let input = form.input();
+            input.value.pop();
+            input.cursor_position = input.value.chars().count() as u16;
+        },
+        _ => {},
+    }
+    Ok(())
+}
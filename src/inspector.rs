@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use tui::widgets::ListState;
+
+use crate::klipper::ObjectStore;
+
+/// How long a changed value stays highlighted
+pub const HIGHLIGHT_DURATION: Duration = Duration::from_secs(3);
+
+/// A line of the object tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum InspectorRow {
+    Object {
+        name: String,
+        expanded: bool,
+    },
+    Field {
+        object: String,
+        field: String,
+        /// Value as JSON
+        value: String,
+        updated_at: Option<Instant>,
+    },
+}
+
+/// State of the object inspector tab
+#[derive(Debug, Clone, Default)]
+pub struct Inspector {
+    pub state: ListState,
+    /// Objects whose fields are shown
+    pub expanded: HashSet<String>,
+    pub search: String,
+    /// Set while typing in the search field
+    pub searching: bool,
+}
+
+impl Inspector {
+    /// Lines of the tree, for the objects and fields matching the search.
+    ///
+    /// Objects with a matching name are shown as usual, others only when some of their
+    /// fields match, in which case these fields are shown.
+    pub fn rows(&self, store: &ObjectStore) -> Vec<InspectorRow> {
+        let search = self.search.to_lowercase();
+        let mut rows = vec![];
+        for name in store.names() {
+            let fields = match store.get(name).and_then(|o| o.as_object()) {
+                Some(fields) => fields,
+                None => continue,
+            };
+            let name_matches = name.to_lowercase().contains(&search);
+            let mut matching: Vec<&String> = fields.keys()
+                .filter(|field| name_matches || field.to_lowercase().contains(&search))
+                .collect();
+            if !name_matches && matching.is_empty() {
+                continue;
+            }
+            let expanded = self.expanded.contains(name) || !name_matches;
+            rows.push(InspectorRow::Object { name: name.clone(), expanded });
+            if expanded {
+                matching.sort();
+                for field in matching {
+                    rows.push(InspectorRow::Field {
+                        object: name.clone(),
+                        field: field.clone(),
+                        value: fields[field].to_string(),
+                        updated_at: store.updated_at(name, field),
+                    });
+                }
+            }
+        }
+        rows
+    }
+
+    pub fn next(&mut self, count: usize) {
+        let i = self.state.selected().map_or(0, |i| i + 1);
+        self.state.select(if count == 0 { None } else { Some(i.min(count - 1)) });
+    }
+
+    pub fn previous(&mut self) {
+        let i = self.state.selected().map_or(0, |i| i.saturating_sub(1));
+        self.state.select(Some(i));
+    }
+
+    /// Shows or hides the fields of the selected object, or of the object of the selected field
+    pub fn toggle(&mut self, store: &ObjectStore) {
+        let rows = self.rows(store);
+        let selected = match self.state.selected().and_then(|i| rows.get(i)) {
+            Some(row) => row,
+            None => return,
+        };
+        let name = match selected {
+            InspectorRow::Object { name, .. } => name,
+            InspectorRow::Field { object, .. } => object,
+        };
+        if !self.expanded.remove(name) {
+            self.expanded.insert(name.clone());
+        }
+        // Keep the object selected when its fields are hidden
+        if let Some(i) = self.rows(store).iter().position(|r| matches!(r, InspectorRow::Object { name: n, .. } if n == name)) {
+            if matches!(selected, InspectorRow::Field { .. }) {
+                self.state.select(Some(i));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn store() -> ObjectStore {
+        let mut store = ObjectStore::default();
+        store.merge(&json!({
+            "idle_timeout": {"state": "Idle", "printing_time": 0.0},
+            "gcode_move": {"speed_factor": 1.0, "extrude_factor": 1.0},
+            "mcu": {"mcu_version": "v0.12.0"},
+        }));
+        store
+    }
+
+    fn names(rows: &[InspectorRow]) -> Vec<String> {
+        rows.iter().map(|r| match r {
+            InspectorRow::Object { name, .. } => name.clone(),
+            InspectorRow::Field { field, value, .. } => format!("  {}={}", field, value),
+        }).collect()
+    }
+
+    #[test]
+    fn test_objects_expand_and_collapse() {
+        let store = store();
+        let mut inspector = Inspector::default();
+        assert_eq!(names(&inspector.rows(&store)), vec!["gcode_move", "idle_timeout", "mcu"]);
+
+        inspector.next(3);
+        inspector.next(3);
+        inspector.toggle(&store);
+        assert_eq!(names(&inspector.rows(&store)), vec!["gcode_move", "idle_timeout", "  printing_time=0.0", "  state=\"Idle\"", "mcu"]);
+
+        // Collapsing from a field selects its object
+        inspector.next(5);
+        inspector.toggle(&store);
+        assert_eq!(inspector.state.selected(), Some(1));
+        assert_eq!(inspector.rows(&store).len(), 3);
+    }
+
+    #[test]
+    fn test_search_matches_objects_and_fields() {
+        let store = store();
+        let mut inspector = Inspector { search: "FACTOR".to_string(), ..Inspector::default() };
+        assert_eq!(names(&inspector.rows(&store)), vec!["gcode_move", "  extrude_factor=1.0", "  speed_factor=1.0"]);
+
+        inspector.search = "mc".to_string();
+        assert_eq!(names(&inspector.rows(&store)), vec!["mcu"]);
+    }
+}
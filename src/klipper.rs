@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+/// Reads a field, or `None` when it does not have the expected type
+pub fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+/// Reads a field, or its default value when it does not have the expected type
+pub fn or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    Ok(lenient(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Webhooks {
+    #[serde(default, deserialize_with = "lenient")]
+    pub state: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub state_message: Option<String>,
+}
+
+/// Names of the heaters and temperature sensors
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Heaters {
+    #[serde(default, deserialize_with = "lenient")]
+    pub available_heaters: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub available_sensors: Option<Vec<String>>,
+}
+
+/// A heater (`extruder`, `heater_bed`, `heater_generic x`) or a `temperature_fan`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Heater {
+    #[serde(default, deserialize_with = "lenient")]
+    pub temperature: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub target: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub power: Option<f64>,
+    /// Fan speed of a `temperature_fan`
+    #[serde(default, deserialize_with = "lenient")]
+    pub speed: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PrintStats {
+    #[serde(default, deserialize_with = "lenient")]
+    pub state: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub filename: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub total_duration: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub print_duration: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub filament_used: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub info: Option<PrintInfo>,
+}
+
+/// Set by the `SET_PRINT_STATS_INFO` gcode
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PrintInfo {
+    #[serde(default, deserialize_with = "lenient")]
+    pub total_layer: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub current_layer: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct VirtualSdcard {
+    #[serde(default, deserialize_with = "lenient")]
+    pub progress: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct MotionReport {
+    /// X, Y, Z and E
+    #[serde(default, deserialize_with = "lenient")]
+    pub live_position: Option<Vec<f64>>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub live_velocity: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub live_extruder_velocity: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Toolhead {
+    /// Like `xyz`, empty when no axis is homed
+    #[serde(default, deserialize_with = "lenient")]
+    pub homed_axes: Option<String>,
+    /// X, Y, Z and E
+    #[serde(default, deserialize_with = "lenient")]
+    pub axis_minimum: Option<Vec<f64>>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub axis_maximum: Option<Vec<f64>>,
+}
+
+/// The `extruder` object, on top of its [`Heater`] fields
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Extruder {
+    #[serde(default, deserialize_with = "lenient")]
+    pub temperature: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub target: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub pressure_advance: Option<f64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub smooth_time: Option<f64>,
+    /// Whether the extruder is above `min_extrude_temp`
+    #[serde(default, deserialize_with = "lenient")]
+    pub can_extrude: Option<bool>,
+}
+
+/// Factors and offsets applied to the gcode being run
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct GcodeMove {
+    /// Set by `M220`, 1.0 for 100%
+    #[serde(default, deserialize_with = "lenient")]
+    pub speed_factor: Option<f64>,
+    /// Set by `M221`, 1.0 for 100%
+    #[serde(default, deserialize_with = "lenient")]
+    pub extrude_factor: Option<f64>,
+    /// X, Y, Z and E offsets set by `SET_GCODE_OFFSET`
+    #[serde(default, deserialize_with = "lenient")]
+    pub homing_origin: Option<Vec<f64>>,
+    /// Position in gcode coordinates, as changed by `G92`
+    #[serde(default, deserialize_with = "lenient")]
+    pub gcode_position: Option<Vec<f64>>,
+}
+
+impl GcodeMove {
+    pub fn z_offset(&self) -> f64 {
+        self.homing_origin.as_ref().and_then(|o| o.get(2)).copied().unwrap_or(0.0)
+    }
+}
+
+/// The `bed_mesh` object
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct BedMesh {
+    /// Profile of the current mesh, empty when no mesh is loaded
+    #[serde(default, deserialize_with = "lenient")]
+    pub profile_name: Option<String>,
+    /// X and Y of the first probe point
+    #[serde(default, deserialize_with = "lenient")]
+    pub mesh_min: Option<Vec<f64>>,
+    /// X and Y of the last probe point
+    #[serde(default, deserialize_with = "lenient")]
+    pub mesh_max: Option<Vec<f64>>,
+    /// Z of each probe point, one row per Y, from the front of the bed
+    #[serde(default, deserialize_with = "lenient")]
+    pub probed_matrix: Option<Vec<Vec<f64>>>,
+    /// The probed points once interpolated
+    #[serde(default, deserialize_with = "lenient")]
+    pub mesh_matrix: Option<Vec<Vec<f64>>>,
+    /// Saved profiles by name, with their `points` and `mesh_params`
+    #[serde(default, deserialize_with = "lenient")]
+    pub profiles: Option<BTreeMap<String, Value>>,
+}
+
+/// The `quad_gantry_level` or `z_tilt` object
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct GantryLevel {
+    #[serde(default, deserialize_with = "lenient")]
+    pub applied: Option<bool>,
+}
+
+/// Result of `SCREWS_TILT_CALCULATE` for one screw
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ScrewAdjustment {
+    /// Name from the config, like `front left screw`
+    #[serde(default, deserialize_with = "lenient")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub z: Option<f64>,
+    /// `CW` or `CCW`
+    #[serde(default, deserialize_with = "lenient")]
+    pub sign: Option<String>,
+    /// Full turns and minutes, like `01:15`
+    #[serde(default, deserialize_with = "lenient")]
+    pub adjust: Option<String>,
+    /// The screw the others are adjusted to
+    #[serde(default, deserialize_with = "lenient")]
+    pub is_base: Option<bool>,
+}
+
+impl ScrewAdjustment {
+    /// Direction and amount to turn the screw, like `CW 00:15`
+    pub fn turn(&self) -> String {
+        let adjust = self.adjust.clone().unwrap_or_default();
+        match &self.sign {
+            Some(sign) if !adjust.contains(sign.as_str()) => format!("{} {}", sign, adjust),
+            _ => adjust,
+        }
+    }
+
+    /// Minutes to turn the screw, an hour being a full turn
+    pub fn minutes(&self) -> Option<u32> {
+        let adjust = self.adjust.as_ref()?;
+        let time = adjust.split_whitespace().last()?;
+        let (turns, minutes) = time.split_once(':')?;
+        Some(turns.parse::<u32>().ok()? * 60 + minutes.parse::<u32>().ok()?)
+    }
+}
+
+/// The `screws_tilt_adjust` object
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ScrewsTiltAdjust {
+    /// Set when a screw is further off than `MAX_DEVIATION`
+    #[serde(default, deserialize_with = "lenient")]
+    pub error: Option<bool>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub max_deviation: Option<f64>,
+    /// By screw, like `screw1`, empty until the first `SCREWS_TILT_CALCULATE`
+    #[serde(default, deserialize_with = "lenient")]
+    pub results: Option<BTreeMap<String, ScrewAdjustment>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Fan {
+    #[serde(default, deserialize_with = "lenient")]
+    pub speed: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SystemStats {
+    #[serde(default, deserialize_with = "lenient")]
+    pub sysload: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct StepperEnable {
+    /// Whether each stepper is enabled, by stepper name
+    #[serde(default, deserialize_with = "lenient")]
+    pub steppers: Option<BTreeMap<String, bool>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct FilamentSensor {
+    #[serde(default, deserialize_with = "lenient")]
+    pub filament_detected: Option<bool>,
+}
+
+/// Objects of a status update that krui knows about, as found in `printer.objects.query`
+/// results and `notify_status_update` notifications.
+///
+/// Updates only contain the fields that changed, so every field is optional. A field
+/// with an unexpected type is read as missing, and an object that is not a JSON object
+/// is logged and skipped, so that a malformed payload never takes krui down.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub webhooks: Option<Webhooks>,
+    pub heaters: Option<Heaters>,
+    pub print_stats: Option<PrintStats>,
+    pub virtual_sdcard: Option<VirtualSdcard>,
+    pub motion_report: Option<MotionReport>,
+    pub toolhead: Option<Toolhead>,
+    pub quad_gantry_level: Option<GantryLevel>,
+    pub z_tilt: Option<GantryLevel>,
+    pub fan: Option<Fan>,
+    pub system_stats: Option<SystemStats>,
+    pub stepper_enable: Option<StepperEnable>,
+    /// Objects whose name is only known at runtime: heaters, sensors, filament sensors
+    pub others: Map<String, Value>,
+}
+
+impl Status {
+    /// Reads the objects of a status update, skipping the ones that can't be read
+    pub fn parse(data: &Value) -> Status {
+        let mut status = Status::default();
+        let objects = match data.as_object() {
+            Some(o) => o,
+            None => {
+                log::warn!("status update is not an object: {}", data);
+                return status;
+            },
+        };
+        for (name, value) in objects {
+            match name.as_str() {
+                "webhooks" => status.webhooks = object(name, value),
+                "heaters" => status.heaters = object(name, value),
+                "print_stats" => status.print_stats = object(name, value),
+                "virtual_sdcard" => status.virtual_sdcard = object(name, value),
+                "motion_report" => status.motion_report = object(name, value),
+                "toolhead" => status.toolhead = object(name, value),
+                "quad_gantry_level" => status.quad_gantry_level = object(name, value),
+                "z_tilt" => status.z_tilt = object(name, value),
+                "fan" => status.fan = object(name, value),
+                "system_stats" => status.system_stats = object(name, value),
+                "stepper_enable" => status.stepper_enable = object(name, value),
+                _ => {
+                    status.others.insert(name.clone(), value.clone());
+                },
+            }
+        }
+        status
+    }
+
+    /// Heater or temperature fan called `name`, if it is part of the update
+    pub fn heater(&self, name: &str) -> Option<Heater> {
+        self.others.get(name).and_then(|v| object(name, v))
+    }
+
+    /// Filament sensors of the update
+    pub fn filament_sensors(&self) -> impl Iterator<Item = FilamentSensor> + '_ {
+        self.others.iter()
+            .filter(|(name, _)| name.starts_with("filament_switch_sensor") || name.starts_with("filament_motion_sensor"))
+            .filter_map(|(name, v)| object(name, v))
+    }
+}
+
+/// State of every subscribed Klipper object, as JSON.
+///
+/// Updates are merged field by field, as Moonraker only sends the fields that changed.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectStore {
+    objects: HashMap<String, Value>,
+    /// Objects changed since the last frame
+    changed: HashSet<String>,
+    /// When each field last changed, by object, not counting the first value
+    updated_at: HashMap<String, HashMap<String, Instant>>,
+}
+
+impl ObjectStore {
+    /// Merges a status update, or the result of an objects query
+    pub fn merge(&mut self, data: &Value) {
+        let Some(objects) = data.as_object() else {
+            return;
+        };
+        let now = Instant::now();
+        for (name, value) in objects {
+            let Some(fields) = value.as_object() else {
+                log::warn!("skipping malformed {} {}", name, value);
+                continue;
+            };
+            let known = self.objects.get(name).is_some_and(|o| o.is_object());
+            if !known {
+                self.objects.insert(name.clone(), Value::Object(Map::new()));
+                self.changed.insert(name.clone());
+            }
+            let Some(object) = self.objects.get_mut(name).and_then(|o| o.as_object_mut()) else {
+                continue;
+            };
+            for (field, value) in fields {
+                if object.get(field) != Some(value) {
+                    object.insert(field.clone(), value.clone());
+                    self.changed.insert(name.clone());
+                    if known {
+                        self.updated_at.entry(name.clone()).or_default().insert(field.clone(), now);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Current state of the object called `name`
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.objects.get(name)
+    }
+
+    /// Current value of a field of an object
+    pub fn field(&self, name: &str, field: &str) -> Option<&Value> {
+        self.objects.get(name).and_then(|o| o.get(field))
+    }
+
+    /// Names of the known objects, sorted
+    pub fn names(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.objects.keys().collect();
+        names.sort();
+        names
+    }
+
+    /// The object called `name`, read as `T`
+    pub fn parse<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        self.objects.get(name).and_then(|v| object(name, v))
+    }
+
+    /// Whether the object called `name` changed since the last frame
+    pub fn changed(&self, name: &str) -> bool {
+        self.changed.contains(name)
+    }
+
+    /// When a field of an object last changed, if it did since the object was first seen
+    pub fn updated_at(&self, name: &str, field: &str) -> Option<Instant> {
+        self.updated_at.get(name).and_then(|f| f.get(field)).copied()
+    }
+
+    /// Called once a frame is drawn, so that changes are only reported once
+    pub fn end_frame(&mut self) {
+        self.changed.clear();
+    }
+
+    /// Forgets all objects, when the list of objects changes
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.changed.clear();
+        self.updated_at.clear();
+    }
+}
+
+/// Reads the object called `name`
+fn object<T: DeserializeOwned>(name: &str, value: &Value) -> Option<T> {
+    match T::deserialize(value) {
+        Ok(o) => Some(o),
+        Err(e) => {
+            log::warn!("skipping malformed {} {}: {}", name, value, e);
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_wrong_types_are_skipped() {
+        let status = Status::parse(&json!({
+            "print_stats": {"state": "printing", "print_duration": "soon", "info": {"total_layer": null, "current_layer": 3}},
+            "quad_gantry_level": {"applied": 1},
+            "toolhead": "xyz",
+            "extruder": {"temperature": 210.5, "target": null},
+        }));
+        let print_stats = status.print_stats.clone().unwrap();
+        assert_eq!(print_stats.state.as_deref(), Some("printing"));
+        assert_eq!(print_stats.print_duration, None);
+        assert_eq!(print_stats.info.unwrap().current_layer, Some(3));
+        assert_eq!(status.quad_gantry_level.as_ref().unwrap().applied, None);
+        assert_eq!(status.toolhead, None);
+        assert_eq!(status.heater("extruder"), Some(Heater { temperature: Some(210.5), ..Heater::default() }));
+        assert_eq!(Status::parse(&json!([1, 2])), Status::default());
+    }
+
+    #[test]
+    fn test_screw_adjustments() {
+        let screws: ScrewsTiltAdjust = serde_json::from_value(json!({
+            "error": false,
+            "max_deviation": null,
+            "results": {
+                "screw1": {"z": 2.4875, "sign": "CW", "adjust": "00:00", "is_base": true},
+                "screw2": {"z": 2.36, "sign": "CW", "adjust": "01:15", "is_base": false},
+                "screw3": {"z": 2.5, "adjust": "CCW 00:05"},
+            },
+        })).unwrap();
+        let results = screws.results.unwrap();
+        assert_eq!(results["screw2"].turn(), "CW 01:15");
+        assert_eq!(results["screw2"].minutes(), Some(75));
+        assert_eq!(results["screw3"].turn(), "CCW 00:05");
+        assert_eq!(results["screw3"].minutes(), Some(5));
+        assert_eq!(ScrewAdjustment::default().minutes(), None);
+    }
+
+    #[test]
+    fn test_object_store_merges_partial_updates() {
+        let mut store = ObjectStore::default();
+        store.merge(&json!({"gcode_move": {"speed_factor": 1.0, "homing_origin": [0.0, 0.0, 0.0, 0.0]}, "idle_timeout": {"state": "Idle"}}));
+        assert!(store.changed("gcode_move") && store.changed("idle_timeout"));
+        assert_eq!(store.updated_at("gcode_move", "speed_factor"), None);
+        store.end_frame();
+
+        store.merge(&json!({"gcode_move": {"speed_factor": 1.5}, "idle_timeout": {"state": "Idle"}, "mcu": "garbage"}));
+        assert_eq!(store.get("gcode_move"), Some(&json!({"speed_factor": 1.5, "homing_origin": [0.0, 0.0, 0.0, 0.0]})));
+        assert!(store.changed("gcode_move"));
+        assert!(store.updated_at("gcode_move", "speed_factor").is_some());
+        assert_eq!(store.updated_at("gcode_move", "homing_origin"), None);
+        // Same value again is not a change
+        assert!(!store.changed("idle_timeout"));
+        assert_eq!(store.get("mcu"), None);
+        assert_eq!(store.field("idle_timeout", "state"), Some(&json!("Idle")));
+        assert_eq!(store.names(), vec!["gcode_move", "idle_timeout"]);
+    }
+}
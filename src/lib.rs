@@ -13,5 +13,43 @@ pub mod tui;
 /// Event handler.
 pub mod handler;
 pub mod printer;
+/// Typed Klipper status objects.
+pub mod klipper;
 pub mod button;
 pub mod markdown;
+
+/// Moonraker server address and authentication.
+pub mod server;
+
+/// Moonraker user login.
+pub mod auth;
+
+/// Connection state and reconnection delays.
+pub mod connection;
+
+/// Typed JSON-RPC client for Moonraker.
+pub mod moonraker;
+
+/// Config file and command line arguments.
+pub mod config;
+
+/// Commands run without the user interface.
+pub mod cli;
+
+/// Several printers, one of them shown at a time.
+pub mod farm;
+
+/// Recording and replay of websocket sessions.
+pub mod record;
+
+/// Simulated printer for `--demo`.
+pub mod demo;
+
+/// Websocket and unix socket connections to Moonraker.
+pub mod transport;
+
+/// Live view of the printer objects.
+pub mod inspector;
+
+/// Bed mesh tab state and statistics.
+pub mod bed_mesh;
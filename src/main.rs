@@ -1,12 +1,16 @@
 
 
 use krui::app::{App, AppResult};
+use krui::cli::{self, Command};
 use krui::event::{Event, EventHandler};
+use krui::farm::Farm;
 use krui::handler::handle_key_events;
+use krui::config::{self, Args, Config};
+use krui::record::{self, Recorder};
 use krui::tui::Tui;
 
 
-use std::{io, env};
+use std::{io, env, fs, process};
 
 use tui::backend::CrosstermBackend;
 use tui::Terminal;
@@ -14,46 +18,149 @@ use tui::Terminal;
 use log::LevelFilter;
 use log4rs::append::file::FileAppender;
 use log4rs::encode::pattern::PatternEncoder;
-use log4rs::config::{Appender, Config, Root};
+use log4rs::config::{Appender, Config as LogConfig, Root};
+
+const USAGE: &str = "Usage: krui [<printer>|<server url>]... [options]
+       krui <printer> <command> [options]
+       krui --replay <file> [--speed <factor>]
+       krui --demo
+
+Printers are the names of profiles from the config file, or Moonraker addresses.
+Without printers, krui connects to every printer of the config file.
+
+Commands, run without the user interface:
+    status [--json]                 Show the printer state
+    print <file>                    Start printing a file
+    gcode <script>                  Run gcode and show the response
+    pause, resume, cancel           Control the current print
+    wait [--until <state>] [--timeout <seconds>]
+                                    Wait for the print to reach a state, complete by default
+
+Exit codes: 0 on success, 1 when the printer refused the command or the print failed,
+2 for usage errors, 3 when the printer can't be reached, 4 on timeout.
+
+Options:
+    --api-key <key>     Moonraker API key
+    --user <username>   User to log in as
+    --config <file>     Config file to use instead of the default one
+    --tick-rate <ms>    Time between two screen refreshes
+    --log <file>        Log file
+    --cache-dir <dir>   Where thumbnails and webcam snapshots are saved
+    --record <file>     Record the websocket session of the printer to a file
+    --replay <file>     Play a recorded session instead of connecting to printers
+    --speed <factor>    Replay speed, 2 plays twice as fast as recorded
+    --demo              Show a simulated printer";
 
 fn main() -> AppResult<()> {
-    let args: Vec<String> = env::args().collect();
-    // Create an application.
-    if args.len() < 2 {
-        println!("Usage: krui <server url>");
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(cli::EXIT_USAGE);
+        },
+    };
+    let command = match Command::parse(&args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(cli::EXIT_USAGE);
+        },
+    };
+    let config_path = args.config.clone().unwrap_or_else(config::config_path);
+    let mut config = Config::load(&config_path)?;
+    config.apply(&args);
+
+    // Create an application per printer.
+    let profiles = config.profiles(&args);
+    let offline = args.replay.is_some() || args.demo;
+    if args.help || (profiles.is_empty() && !offline) {
+        println!("{}\n\nConfig file: {}", USAGE, config_path.display());
         return Ok(());
     }
-    let mut app = App::new(args[1].clone());
+    let speed = args.speed.unwrap_or(1.0);
+    if speed <= 0.0 {
+        eprintln!("--speed must be positive");
+        process::exit(cli::EXIT_USAGE);
+    }
+    let mut apps = vec![];
+    let replay = match &args.replay {
+        Some(path) => Some(record::load(path)?),
+        None => None,
+    };
+    if offline {
+        if !args.printers.is_empty() {
+            eprintln!("--replay and --demo do not connect to printers");
+            process::exit(cli::EXIT_USAGE);
+        }
+        let name = match &args.replay {
+            Some(path) => path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+            None => "demo".to_string(),
+        };
+        apps.push(App {
+            name,
+            history_limit: config.ui.history_limit,
+            cache_dir: config.ui.cache_dir.clone(),
+            ..App::default()
+        });
+    } else {
+        for (name, profile) in profiles.iter() {
+            apps.push(App::from_profile(name, profile, &config.ui)?);
+        }
+    }
+    if let Some(path) = &args.record {
+        if apps.len() != 1 {
+            eprintln!("--record works with a single printer");
+            process::exit(cli::EXIT_USAGE);
+        }
+        apps[0].recorder = Some(Recorder::create(path)?);
+    }
+    let mut farm = Farm::new(apps);
+    let tick_rate = config.ui.tick_rate;
 
     // Initialize log writer
+    if let Some(dir) = config.ui.log_file.parent() {
+        fs::create_dir_all(dir)?;
+    }
     let logfile = FileAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{l} - {m}\n")))
-        .build("output.log")?;
+        .build(&config.ui.log_file)?;
 
-    let config = Config::builder()
+    let log_config = LogConfig::builder()
         .appender(Appender::builder().build("logfile", Box::new(logfile)))
         .build(Root::builder()
                    .appender("logfile")
                    .build(LevelFilter::Info))?;
 
-    log4rs::init_config(config)?;
+    log4rs::init_config(log_config)?;
+
+    if let Some(command) = command {
+        if farm.apps.len() != 1 {
+            eprintln!("Commands run on a single printer");
+            process::exit(cli::EXIT_USAGE);
+        }
+        process::exit(cli::run(farm.current(), &command));
+    }
 
     // Initialize the terminal user interface.
     let backend = CrosstermBackend::new(io::stderr());
     let terminal = Terminal::new(backend)?;
-    let events = EventHandler::new(100);
+    let events = EventHandler::new(tick_rate);
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
 
-    app.init();
+    match replay {
+        Some(frames) => farm.current().replay(frames, speed),
+        None if args.demo => farm.current().demo(),
+        None => farm.init(),
+    }
     // Start the main loop.
-    while app.running {
+    while farm.is_running() {
         // Render the user interface.
-        tui.draw(&mut app)?;
+        tui.draw(&mut farm)?;
         // Handle events.
         match tui.events.next()? {
-            Event::Tick => app.tick(),
-            Event::Key(key_event) => handle_key_events(key_event, &mut app)?,
+            Event::Tick => farm.tick(),
+            Event::Key(key_event) => handle_key_events(key_event, &mut farm)?,
             Event::Mouse(_) => {}
             Event::Resize(_, _) => {}
         }
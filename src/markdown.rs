@@ -1,7 +1,7 @@
 use tui::text::Line;
 use tui::prelude::*;
 
-pub fn parse<'a>(text: &'a str) -> Vec<Line> {
+pub fn parse(text: &str) -> Vec<Line<'_>> {
     let mut ret: Vec<Line> = vec![];
     for line in text.split("\n") {
         if line.starts_with("# ") {
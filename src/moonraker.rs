@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use flume::Sender;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use websocket::OwnedMessage;
+
+use crate::klipper::or_default;
+
+/// Time to wait for the response to a request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Gcode scripts only return once done, which can take a while when homing or heating
+pub const GCODE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct JsonRpcResponse {
+    jsonrpc: String,
+    #[serde(default)]
+    pub result: Value,
+    pub error: Option<JsonRpcError>,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct JsonRpcServerRequest {
+    jsonrpc: String,
+    pub method: String,
+    pub params: Option<Value>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+pub struct JsonRpcClientRequest {
+    jsonrpc: String,
+    pub method: String,
+    pub params: Value,
+    pub id: Option<String>,
+}
+
+impl JsonRpcClientRequest {
+    fn new(method: &str, params: Value) -> JsonRpcClientRequest {
+        let mut rng = rand::thread_rng();
+        let id = rng.gen::<u32>();
+        JsonRpcClientRequest {
+            jsonrpc: String::from("2.0"),
+            method: method.to_string(),
+            params,
+            id: Some(format!("{:x}", id)),
+        }
+    }
+}
+
+/// Why a request did not give a result
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcError {
+    /// Moonraker answered with an error
+    Server(JsonRpcError),
+    /// The result does not have the expected shape
+    Decode(String),
+    /// No response in time
+    Timeout,
+    /// There was no connection to send the request on
+    NotConnected,
+}
+
+impl RpcError {
+    /// JSON-RPC error code, for errors returned by Moonraker
+    pub fn code(&self) -> Option<i64> {
+        match self {
+            RpcError::Server(e) => Some(e.code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Server(e) => write!(f, "{}", e.message),
+            RpcError::Decode(e) => write!(f, "unexpected response: {}", e),
+            RpcError::Timeout => write!(f, "no response from Moonraker"),
+            RpcError::NotConnected => write!(f, "not connected"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Request sent to Moonraker and still waiting for its response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcRequest {
+    pub method: String,
+    pub sent_at: Instant,
+    pub timeout: Duration,
+}
+
+impl RpcRequest {
+    pub fn new(method: String, sent_at: Instant) -> RpcRequest {
+        let timeout = match method.as_str() {
+            "printer.gcode.script" => GCODE_TIMEOUT,
+            _ => REQUEST_TIMEOUT,
+        };
+        RpcRequest { method, sent_at, timeout }
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.sent_at) >= self.timeout
+    }
+}
+
+type Callback<C> = Box<dyn FnOnce(&mut C, Result<Value, RpcError>)>;
+
+/// Continuation ready to run, with the outcome of its request
+pub type Completion<C> = Box<dyn FnOnce(&mut C)>;
+
+struct Pending<C> {
+    request: RpcRequest,
+    /// False when there was no connection to send the request on
+    sent: bool,
+    callback: Option<Callback<C>>,
+}
+
+/// JSON-RPC client for Moonraker.
+///
+/// Requests are written to `tx`, and each may carry a continuation that runs on a
+/// context `C` once its response comes back. The owner of the client feeds it the
+/// responses with [`MoonrakerClient::resolve`], checks for timeouts with
+/// [`MoonrakerClient::expire`], and runs the returned completions:
+///
+/// ```no_run
+/// # use krui::moonraker::MoonrakerClient;
+/// struct Ctx { ready: bool }
+/// let mut client: MoonrakerClient<Ctx> = MoonrakerClient::new();
+/// client.server_info().then(|ctx, info| {
+///     ctx.ready = info.map(|i| i.is_ready()).unwrap_or(false);
+/// });
+/// ```
+pub struct MoonrakerClient<C> {
+    /// Outgoing messages, `None` while disconnected
+    pub tx: Option<Sender<OwnedMessage>>,
+    pending: HashMap<String, Pending<C>>,
+}
+
+impl<C: 'static> Default for MoonrakerClient<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: 'static> MoonrakerClient<C> {
+    pub fn new() -> MoonrakerClient<C> {
+        MoonrakerClient { tx: None, pending: HashMap::new() }
+    }
+
+    /// Sends a request whose result will be decoded as `R`
+    pub fn call<R: DeserializeOwned + 'static>(&mut self, method: &str, params: Value) -> RequestHandle<'_, C, R> {
+        let message = JsonRpcClientRequest::new(method, params);
+        let id = message.id.clone().unwrap_or_default();
+        let sent = match (&self.tx, serde_json::to_string(&message)) {
+            (Some(tx), Ok(m)) => tx.send(OwnedMessage::Text(m)).is_ok(),
+            _ => false,
+        };
+        self.pending.insert(id.clone(), Pending {
+            request: RpcRequest::new(method.to_string(), Instant::now()),
+            sent,
+            callback: None,
+        });
+        RequestHandle { client: self, id, result: PhantomData }
+    }
+
+    /// Matches a response with its request, and gives back the continuation to run, if any
+    pub fn resolve(&mut self, response: JsonRpcResponse) -> Option<Completion<C>> {
+        let pending = self.pending.remove(&response.id)?;
+        let callback = pending.callback?;
+        let result = match response.error {
+            Some(e) => Err(RpcError::Server(e)),
+            None => Ok(response.result),
+        };
+        Some(Box::new(move |c: &mut C| callback(c, result)))
+    }
+
+    /// Forgets the requests that got no response in time, or could not be sent, and
+    /// gives back their continuations
+    pub fn expire(&mut self, now: Instant) -> Vec<Completion<C>> {
+        let expired: Vec<String> = self.pending.iter()
+            .filter(|(_, p)| !p.sent || p.request.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut completions: Vec<Completion<C>> = vec![];
+        for id in expired {
+            if let Some(pending) = self.pending.remove(&id) {
+                let error = if pending.sent { RpcError::Timeout } else { RpcError::NotConnected };
+                log::error!("{} failed : {}", pending.request.method, error);
+                if let Some(callback) = pending.callback {
+                    completions.push(Box::new(move |c: &mut C| callback(c, Err(error))));
+                }
+            }
+        }
+        completions
+    }
+
+    /// Drops the connection, and gives back the continuations of the requests waiting on it
+    pub fn disconnect(&mut self) -> Vec<Completion<C>> {
+        self.tx = None;
+        let mut completions: Vec<Completion<C>> = vec![];
+        for (_, pending) in self.pending.drain() {
+            log::error!("{} failed : {}", pending.request.method, RpcError::NotConnected);
+            if let Some(callback) = pending.callback {
+                completions.push(Box::new(move |c: &mut C| callback(c, Err(RpcError::NotConnected))));
+            }
+        }
+        completions
+    }
+
+    /// Whether a request for `method` is waiting for its response
+    pub fn is_pending(&self, method: &str) -> bool {
+        self.pending.values().any(|p| p.request.method == method)
+    }
+
+    /// Whether the request `id` is waiting for its response
+    pub fn is_waiting(&self, id: &str) -> bool {
+        self.pending.contains_key(id)
+    }
+
+    /// Number of requests waiting for their response
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn server_info(&mut self) -> RequestHandle<'_, C, ServerInfo> {
+        self.call("server.info", json!({}))
+    }
+
+    pub fn objects_list(&mut self) -> RequestHandle<'_, C, ObjectList> {
+        self.call("printer.objects.list", json!({}))
+    }
+
+    /// Queries the full state of `objects`
+    pub fn objects_query(&mut self, objects: &[String]) -> RequestHandle<'_, C, ObjectStatus> {
+        self.call("printer.objects.query", objects_params(objects))
+    }
+
+    /// Subscribes to changes of `objects`, replacing any previous subscription
+    pub fn objects_subscribe(&mut self, objects: &[String]) -> RequestHandle<'_, C, ObjectStatus> {
+        self.call("printer.objects.subscribe", objects_params(objects))
+    }
+
+    /// Last `limit` jobs, most recent first
+    pub fn history_list(&mut self, limit: u32) -> RequestHandle<'_, C, HistoryList> {
+        self.call("server.history.list", json!({
+            "limit": limit,
+            "start": 0,
+            "since": 0,
+            "order": "desc"
+        }))
+    }
+
+    pub fn webcams_list(&mut self) -> RequestHandle<'_, C, WebcamList> {
+        self.call("server.webcams.list", json!({}))
+    }
+
+    pub fn files_metadata(&mut self, filename: &str) -> RequestHandle<'_, C, GcodeMetadata> {
+        self.call("server.files.metadata", json!({"filename": filename}))
+    }
+}
+
+/// Parameters asking for all the fields of each object
+fn objects_params(objects: &[String]) -> Value {
+    let mut params = serde_json::Map::new();
+    for ob in objects {
+        params.insert(ob.clone(), Value::Null);
+    }
+    json!({"objects": params})
+}
+
+/// Request that was just sent, to attach a continuation to.
+pub struct RequestHandle<'a, C, R> {
+    client: &'a mut MoonrakerClient<C>,
+    id: String,
+    result: PhantomData<fn() -> R>,
+}
+
+impl<'a, C: 'static, R: DeserializeOwned + 'static> RequestHandle<'a, C, R> {
+    /// JSON-RPC id of the request
+    pub fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    /// Runs `f` with the decoded result, or the error, once the response comes back
+    pub fn then<F>(self, f: F) -> String
+    where
+        F: FnOnce(&mut C, Result<R, RpcError>) + 'static,
+    {
+        if let Some(pending) = self.client.pending.get_mut(&self.id) {
+            pending.callback = Some(Box::new(move |c, result| {
+                f(c, result.and_then(|v| serde_json::from_value(v).map_err(|e| RpcError::Decode(e.to_string()))))
+            }));
+        }
+        self.id
+    }
+}
+
+/// Result of `server.info`
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerInfo {
+    pub klippy_connected: bool,
+    pub klippy_state: String,
+    pub components: Vec<String>,
+    pub warnings: Vec<String>,
+    pub moonraker_version: String,
+}
+
+impl ServerInfo {
+    /// Whether Klippy is connected and ready to accept commands
+    pub fn is_ready(&self) -> bool {
+        self.klippy_connected && self.klippy_state == "ready"
+    }
+}
+
+/// Result of `printer.objects.list`
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct ObjectList {
+    pub objects: Vec<String>,
+}
+
+/// Result of `printer.objects.query` and `printer.objects.subscribe`
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct ObjectStatus {
+    pub eventtime: f64,
+    /// State of each object, by object name
+    pub status: Value,
+}
+
+/// Result of `server.history.list`
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct HistoryList {
+    pub count: u64,
+    pub jobs: Vec<HistoryJob>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct HistoryJob {
+    pub job_id: String,
+    pub filename: String,
+    pub status: String,
+    pub start_time: f64,
+    /// Not set while the job is running
+    pub end_time: Option<f64>,
+    pub total_duration: f64,
+    pub filament_used: f64,
+    pub metadata: JobMetadata,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct JobMetadata {
+    pub estimated_time: Option<f64>,
+}
+
+/// Result of `server.webcams.list`
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct WebcamList {
+    pub webcams: Vec<WebcamInfo>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct WebcamInfo {
+    pub name: String,
+    pub stream_url: String,
+    pub snapshot_url: String,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+/// Result of `server.files.metadata`
+///
+/// Slicers leave out or null some of the fields, which then get their default value
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct GcodeMetadata {
+    #[serde(deserialize_with = "or_default")]
+    pub filename: String,
+    #[serde(deserialize_with = "or_default")]
+    pub size: u64,
+    #[serde(deserialize_with = "or_default")]
+    pub slicer: String,
+    #[serde(deserialize_with = "or_default")]
+    pub layer_height: f64,
+    #[serde(deserialize_with = "or_default")]
+    pub first_layer_height: f64,
+    #[serde(deserialize_with = "or_default")]
+    pub object_height: f64,
+    #[serde(deserialize_with = "or_default")]
+    pub filament_total: f64,
+    #[serde(deserialize_with = "or_default")]
+    pub estimated_time: f64,
+    #[serde(deserialize_with = "or_default")]
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Thumbnail {
+    #[serde(deserialize_with = "or_default")]
+    pub width: u32,
+    #[serde(deserialize_with = "or_default")]
+    pub height: u32,
+    #[serde(deserialize_with = "or_default")]
+    pub size: u64,
+    /// Path relative to the gcodes root
+    #[serde(deserialize_with = "or_default")]
+    pub relative_path: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected() -> (MoonrakerClient<Vec<String>>, flume::Receiver<OwnedMessage>) {
+        let (tx, rx) = flume::unbounded();
+        let mut client = MoonrakerClient::new();
+        client.tx = Some(tx);
+        (client, rx)
+    }
+
+    fn response(id: &str, body: Value) -> JsonRpcResponse {
+        let mut r = json!({"jsonrpc": "2.0", "id": id});
+        r.as_object_mut().unwrap().extend(body.as_object().unwrap().clone());
+        serde_json::from_value(r).unwrap()
+    }
+
+    #[test]
+    fn test_continuation_gets_typed_result() {
+        let (mut client, rx) = connected();
+        let id = client.server_info().then(|log: &mut Vec<String>, info| {
+            log.push(format!("ready {}", info.unwrap().is_ready()));
+        });
+        match rx.try_recv().unwrap() {
+            OwnedMessage::Text(t) => {
+                let sent: Value = serde_json::from_str(t.as_str()).unwrap();
+                assert_eq!(sent["method"], "server.info");
+                assert_eq!(sent["id"], json!(id));
+            },
+            m => panic!("unexpected message {:?}", m),
+        }
+        let mut log = vec![];
+        // Responses to unknown ids are ignored
+        assert!(client.resolve(response("0", json!({"result": {}}))).is_none());
+        let done = client.resolve(response(id.as_str(), json!({"result": {"klippy_connected": true, "klippy_state": "ready"}}))).unwrap();
+        done(&mut log);
+        assert_eq!(log, vec!["ready true"]);
+        assert_eq!(client.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_errors_reach_the_continuation() {
+        let (mut client, _rx) = connected();
+        let id = client.files_metadata("a.gcode").then(|log: &mut Vec<String>, r| log.push(r.unwrap_err().to_string()));
+        let bad = client.objects_list().then(|log: &mut Vec<String>, r| log.push(r.unwrap_err().to_string()));
+        let mut log = vec![];
+        client.resolve(response(id.as_str(), json!({"error": {"code": 404, "message": "File not found"}}))).unwrap()(&mut log);
+        client.resolve(response(bad.as_str(), json!({"result": {"objects": "webhooks"}}))).unwrap()(&mut log);
+        assert_eq!(log[0], "File not found");
+        assert!(log[1].starts_with("unexpected response"));
+    }
+
+    #[test]
+    fn test_requests_expire() {
+        let (mut client, _rx) = connected();
+        client.files_metadata("a.gcode").then(|log: &mut Vec<String>, r| log.push(r.unwrap_err().to_string()));
+        client.call::<Value>("printer.gcode.script", json!({"script": "G28"}));
+        let now = Instant::now();
+        assert!(client.expire(now + Duration::from_secs(1)).is_empty());
+        let mut log = vec![];
+        for done in client.expire(now + REQUEST_TIMEOUT + Duration::from_secs(1)) {
+            done(&mut log);
+        }
+        assert_eq!(log, vec!["no response from Moonraker"]);
+        assert!(client.is_pending("printer.gcode.script"));
+
+        // Without a connection, requests fail on the next check
+        assert!(client.disconnect().is_empty());
+        client.webcams_list().then(|log: &mut Vec<String>, r| log.push(r.unwrap_err().to_string()));
+        for done in client.expire(now) {
+            done(&mut log);
+        }
+        assert_eq!(log[1], "not connected");
+    }
+
+    #[test]
+    fn test_disconnect_fails_pending_requests() {
+        let (mut client, _rx) = connected();
+        client.files_metadata("a.gcode").then(|log: &mut Vec<String>, r| log.push(r.unwrap_err().to_string()));
+        client.call::<Value>("printer.gcode.script", json!({"script": "G28"}));
+        let mut log = vec![];
+        for done in client.disconnect() {
+            done(&mut log);
+        }
+        assert_eq!(log, vec!["not connected"]);
+        assert_eq!(client.pending_count(), 0);
+        assert!(client.tx.is_none());
+    }
+}
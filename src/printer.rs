@@ -1,5 +1,8 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
 use chrono::DateTime;
-use crate::{ui::stateful_list::StatefulList, app::HistoryItem};
+use crate::{ui::stateful_list::StatefulList, app::HistoryItem, klipper};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Webcam {
@@ -11,14 +14,31 @@ pub struct Webcam {
     pub render: u64,
 }
 
+/// How long the path of the toolhead is kept
+pub const TRAIL_DURATION: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Toolhead {
     pub position: Position,
+    /// Recent positions, oldest first
+    pub trail: VecDeque<(Instant, Position)>,
     pub homed: Homed,
     pub fan: Fan,
     pub speed: f64,
     pub extruder_velocity: f64,
 }
+impl Toolhead {
+    /// Remembers the current position, and forgets the ones older than [`TRAIL_DURATION`]
+    pub fn add_to_trail(&mut self, now: Instant) {
+        while self.trail.front().is_some_and(|(t, _)| now.saturating_duration_since(*t) > TRAIL_DURATION) {
+            self.trail.pop_front();
+        }
+        if self.trail.back().is_none_or(|(_, p)| p != &self.position) {
+            self.trail.push_back((now, self.position.clone()));
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Position {
     pub x: f64,
@@ -30,7 +50,32 @@ pub struct Homed {
     pub x: bool,
     pub y: bool,
     pub z: bool,
-    pub qgl: bool,
+    /// Set once the gantry leveling was applied
+    pub leveled: bool,
+}
+
+/// Gantry leveling done by Klipper
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leveling {
+    QuadGantry,
+    ZTilt,
+}
+
+impl Leveling {
+    /// Short name for the header and buttons
+    pub fn name(&self) -> &'static str {
+        match self {
+            Leveling::QuadGantry => "QGL",
+            Leveling::ZTilt => "Z tilt",
+        }
+    }
+
+    pub fn command(&self) -> &'static str {
+        match self {
+            Leveling::QuadGantry => "QUAD_GANTRY_LEVEL",
+            Leveling::ZTilt => "Z_TILT_ADJUST",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -58,6 +103,12 @@ pub struct PrintStats {
     pub image: String,
 }
 
+impl Default for PrintStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PrintStats {
     pub fn new() -> PrintStats {
         PrintStats {
@@ -80,6 +131,18 @@ impl PrintStats {
             image: "".to_string(),
         }
     }
+
+    /// Seconds left according to the slicer, or extrapolated from the progress
+    /// when the file has no estimate
+    pub fn time_left(&self) -> f64 {
+        if self.file.estimated_time > 0.0 {
+            (self.file.estimated_time - self.print_duration).max(0.0)
+        } else if self.progress > 0.0 {
+            self.print_duration / self.progress - self.print_duration
+        } else {
+            0.0
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -103,7 +166,7 @@ pub struct Heater {
 }
 
 pub fn default_float() -> f64 {
-    return 0.0;
+    0.0
 }
 
 #[derive(Debug, Clone)]
@@ -124,19 +187,25 @@ pub struct PrinterStatus {
 
 #[derive(Debug, Clone)]
 pub struct Printer {
-    pub connected: bool,
     pub status: PrinterStatus,
     pub toolhead: Toolhead,
     pub sysload: f64,
     pub will_print_file: Option<HistoryItem>,
     pub current_print: Option<PrintStats>,
     pub webcams: Vec<Webcam>,
+    /// Every object of the subscription, including the ones not shown above
+    pub objects: klipper::ObjectStore,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Printer {
     pub fn new() -> Printer {
         Printer {
-            connected: false,
             status: PrinterStatus {
                 heaters: StatefulList::with_items(vec![]),
                 state: String::from("unknown"),
@@ -152,7 +221,8 @@ impl Printer {
                     y: 0.0,
                     z: 0.0,
                 }, 
-                homed: Homed { x: false, y: false, z: false, qgl: false },
+                trail: VecDeque::new(),
+                homed: Homed { x: false, y: false, z: false, leveled: false },
                 fan: Fan { speed: 0.0 },
                 speed: 0.0,
                 extruder_velocity: 0.0,
@@ -161,252 +231,185 @@ impl Printer {
             will_print_file: None,
             current_print: None,
             webcams: vec![],
+            objects: klipper::ObjectStore::default(),
         }
     }
 
+    /// Whether a print is running or paused, when the toolhead and bed must be left alone
+    pub fn is_printing(&self) -> bool {
+        matches!(self.status.print_state.as_str(), "printing" | "paused")
+    }
+
+    /// Extruder temperature and settings
+    pub fn extruder(&self) -> klipper::Extruder {
+        self.objects.parse("extruder").unwrap_or_default()
+    }
+
+    /// Speed and flow factors and Z offset
+    pub fn gcode_move(&self) -> klipper::GcodeMove {
+        self.objects.parse("gcode_move").unwrap_or_default()
+    }
+
+    /// Current mesh and saved profiles
+    pub fn bed_mesh(&self) -> klipper::BedMesh {
+        self.objects.parse("bed_mesh").unwrap_or_default()
+    }
+
+    /// Gantry leveling of the printer, `None` when it has neither `quad_gantry_level` nor `z_tilt`
+    pub fn leveling(&self) -> Option<Leveling> {
+        if self.objects.get("quad_gantry_level").is_some() {
+            Some(Leveling::QuadGantry)
+        } else if self.objects.get("z_tilt").is_some() {
+            Some(Leveling::ZTilt)
+        } else {
+            None
+        }
+    }
+
+    /// Results of the last `SCREWS_TILT_CALCULATE`
+    pub fn screws_tilt_adjust(&self) -> klipper::ScrewsTiltAdjust {
+        self.objects.parse("screws_tilt_adjust").unwrap_or_default()
+    }
+
+    /// Applies a status update, or the result of an objects query.
+    ///
+    /// Only the fields present in `data` change, and malformed objects are skipped.
     pub fn update(&mut self, data: serde_json::Value) {
-        if let Some(motion) = data.get("motion_report") {
-            if let Some(position) = motion.get("live_position") {
-                if let Some(x) = position.get(0) {
-                    self.toolhead.position.x = x.as_f64().unwrap();
+        self.objects.merge(&data);
+        let update = klipper::Status::parse(&data);
+
+        if let Some(motion) = &update.motion_report {
+            if let Some(position) = &motion.live_position {
+                if let Some(x) = position.first() {
+                    self.toolhead.position.x = *x;
                 }
                 if let Some(y) = position.get(1) {
-                    self.toolhead.position.y = y.as_f64().unwrap();
+                    self.toolhead.position.y = *y;
                 }
                 if let Some(z) = position.get(2) {
-                    self.toolhead.position.z = z.as_f64().unwrap();
+                    self.toolhead.position.z = *z;
                 }
+                self.toolhead.add_to_trail(Instant::now());
             }
-            if let Some(speed) = motion.get("live_velocity") {
-                if let Some(s) = speed.as_f64() {
-                    self.toolhead.speed = s;
-                }
+            if let Some(s) = motion.live_velocity {
+                self.toolhead.speed = s;
             }
-
-            if let Some(speed) = motion.get("live_extruder_velocity") {
-                if let Some(s) = speed.as_f64() {
-                    self.toolhead.extruder_velocity = s;
-                }
+            if let Some(s) = motion.live_extruder_velocity {
+                self.toolhead.extruder_velocity = s;
             }
         }
 
         let mut status = self.status.clone();
-        if let Some(print_stats) = data.get("webhooks") {
-            if let Some(state) = print_stats.get("state") {
-                if let Some(s) = state.as_str() {
-                    status.state = s.to_string();
-                }
+        if let Some(webhooks) = &update.webhooks {
+            if let Some(s) = &webhooks.state {
+                status.state = s.clone();
             }
-            if let Some(state_msg) = print_stats.get("state_message") {
-                if let Some(s) = state_msg.as_str() {
-                    status.state_message = s.to_string();
-                }
+            if let Some(s) = &webhooks.state_message {
+                status.state_message = s.clone();
             }
         }
-        if let Some(sdcard) = data.get("virtual_sdcard") {
-            if let Some(state) = sdcard.get("progress") {
-                if let Some(s) = state.as_f64() {
-
-                    if let Some(mut cp) = self.current_print.clone() {
-                        cp.progress = s;
-                        self.current_print = Some(cp);
-                    }
-                }
+        if let Some(progress) = update.virtual_sdcard.as_ref().and_then(|s| s.progress) {
+            if let Some(cp) = &mut self.current_print {
+                cp.progress = progress;
             }
         }
 
-
-        if let Some(heaters) = data.get("heaters") {
-            if let Some(available_heaters) = heaters.get("available_heaters") {
-                // add heater to HashMap of heaters, with blank heaters
-                for h in available_heaters.as_array().unwrap() {
-                    let heater_name: String = serde_json::from_value(h.clone()).unwrap();
-                    if let None = self.status.heaters.items.iter().find(|h| {h.name == heater_name}) {
-                        self.status.heaters.add(Heater { name: heater_name, temperature: 0.0, target: 0.0, power: 0.0, heater_type: HeaterType::Heater });
-                    }
+        if let Some(heaters) = &update.heaters {
+            // add heater to the list of heaters, with blank data
+            for heater_name in heaters.available_heaters.iter().flatten() {
+                if !status.heaters.items.iter().any(|h| &h.name == heater_name) {
+                    status.heaters.add(Heater { name: heater_name.clone(), temperature: 0.0, target: 0.0, power: 0.0, heater_type: HeaterType::Heater });
                 }
             }
-            if let Some(available_sensors) = heaters.get("available_sensors") {
-                // add temperature fans to HashMap of temperature fans, with blank data
-                for h in available_sensors.as_array().unwrap() {
-                    if let Some(s) = h.as_str() {
-                        if !String::from(s).contains("temperature_fan") {
-                            continue;
-                        }
-                    }
-                    let heater_name: String = serde_json::from_value(h.clone()).unwrap();
-                    if let None = self.status.heaters.items.iter().find(|h| {h.name == heater_name}) {
-                        self.status.heaters.add(Heater { name:heater_name, temperature: 0.0, target: 0.0, power: 0.0, heater_type: HeaterType::TemperatureFan });
-                    }
+            // add temperature fans too, other sensors can't be set
+            for heater_name in heaters.available_sensors.iter().flatten().filter(|s| s.contains("temperature_fan")) {
+                if !status.heaters.items.iter().any(|h| &h.name == heater_name) {
+                    status.heaters.add(Heater { name: heater_name.clone(), temperature: 0.0, target: 0.0, power: 0.0, heater_type: HeaterType::TemperatureFan });
                 }
             }
         }
-        let mut new_heaters = self.status.heaters.items.clone();
         // For each heater, check if we have data to set their values
-        for (i, heater) in self.status.heaters.items.iter().enumerate() {
-            // try to get current heater from data
-            let mut nh = heater.clone();
-            if let Some(heater_data) = data.get(nh.name.clone()) {
-                if let Some(temp) = heater_data.get("temperature") {
-                    nh.temperature = temp.as_f64().unwrap();
+        for heater in status.heaters.items.iter_mut() {
+            if let Some(heater_data) = update.heater(heater.name.as_str()) {
+                if let Some(t) = heater_data.temperature {
+                    heater.temperature = t;
                 }
-                if let Some(pow) = heater_data.get("power") {
-                    nh.power = pow.as_f64().unwrap();
-                    if status.state == "shutdown" {
-                        nh.power = 0.0;
-                    }
+                if let Some(p) = heater_data.power {
+                    heater.power = if status.state == "shutdown" { 0.0 } else { p };
                 }
-                if let Some(pow) = heater_data.get("speed") {
-                    nh.power = pow.as_f64().unwrap();
+                if let Some(p) = heater_data.speed {
+                    heater.power = p;
                 }
-                if let Some(t) = heater_data.get("target") {
-                    nh.target = serde_json::from_value(t.clone()).unwrap();
+                if let Some(t) = heater_data.target {
+                    heater.target = t;
                 }
-                new_heaters[i] = nh;
             }
         }
-        status.heaters.items = new_heaters;
 
-
-        if let Some(print_stats) = data.get("print_stats") {
-            // log::info!("print_stats: {:?}", print_stats);
-            if let Some(state) = print_stats.get("state") {
-                if let Some(s) = state.as_str() {
-                    status.print_state = s.to_string();
-                }
+        if let Some(print_stats) = &update.print_stats {
+            if let Some(s) = &print_stats.state {
+                status.print_state = s.clone();
             }
-            if status.print_state == "printing" {
-                let mut current_print = PrintStats::new();
-                
-                if let Some(cp) = &self.current_print {
-                    current_print = cp.clone();
-                }
-                
+            if status.print_state == "printing" || status.print_state == "paused" {
+                let mut current_print = self.current_print.clone().unwrap_or_default();
+
                 // Set file data in main view
-                if let Some(filename) = print_stats.get("filename") {
-                    if let Some(f) = filename.as_str() {
-                        current_print.filename = f.to_string();
-                    }
+                if let Some(f) = &print_stats.filename {
+                    current_print.filename = f.clone();
                 }
-                if let Some(total_duration) = print_stats.get("total_duration") {
-                    if let Some(f) = total_duration.as_f64() {
-                        current_print.total_duration = f;
-                    }
+                if let Some(f) = print_stats.total_duration {
+                    current_print.total_duration = f;
                 }
-                if let Some(print_duration) = print_stats.get("print_duration") {
-                    if let Some(f) = print_duration.as_f64() {
-                        current_print.print_duration = f;
-                    }
+                if let Some(f) = print_stats.print_duration {
+                    current_print.print_duration = f;
                 }
-                if let Some(filament_used) = print_stats.get("filament_used") {
-                    if let Some(f) = filament_used.as_f64() {
-                        current_print.filament_used = f;
-                    }
+                if let Some(f) = print_stats.filament_used {
+                    current_print.filament_used = f;
                 }
-                if let Some(info) = print_stats.get("info") {
-                    if let Some(total_layers) = info.get("total_layer") {
-                        if let Some(f) = total_layers.as_i64() {
-                            current_print.total_layers = f;
-                        }
+                if let Some(info) = &print_stats.info {
+                    if let Some(f) = info.total_layer {
+                        current_print.total_layers = f;
                     }
-                    if let Some(current_layer) = info.get("current_layer") {
-                        if let Some(f) = current_layer.as_i64() {
-                            current_print.current_layer = f;
-                        }
+                    if let Some(f) = info.current_layer {
+                        current_print.current_layer = f;
                     }
                 }
-
-
                 self.current_print = Some(current_print);
             } else {
                 self.current_print = None;
             }
         }
         // TODO make this optional
-        if let Some(ks) = data.as_object() {
-            for (k, v) in ks {
-                if k.contains("filament_switch_sensor") {
-                    if let Some(fs) = v.get("filament_detected") {
-                        if let Some(s) = fs.as_bool() {
-                            status.filament_switch = s;
-                        }
-                    }
-                }
+        for sensor in update.filament_sensors() {
+            if let Some(detected) = sensor.filament_detected {
+                status.filament_switch = detected;
             }
         }
-        
-        if let Some(step) = data.get("stepper_enable") {
-            if let Some(steppers) = step.get("steppers") {
-                if let Some(ks) = steppers.as_object() {
-                    let mut e = false;
-                    for (_, v) in ks {
-                        if let Some(s) = v.as_bool() {
-                            if s {
-                                e = true;
-                                break;
-                            }
-                        }
-                    }
-                    status.stepper_enable = e;
-                }
-            }
+
+        if let Some(steppers) = update.stepper_enable.as_ref().and_then(|s| s.steppers.as_ref()) {
+            status.stepper_enable = steppers.values().any(|enabled| *enabled);
         }
         self.status = status;
+
         // Update homed axes
-        let mut homed = self.toolhead.homed.clone();
-        if let Some(toolhead) = data.get("toolhead") {
-            if let Some(homes) = toolhead.get("homed_axes") {
-                log::info!("homed_axes: {:?}", homes);
-                if let Some(axes) = homes.as_str() {
-                    let ax = String::from(axes);
-                    if ax.contains("x") {
-                        homed.x = true;
-                    } else {
-                        homed.x = false;
-                    }
-                    if ax.contains("y") {
-                        homed.y = true;
-                    } else {
-                        homed.y = false;
-                    }
-                    if ax.contains("z") {
-                        homed.z = true;
-                    } else {
-                        homed.z = false;
-                    }
-                }
-            }
+        if let Some(axes) = update.toolhead.as_ref().and_then(|t| t.homed_axes.as_ref()) {
+            log::info!("homed_axes: {:?}", axes);
+            self.toolhead.homed.x = axes.contains('x');
+            self.toolhead.homed.y = axes.contains('y');
+            self.toolhead.homed.z = axes.contains('z');
         }
-        if let Some(qgl) = data.get("quad_gantry_level") {
-            if let Some(a) = qgl.get("applied") {
-                homed.qgl = a.as_bool().unwrap();
-            }
+        if let Some(applied) = update.quad_gantry_level.as_ref().or(update.z_tilt.as_ref()).and_then(|l| l.applied) {
+            self.toolhead.homed.leveled = applied;
         }
-        self.toolhead.homed = homed;
 
         // Update part fan speed
-        let mut fan = self.toolhead.fan.clone();
-        if let Some(f) = data.get("fan") {
-            if let Some(s) = f.get("speed") {
-                if let Some(speed) = s.as_f64() {
-                    fan.speed = speed;
-                }
-            }
+        if let Some(speed) = update.fan.as_ref().and_then(|f| f.speed) {
+            self.toolhead.fan.speed = speed;
         }
-        self.toolhead.fan = fan;
         // Update sys load
-
-        if let Some(f) = data.get("system_stats") {
-            if let Some(s) = f.get("sysload") {
-                if let Some(load) = s.as_f64() {
-                    self.sysload = load;
-                }
-            }
-        }
-        if let Some(c) = data.get("connected") {
-            self.connected = c.as_bool().unwrap();
+        if let Some(load) = update.system_stats.as_ref().and_then(|s| s.sysload) {
+            self.sysload = load;
         }
-
     }
 }
 
@@ -433,6 +436,33 @@ mod tests {
         assert_eq!(p.toolhead.fan.speed, 0.75);
     }
 
+    #[test]
+    fn test_trail_keeps_recent_positions() {
+        let mut p = Printer::new();
+        let start = Instant::now();
+        for (i, x) in [10.0, 20.0, 20.0, 30.0].iter().enumerate() {
+            p.toolhead.position.x = *x;
+            p.toolhead.add_to_trail(start + Duration::from_secs(i as u64 * 2));
+        }
+        let xs: Vec<f64> = p.toolhead.trail.iter().map(|(_, p)| p.x).collect();
+        assert_eq!(xs, vec![20.0, 30.0]);
+
+        p.update(serde_json::json!({"motion_report": {"live_position": [40.0, 5.0, 1.0, 0.0]}}));
+        assert_eq!(p.toolhead.trail.back().unwrap().1, Position { x: 40.0, y: 5.0, z: 1.0 });
+    }
+
+    #[test]
+    fn test_leveling_follows_the_printer_objects() {
+        let mut p = Printer::new();
+        p.update(serde_json::json!({"toolhead": {"homed_axes": "xyz"}}));
+        assert_eq!(p.leveling(), None);
+        p.update(serde_json::json!({"z_tilt": {"applied": false}}));
+        assert_eq!(p.leveling(), Some(Leveling::ZTilt));
+        assert!(!p.toolhead.homed.leveled);
+        p.update(serde_json::json!({"z_tilt": {"applied": true}}));
+        assert!(p.toolhead.homed.leveled);
+    }
+
     #[test]
     fn test_update_fan_speed_does_not_change_other_data() {
         let mut p = Printer::new();
@@ -450,7 +480,7 @@ mod tests {
         });
         p.update(data);
         assert_eq!(p.toolhead.fan.speed, 0.75);
-        assert_eq!(p.toolhead.homed.x, false);
+        assert!(!p.toolhead.homed.x);
         assert_eq!(p.status.state, "unknown");
     }
 
@@ -540,6 +570,6 @@ mod tests {
             }
         });
         p.update(data);
-        assert_eq!(p.status.filament_switch, true);
+        assert!(p.status.filament_switch);
     }
 }
\ No newline at end of file
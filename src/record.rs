@@ -0,0 +1,165 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use flume::{Receiver, Sender};
+use serde_json::Value;
+use websocket::OwnedMessage;
+
+use crate::app::AppResult;
+
+/// Longest time a recorded response waits for the request it answers to be sent again
+const REQUEST_WAIT: Duration = Duration::from_millis(500);
+
+/// Websocket frame of a recorded session, one per line of the recording.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Frame {
+    /// Seconds since the start of the recording
+    pub time: f64,
+    /// Sent by krui, rather than received from Moonraker
+    #[serde(default)]
+    pub sent: bool,
+    pub text: String,
+}
+
+/// Writes the frames of a session to a file, see `--record`.
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<BufWriter<File>>>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> AppResult<Recorder> {
+        let file = File::create(path).map_err(|e| format!("cannot record to {}: {}", path.display(), e))?;
+        Ok(Recorder { file: Arc::new(Mutex::new(BufWriter::new(file))), start: Instant::now() })
+    }
+
+    pub fn write(&self, sent: bool, text: &str) {
+        let frame = Frame { time: self.start.elapsed().as_secs_f64(), sent, text: text.to_string() };
+        if let (Ok(mut file), Ok(line)) = (self.file.lock(), serde_json::to_string(&frame)) {
+            // Flushed right away, krui may not exit cleanly when something goes wrong
+            if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+                log::error!("Could not record frame {:?}", e);
+            }
+        }
+    }
+}
+
+/// Reads a recording made with `--record`
+pub fn load(path: &Path) -> AppResult<Vec<Frame>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot replay {}: {}", path.display(), e))?;
+    text.lines()
+        .filter(|l| !l.trim().is_empty())
+        .enumerate()
+        .map(|(i, l)| serde_json::from_str(l).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e).into()))
+        .collect()
+}
+
+/// Sends the received frames of a recording to `messages`, at `speed` times the recorded pace.
+///
+/// Responses are given the id of the request that `requests` sends for the same method,
+/// so they are handled like on the recorded connection.
+pub fn play(frames: Vec<Frame>, speed: f64, requests: Receiver<OwnedMessage>, messages: Sender<OwnedMessage>) {
+    let start = Instant::now();
+    // Method of each recorded request, by id
+    let mut methods: HashMap<String, String> = HashMap::new();
+    // Ids of the requests sent during the replay, by method
+    let mut waiting: HashMap<String, VecDeque<String>> = HashMap::new();
+    for frame in frames {
+        let mut message: Value = match serde_json::from_str(frame.text.as_str()) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        if frame.sent {
+            if let (Some(id), Some(method)) = (message["id"].as_str(), message["method"].as_str()) {
+                methods.insert(id.to_string(), method.to_string());
+            }
+            continue;
+        }
+        let due = start + Duration::from_secs_f64(frame.time.max(0.0) / speed);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+
+        if message.get("method").is_none() {
+            let method = match message["id"].as_str().and_then(|id| methods.get(id)) {
+                Some(m) => m.clone(),
+                None => continue,
+            };
+            let deadline = Instant::now() + REQUEST_WAIT;
+            let id = loop {
+                for request in requests.try_iter() {
+                    if let OwnedMessage::Text(t) = request {
+                        if let Ok(r) = serde_json::from_str::<Value>(t.as_str()) {
+                            if let (Some(id), Some(m)) = (r["id"].as_str(), r["method"].as_str()) {
+                                waiting.entry(m.to_string()).or_default().push_back(id.to_string());
+                            }
+                        }
+                    }
+                }
+                if let Some(id) = waiting.get_mut(&method).and_then(|ids| ids.pop_front()) {
+                    break Some(id);
+                }
+                if Instant::now() >= deadline {
+                    break None;
+                }
+                thread::sleep(Duration::from_millis(5));
+            };
+            match id {
+                Some(id) => message["id"] = Value::String(id),
+                // Nobody asked this time
+                None => continue,
+            }
+        }
+        if messages.send(OwnedMessage::Text(message.to_string())).is_err() {
+            return;
+        }
+    }
+    log::info!("Replay finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_recording_is_read_back() {
+        let path = std::env::temp_dir().join(format!("krui-record-{}.jsonl", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        recorder.write(true, r#"{"method":"server.info","id":"1"}"#);
+        recorder.clone().write(false, r#"{"result":{},"id":"1"}"#);
+        let frames = load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].sent && !frames[1].sent);
+        assert!(frames[0].time <= frames[1].time);
+        assert_eq!(frames[1].text, r#"{"result":{},"id":"1"}"#);
+    }
+
+    #[test]
+    fn test_responses_follow_the_new_requests() {
+        let frame = |time: f64, sent: bool, v: Value| Frame { time, sent, text: v.to_string() };
+        let frames = vec![
+            frame(0.0, true, json!({"method": "server.info", "id": "old"})),
+            frame(0.0, false, json!({"method": "notify_klippy_ready", "params": []})),
+            frame(0.01, false, json!({"result": {"klippy_state": "ready"}, "id": "old"})),
+            frame(0.02, false, json!({"result": "ok", "id": "unknown"})),
+        ];
+        let (req_tx, req_rx) = flume::unbounded();
+        let (msg_tx, msg_rx) = flume::unbounded();
+        req_tx.send(OwnedMessage::Text(json!({"method": "server.info", "id": "new"}).to_string())).unwrap();
+        play(frames, 10.0, req_rx, msg_tx);
+
+        let received: Vec<Value> = msg_rx.try_iter().map(|m| match m {
+            OwnedMessage::Text(t) => serde_json::from_str(t.as_str()).unwrap(),
+            _ => Value::Null,
+        }).collect();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0]["method"], "notify_klippy_ready");
+        assert_eq!(received[1]["id"], "new");
+    }
+}
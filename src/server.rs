@@ -0,0 +1,261 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use curl::easy::{Easy, List};
+use serde_json::Value;
+use websocket::url::{Url, ParseError};
+
+use crate::app::AppResult;
+
+/// Longest wait for the server to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Longest time a request may take, as they run on the UI thread
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `path` when it is relative and stays below the directory it is joined to,
+/// `None` when it is absolute or goes up with `..`
+pub fn relative_path(path: &str) -> Option<&Path> {
+    let relative = Path::new(path);
+    let below = relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    (!path.is_empty() && below).then_some(relative)
+}
+
+/// Location of a Moonraker instance and the credentials needed to talk to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    /// Base http(s) url of the instance, always ending with a `/`
+    pub url: Url,
+    /// Moonraker API key, sent as `X-Api-Key`
+    pub api_key: Option<String>,
+    /// User to log in as when Moonraker asks for a login
+    pub username: Option<String>,
+    /// JWT of the logged in user, sent as `Authorization: Bearer`
+    pub access_token: Option<String>,
+}
+
+/// Moonraker refused our credentials, retrying will not help.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unauthorized;
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unauthorized")
+    }
+}
+
+impl Error for Unauthorized {}
+
+impl Server {
+    /// Parses a server address given on the command line.
+    ///
+    /// Accepts a bare host (`192.168.1.121`, `printer.local:7125`) as well as full
+    /// urls with a scheme and a path prefix (`https://farm.lan/voron/`).
+    /// Websocket schemes are mapped to their http equivalent and a trailing
+    /// `/websocket` is ignored. `unix:///home/pi/printer_data/comms/moonraker.sock`
+    /// is the unix socket of a Moonraker running on this host.
+    pub fn parse(address: &str, api_key: Option<String>) -> AppResult<Server> {
+        let address = address.trim();
+        let mut url = if address.contains("://") {
+            Url::parse(address)?
+        } else {
+            Url::parse(format!("http://{}", address).as_str())?
+        };
+
+        let scheme = match url.scheme() {
+            "unix" if url.path().len() > 1 => return Ok(Server { url, api_key, username: None, access_token: None }),
+            "ws" | "http" => "http",
+            "wss" | "https" => "https",
+            s => return Err(format!("unsupported scheme {}", s).into()),
+        };
+        let _ = url.set_scheme(scheme);
+
+        let path = url.path().trim_end_matches('/').trim_end_matches("/websocket").to_string();
+        url.set_path(format!("{}/", path).as_str());
+        url.set_query(None);
+        url.set_fragment(None);
+
+        Ok(Server { url, api_key, username: None, access_token: None })
+    }
+
+    /// Whether the connection to this server uses TLS
+    pub fn is_secure(&self) -> bool {
+        self.url.scheme() == "https"
+    }
+
+    /// Path of the unix socket, for servers on this host
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        match self.url.scheme() {
+            "unix" => Some(PathBuf::from(self.url.path())),
+            _ => None,
+        }
+    }
+
+    /// Where Moonraker keeps its files on this host, when connected through its unix socket.
+    ///
+    /// The socket is in `<data path>/comms/`, next to the `gcodes` and `config` directories.
+    fn local_file(&self, path: &str) -> Option<PathBuf> {
+        let data_path = self.socket_path()?.parent()?.parent()?.to_path_buf();
+        let file = relative_path(path.strip_prefix("server/files/")?)?;
+        Some(data_path.join(file))
+    }
+
+    /// Short name used to display this server in the UI
+    pub fn name(&self) -> String {
+        let url = self.url.as_str();
+        url.split_once("://").map(|(_, rest)| rest).unwrap_or(url).trim_end_matches('/').to_string()
+    }
+
+    /// Url of an http endpoint.
+    ///
+    /// Relative paths are resolved below the path prefix, absolute paths (as
+    /// returned for webcam snapshots) against the host, and full urls are left as is.
+    /// Over the unix socket, the host is `localhost`.
+    pub fn http_url(&self, path: &str) -> Result<Url, ParseError> {
+        match self.socket_path() {
+            Some(_) => Url::parse("http://localhost/")?.join(path),
+            None => self.url.join(path),
+        }
+    }
+
+    /// Url of the websocket endpoint, with an optional oneshot token for authentication
+    pub fn websocket_url(&self, token: Option<&str>) -> Url {
+        let mut url = self.url.join("websocket").unwrap_or_else(|_| self.url.clone());
+        let _ = url.set_scheme(if self.is_secure() { "wss" } else { "ws" });
+        if let Some(t) = token {
+            url.query_pairs_mut().append_pair("token", t);
+        }
+        url
+    }
+
+    /// Requests a oneshot token that allows opening the websocket on
+    /// instances with authorization enabled.
+    ///
+    /// Returns `None` when no API key is configured, or over the unix socket.
+    pub fn oneshot_token(&self) -> AppResult<Option<String>> {
+        if self.api_key.is_none() || self.socket_path().is_some() {
+            return Ok(None);
+        }
+        let mut body: Vec<u8> = vec![];
+        let mut easy = self.request(self.http_url("access/oneshot_token")?)?;
+        easy.fail_on_error(false)?;
+        {
+            let mut transfer = easy.transfer();
+            transfer.write_function(|data| {
+                body.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            transfer.perform()?;
+        }
+        match easy.response_code()? {
+            401 | 403 => return Err(Box::new(Unauthorized)),
+            code if code >= 400 => return Err(format!("oneshot token request failed with status {}", code).into()),
+            _ => {},
+        }
+        let response: Value = serde_json::from_slice(&body)?;
+        match response.get("result").and_then(|r| r.as_str()) {
+            Some(token) => Ok(Some(token.to_string())),
+            None => Err(format!("unexpected oneshot token response {}", response).into()),
+        }
+    }
+
+    /// Downloads `path` from the server and saves it to `filepath`
+    pub fn download(&self, path: &str, filepath: &Path) -> AppResult<()> {
+        // The unix socket only speaks JSON-RPC, but the files are right here
+        if let Some(file) = self.local_file(path) {
+            log::info!("Copying {:?}", file);
+            fs::copy(file, filepath)?;
+            return Ok(());
+        }
+        let url = self.http_url(path)?;
+        log::info!("Downloading {}", url);
+        let mut easy = self.request(url)?;
+        let mut file = File::create(filepath)?;
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            match file.write_all(data) {
+                Ok(_) => Ok(data.len()),
+                // Returning a short count makes curl abort the transfer
+                Err(_) => Ok(0),
+            }
+        })?;
+        transfer.perform()?;
+        Ok(())
+    }
+
+    fn request(&self, url: Url) -> AppResult<Easy> {
+        let mut easy = Easy::new();
+        easy.url(url.as_str())?;
+        easy.fail_on_error(true)?;
+        easy.connect_timeout(CONNECT_TIMEOUT)?;
+        easy.timeout(REQUEST_TIMEOUT)?;
+        let mut headers = List::new();
+        if let Some(key) = &self.api_key {
+            headers.append(format!("X-Api-Key: {}", key).as_str())?;
+        }
+        if let Some(token) = &self.access_token {
+            headers.append(format!("Authorization: Bearer {}", token).as_str())?;
+        }
+        easy.http_headers(headers)?;
+        Ok(easy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bare_host_uses_plain_http() {
+        let s = Server::parse("192.168.1.121", None).unwrap();
+        assert_eq!(s.url.as_str(), "http://192.168.1.121/");
+        assert_eq!(s.websocket_url(None).as_str(), "ws://192.168.1.121/websocket");
+        assert!(!s.is_secure());
+        assert_eq!(s.name(), "192.168.1.121");
+    }
+
+    #[test]
+    fn test_full_url_keeps_port_and_prefix() {
+        let s = Server::parse("https://farm.lan:8443/voron", None).unwrap();
+        assert!(s.is_secure());
+        assert_eq!(s.websocket_url(None).as_str(), "wss://farm.lan:8443/voron/websocket");
+        assert_eq!(s.http_url("server/files/gcodes/a b.png").unwrap().as_str(), "https://farm.lan:8443/voron/server/files/gcodes/a%20b.png");
+        assert_eq!(s.http_url("/webcam/?action=snapshot").unwrap().as_str(), "https://farm.lan:8443/webcam/?action=snapshot");
+        assert_eq!(s.name(), "farm.lan:8443/voron");
+    }
+
+    #[test]
+    fn test_websocket_url_is_accepted() {
+        let s = Server::parse("wss://farm.lan/voron/websocket", None).unwrap();
+        assert_eq!(s.url.as_str(), "https://farm.lan/voron/");
+        assert_eq!(s.websocket_url(Some("abc")).as_str(), "wss://farm.lan/voron/websocket?token=abc");
+    }
+
+    #[test]
+    fn test_unix_socket_url() {
+        let s = Server::parse("unix:///home/pi/printer_data/comms/moonraker.sock", None).unwrap();
+        assert_eq!(s.socket_path(), Some(PathBuf::from("/home/pi/printer_data/comms/moonraker.sock")));
+        assert_eq!(s.local_file("server/files/gcodes/.thumbs/a.png"), Some(PathBuf::from("/home/pi/printer_data/gcodes/.thumbs/a.png")));
+        assert_eq!(s.local_file("server/files/gcodes/../../.ssh/id_rsa"), None);
+        assert_eq!(s.http_url("/webcam/?action=snapshot").unwrap().as_str(), "http://localhost/webcam/?action=snapshot");
+        assert!(Server::parse("unix://", None).is_err());
+        assert_eq!(Server::parse("printer.local", None).unwrap().socket_path(), None);
+    }
+
+    #[test]
+    fn test_relative_paths_stay_below() {
+        assert_eq!(relative_path("sub/.thumbs/cube.png"), Some(Path::new("sub/.thumbs/cube.png")));
+        assert_eq!(relative_path("../../.bashrc"), None);
+        assert_eq!(relative_path(".thumbs/../../cube.png"), None);
+        assert_eq!(relative_path("/etc/passwd"), None);
+        assert_eq!(relative_path(""), None);
+    }
+
+    #[test]
+    fn test_unknown_scheme_is_rejected() {
+        assert!(Server::parse("ftp://farm.lan", None).is_err());
+    }
+}
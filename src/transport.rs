@@ -0,0 +1,239 @@
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use websocket::dataframe::{DataFrame, Opcode};
+use websocket::sync::{Client, Stream};
+use websocket::ws::Message;
+use websocket::{OwnedMessage, WebSocketError};
+
+/// Marks the end of a message on Moonraker's unix socket
+const ETX: u8 = 0x03;
+/// Larger websocket frames are taken for a corrupted stream
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Carries JSON-RPC messages between krui and Moonraker.
+///
+/// Reads time out quickly, so that the connection thread can send while waiting:
+/// `recv` then fails with `WouldBlock` or `TimedOut`, and `InvalidData` is returned
+/// for messages that could not be read but leave the connection usable.
+/// Any other error means that the connection is lost.
+/// A message read partly when the read times out is kept for the next `recv`.
+pub trait Transport: Send {
+    fn send(&mut self, message: &OwnedMessage) -> io::Result<()>;
+    fn recv(&mut self) -> io::Result<OwnedMessage>;
+
+    /// Whether the other end answers pings, so that a silent connection can be checked
+    fn answers_pings(&self) -> bool {
+        false
+    }
+}
+
+/// A websocket, read one frame at a time.
+///
+/// The websocket crate reads frames straight from the stream and loses what it read
+/// when the read times out, so the frames are buffered here until they are complete.
+pub struct WebsocketTransport<S> {
+    stream: S,
+    /// Start of a frame not received entirely yet
+    buffer: Vec<u8>,
+    /// Frames of a fragmented message, until its last one
+    fragments: Vec<DataFrame>,
+    /// Clients mask the frames they send, servers expect them masked
+    client: bool,
+}
+
+impl<S: Stream + Send> WebsocketTransport<S> {
+    /// The end of the websocket that connected
+    pub fn client(client: Client<S>) -> WebsocketTransport<S> {
+        WebsocketTransport::from_client(client, true)
+    }
+
+    /// The end of the websocket that accepted the connection
+    pub fn server(client: Client<S>) -> WebsocketTransport<S> {
+        WebsocketTransport::from_client(client, false)
+    }
+
+    fn from_client(client: Client<S>, masked: bool) -> WebsocketTransport<S> {
+        // The handshake may have read the first frames already
+        let (stream, read) = client.into_stream();
+        let buffer = read.map(|(buf, pos, cap)| buf[pos..cap].to_vec()).unwrap_or_default();
+        WebsocketTransport { stream, buffer, fragments: vec![], client: masked }
+    }
+
+    /// Takes the next frame off the buffer, if it was received entirely
+    fn next_frame(&mut self) -> io::Result<Option<DataFrame>> {
+        // Moonraker doesn't use reserved opcodes, the stream is out of step
+        if self.buffer.first().is_some_and(|b| !matches!(
+            Opcode::new(b & 0x0F),
+            Some(Opcode::Continuation | Opcode::Text | Opcode::Binary | Opcode::Close | Opcode::Ping | Opcode::Pong)
+        )) {
+            return Err(io::Error::other("reserved websocket opcode"));
+        }
+        let mut cursor = Cursor::new(&self.buffer);
+        match DataFrame::read_dataframe_with_limit(&mut cursor, !self.client, MAX_FRAME_SIZE) {
+            Ok(frame) => {
+                let end = cursor.position() as usize;
+                self.buffer.drain(..end);
+                Ok(Some(frame))
+            },
+            // What the websocket crate makes of an unexpected end of the buffer
+            Err(WebSocketError::NoDataAvailable) => Ok(None),
+            // Frames can't be told apart anymore
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+}
+
+impl<S: Stream + Send> Transport for WebsocketTransport<S> {
+    fn send(&mut self, message: &OwnedMessage) -> io::Result<()> {
+        message.serialize(&mut self.stream, self.client).map_err(websocket_error)?;
+        self.stream.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<OwnedMessage> {
+        loop {
+            while let Some(frame) = self.next_frame()? {
+                // Control frames may come between the fragments of a message
+                let frames = if frame.opcode as u8 >= Opcode::Close as u8 {
+                    vec![frame]
+                } else if frame.finished {
+                    self.fragments.push(frame);
+                    mem::take(&mut self.fragments)
+                } else {
+                    self.fragments.push(frame);
+                    continue;
+                };
+                return OwnedMessage::from_dataframes(frames).map_err(websocket_error);
+            }
+            let mut data = [0u8; 8192];
+            match self.stream.read(&mut data)? {
+                0 => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+                n => self.buffer.extend_from_slice(&data[..n]),
+            }
+        }
+    }
+
+    fn answers_pings(&self) -> bool {
+        true
+    }
+}
+
+fn websocket_error(e: WebSocketError) -> io::Error {
+    match e {
+        WebSocketError::IoError(e) => e,
+        WebSocketError::NoDataAvailable => io::Error::new(ErrorKind::UnexpectedEof, "connection closed"),
+        e => io::Error::new(ErrorKind::InvalidData, e),
+    }
+}
+
+/// Moonraker's unix socket, where each message ends with an ETX character
+pub struct UnixTransport {
+    stream: UnixStream,
+    /// Start of a message not received entirely yet
+    buffer: Vec<u8>,
+}
+
+impl UnixTransport {
+    pub fn connect(path: &Path) -> io::Result<UnixTransport> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+        Ok(UnixTransport::new(stream))
+    }
+
+    pub fn new(stream: UnixStream) -> UnixTransport {
+        UnixTransport { stream, buffer: vec![] }
+    }
+}
+
+impl Transport for UnixTransport {
+    fn send(&mut self, message: &OwnedMessage) -> io::Result<()> {
+        match message {
+            OwnedMessage::Text(text) => {
+                let mut frame = Vec::with_capacity(text.len() + 1);
+                frame.extend_from_slice(text.as_bytes());
+                frame.push(ETX);
+                self.stream.write_all(&frame)
+            },
+            OwnedMessage::Close(_) => self.stream.shutdown(Shutdown::Both),
+            // Pings are a websocket thing
+            _ => Ok(()),
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<OwnedMessage> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|b| *b == ETX) {
+                let frame: Vec<u8> = self.buffer.drain(..=end).take(end).collect();
+                return String::from_utf8(frame)
+                    .map(OwnedMessage::Text)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e));
+            }
+            let mut data = [0u8; 8192];
+            match self.stream.read(&mut data)? {
+                0 => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+                n => self.buffer.extend_from_slice(&data[..n]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_messages_are_delimited() {
+        let (a, mut b) = UnixStream::pair().unwrap();
+        let mut transport = UnixTransport::new(a);
+        transport.send(&OwnedMessage::Text(r#"{"id":"1"}"#.to_string())).unwrap();
+        let mut sent = [0u8; 11];
+        b.read_exact(&mut sent).unwrap();
+        assert_eq!(&sent, b"{\"id\":\"1\"}\x03");
+
+        // Messages may arrive in pieces, or several at once
+        b.write_all(b"{\"a\":").unwrap();
+        b.write_all(b"1}\x03{\"b\":2}\x03").unwrap();
+        assert_eq!(transport.recv().unwrap(), OwnedMessage::Text(r#"{"a":1}"#.to_string()));
+        assert_eq!(transport.recv().unwrap(), OwnedMessage::Text(r#"{"b":2}"#.to_string()));
+        drop(b);
+        assert_eq!(transport.recv().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_websocket_frames_survive_read_timeouts() {
+        use websocket::ws::dataframe::DataFrame as _;
+
+        let (a, mut b) = UnixStream::pair().unwrap();
+        a.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut transport = WebsocketTransport { stream: a, buffer: vec![], fragments: vec![], client: true };
+        transport.send(&OwnedMessage::Text("hello".to_string())).unwrap();
+        let sent = DataFrame::read_dataframe(&mut b, true).unwrap();
+        assert_eq!(sent.data, b"hello");
+
+        // A frame cut by a timeout is completed by the next read
+        let mut frame = vec![];
+        OwnedMessage::Text(r#"{"a":1}"#.to_string()).serialize(&mut frame, false).unwrap();
+        b.write_all(&frame[..4]).unwrap();
+        assert!(matches!(transport.recv().unwrap_err().kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut));
+        b.write_all(&frame[4..]).unwrap();
+        assert_eq!(transport.recv().unwrap(), OwnedMessage::Text(r#"{"a":1}"#.to_string()));
+
+        // Pings may come between the fragments of a message
+        let mut frames = vec![];
+        DataFrame::new(false, Opcode::Text, b"{\"b\"".to_vec()).write_to(&mut frames, false).unwrap();
+        DataFrame::new(true, Opcode::Ping, vec![]).write_to(&mut frames, false).unwrap();
+        DataFrame::new(true, Opcode::Continuation, b":2}".to_vec()).write_to(&mut frames, false).unwrap();
+        b.write_all(&frames).unwrap();
+        assert_eq!(transport.recv().unwrap(), OwnedMessage::Ping(vec![]));
+        assert_eq!(transport.recv().unwrap(), OwnedMessage::Text(r#"{"b":2}"#.to_string()));
+
+        // Reserved opcodes mean that the frames can't be told apart anymore
+        b.write_all(&[0x83, 0x00]).unwrap();
+        assert_eq!(transport.recv().unwrap_err().kind(), ErrorKind::Other);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::app::{App, AppResult};
+use crate::app::AppResult;
+use crate::farm::Farm;
 use crate::event::EventHandler;
 use crate::ui;
 use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
@@ -50,8 +51,9 @@ impl<B: Backend> Tui<B> {
     ///
     /// [`Draw`]: tui::Terminal::draw
     /// [`rendering`]: crate::ui:render
-    pub fn draw(&mut self, app: &mut App) -> AppResult<()> {
-        self.terminal.draw(|frame| ui::render(app, frame))?;
+    pub fn draw(&mut self, farm: &mut Farm) -> AppResult<()> {
+        self.terminal.draw(|frame| ui::render(farm, frame))?;
+        farm.end_frame();
         Ok(())
     }
 
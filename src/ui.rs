@@ -1,9 +1,16 @@
 pub mod header;
 pub mod toolhead;
+pub mod extruder;
 pub mod main;
 
 pub mod console;
 pub mod webcam;
+pub mod login;
+pub mod printers;
+pub mod overview;
+pub mod inspector;
+pub mod bed_mesh;
+pub mod leveling;
 pub mod stateful_list;
 use std::rc::Rc;
 
@@ -15,43 +22,60 @@ use tui::{
     Frame, text::{Line, Span},
 };
 
-use crate::{app::App, button::{Button, action_button}, printer::Webcam};
+use crate::{button::{Button, action_button}, farm::Farm};
 use crate::app::Tab;
+use crate::connection::ConnectionState;
 
 
 
 /// Renders the user interface widgets.
-pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
+pub fn render<B: Backend>(farm: &mut Farm, frame: &mut Frame<'_, B>) {
     // This is where you add new widgets.
     // See the following resources:
     // - https://docs.rs/ratatui/latest/ratatui/widgets/index.html
     // - https://github.com/ratatui-org/ratatui/tree/master/examples
+    if farm.overview {
+        overview::draw_tab(frame, farm, frame.size());
+        return;
+    }
+
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(0)
         .constraints(
             [
                 Constraint::Max(1),
+                // One row per printer, when there are several
+                Constraint::Length(if farm.apps.len() > 1 { farm.apps.len() as u16 } else { 0 }),
                 Constraint::Min(8),
             ].as_ref()
         )
         .split(frame.size());
 
+    printers::draw_printer_rows(frame, farm, chunks[1]);
+    let app = farm.current();
     header::draw_header(frame, app, chunks[0]);
 
     match app.current_tab {
-        Tab::Main => main::draw_main_tab(frame, app, chunks[1]),
-        Tab::Help => main::draw_main_help(frame, app, chunks[1]),
-        Tab::Toolhead => toolhead::draw_toolhead_tab(frame, app, chunks[1]),
-        Tab::ToolheadHelp => toolhead::draw_toolhead_help(frame, app, chunks[1]),
-        Tab::Console => console::draw_tab(frame, app, chunks[1]),
-        Tab::ConsoleHelp => console::draw_help(frame, app, chunks[1]),
-        Tab::Webcam => webcam::draw_tab(frame, app, chunks[1]),
-        Tab::WebcamHelp => webcam::draw_help(frame, app, chunks[1]),
-        _ => {}
+        Tab::Main => main::draw_main_tab(frame, app, chunks[2]),
+        Tab::Help => main::draw_main_help(frame, app, chunks[2]),
+        Tab::Toolhead => toolhead::draw_toolhead_tab(frame, app, chunks[2]),
+        Tab::ToolheadHelp => toolhead::draw_toolhead_help(frame, app, chunks[2]),
+        Tab::Extruder => extruder::draw_tab(frame, app, chunks[2]),
+        Tab::ExtruderHelp => extruder::draw_help(frame, app, chunks[2]),
+        Tab::Console => console::draw_tab(frame, app, chunks[2]),
+        Tab::ConsoleHelp => console::draw_help(frame, app, chunks[2]),
+        Tab::Webcam => webcam::draw_tab(frame, app, chunks[2]),
+        Tab::WebcamHelp => webcam::draw_help(frame, app, chunks[2]),
+        Tab::Objects => inspector::draw_tab(frame, app, chunks[2]),
+        Tab::ObjectsHelp => inspector::draw_help(frame, app, chunks[2]),
+        Tab::BedMesh => bed_mesh::draw_tab(frame, app, chunks[2]),
+        Tab::BedMeshHelp => bed_mesh::draw_help(frame, app, chunks[2]),
+        Tab::Leveling => leveling::draw_tab(frame, app, chunks[2]),
+        Tab::LevelingHelp => leveling::draw_help(frame, app, chunks[2]),
     }
 
-    if app.printer.connected == false && app.printer.status.state == "shutdown".to_string() {
+    if !app.is_ready() && app.printer.status.state == "shutdown" {
         let title = Paragraph::new(vec![
             Line::from(vec![
                 Span::styled(format!("Klipper reports: {}", app.printer.status.state), Style::default().bg(Color::White).fg(Color::Black).add_modifier(Modifier::BOLD)),
@@ -78,12 +102,38 @@ pub fn render<B: Backend>(app: &mut App, frame: &mut Frame<'_, B>) {
         modal(frame, title, text, btn, None);
     }
 
-    
-    
+    if app.connection == ConnectionState::AuthFailed && app.login.is_none() {
+        let title = Paragraph::new(vec![
+            Line::from(vec![
+                Span::styled("Unauthorized", Style::default().bg(Color::White).fg(Color::Red).add_modifier(Modifier::BOLD)),
+            ]).alignment(Alignment::Center),
+        ]);
+        let text = Paragraph::new(format!("{} refused the connection. Check the API key, or add this computer to the trusted clients in moonraker.conf.", app.server.name()))
+        .wrap(Wrap {trim: false});
+
+        let retry: Button = Button::new("Retry".to_string(), Some("10".to_string()));
+        let btn = Table::new(vec![
+            Row::new(vec![
+                Line::from(action_button(retry)).alignment(Alignment::Center),
+            ])
+        ])
+        .widths(&[
+            Constraint::Percentage(100),
+        ])
+        .block(Block::default()
+            .borders(Borders::NONE)
+            .padding(Padding::horizontal(2))
+        )
+        ;
+
+        modal(frame, title, text, btn, None);
+    }
+
+    login::draw_login_modal(frame, app);
 
 }
 
-pub fn modal<'a, B>(f: &mut Frame<B>, title: Paragraph, text: Paragraph, buttons: Table, input: Option<Paragraph>) -> Rc<[Rect]>
+pub fn modal<B>(f: &mut Frame<B>, title: Paragraph, text: Paragraph, buttons: Table, input: Option<Paragraph>) -> Rc<[Rect]>
 where
     B: Backend,
 {
@@ -122,5 +172,18 @@ where
     }
     f.render_widget(buttons, Rect::new(chunks[3].x + 1, chunks[3].y, chunks[3].width - 2, chunks[3].height));
 
-    return chunks;
-}
\ No newline at end of file
+    chunks
+}
+
+/// The values of `options`, with the selected one highlighted
+pub fn selector<'a>(options: &[f64], selected: usize, unit: &str) -> Line<'a> {
+    let spans: Vec<Span> = options.iter().enumerate().map(|(i, v)| {
+        let style = if i == selected {
+            Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        Span::styled(format!(" {}{} ", v, unit), style)
+    }).collect();
+    Line::from(spans).alignment(Alignment::Center)
+}
@@ -0,0 +1,252 @@
+use tui::{widgets::{Borders, Paragraph, Block, Wrap, Table, Row, List, ListItem, ListState, Padding}, prelude::*};
+
+use crate::{ui::{header, modal}, button::{Button, action_button}, markdown, app::App, bed_mesh::{MeshPrompt, MeshStats}};
+
+const BED_MESH_HELP_TEXT: &str = "
+# Bed Mesh Help
+
+This screen shows the bed mesh, seen from above with the front of the bed at the bottom. Blue points are low and red points are high.
+Move between the probe points with the arrow keys, the height of the point under the cursor is shown on the right with the mesh statistics.
+Pressing I switches between the probed points and the interpolated mesh.
+
+Pressing C probes the bed with BED_MESH_CALIBRATE, and pressing X clears the current mesh.
+Select a saved profile with Page Up and Page Down. Pressing L loads it, and pressing D removes it after a confirmation.
+Pressing S saves the current mesh under the name you type, without spaces or quotes. Klipper only keeps saved and removed profiles after a SAVE_CONFIG, which you can send from the console.
+The mesh can't be probed, cleared or loaded while printing.
+Pressing T opens the leveling screen, to level the gantry or adjust the bed screws.
+";
+
+/// Blue for the lowest points, pale yellow in between and red for the highest
+fn heat_color(value: f64, min: f64, max: f64) -> Color {
+    let t = if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) } else { 0.5 };
+    let (from, to, t) = if t < 0.5 { ((49.0, 54.0, 149.0), (255.0, 255.0, 191.0), t * 2.0) } else { ((255.0, 255.0, 191.0), (165.0, 0.0, 38.0), t * 2.0 - 1.0) };
+    let mix = |a: f64, b: f64| (a + (b - a) * t).round() as u8;
+    Color::Rgb(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+/// Lines of the heatmap, the back of the bed first
+fn heatmap<'a>(matrix: &[Vec<f64>], stats: MeshStats, cursor: (usize, usize), area: Rect) -> Vec<Line<'a>> {
+    let columns = matrix.iter().map(|r| r.len()).max().unwrap_or(1).max(1);
+    let width = (area.width as usize / columns).clamp(1, 9);
+    let height = (area.height as usize / matrix.len().max(1)).clamp(1, 3);
+    let mut lines = vec![];
+    for (y, row) in matrix.iter().enumerate().rev() {
+        for line in 0..height {
+            let spans: Vec<Span> = row.iter().enumerate().map(|(x, z)| {
+                let t = if stats.range() > 0.0 { (z - stats.min) / stats.range() } else { 0.5 };
+                let mut style = Style::default()
+                    .bg(heat_color(*z, stats.min, stats.max))
+                    .fg(if (0.2..0.8).contains(&t) { Color::Black } else { Color::White });
+                if (x, y) == cursor {
+                    style = style.add_modifier(Modifier::REVERSED | Modifier::BOLD);
+                }
+                let text = if line == height / 2 && width >= 7 { format!("{:+.3}", z) } else { "".to_string() };
+                Span::styled(format!("{: ^width$}", text, width = width), style)
+            }).collect();
+            lines.push(Line::from(spans).alignment(Alignment::Center));
+        }
+    }
+    lines
+}
+
+
+pub fn draw_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Max(1),     // Title
+            Constraint::Min(6),     // Mesh and statistics
+            Constraint::Length(2),     // Actions
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+    let t_title = Span::styled(format!("{: ^width$}", "Bed Mesh", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+    f.render_widget(Block::default().title(t_title).borders(Borders::NONE), chunks[0]);
+
+    let body = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(20), Constraint::Length(32)].as_ref())
+        .split(chunks[1]);
+
+    let mesh = app.printer.bed_mesh();
+    let view = &app.bed_mesh;
+    let matrix = view.matrix(&mesh);
+    let stats = MeshStats::of(matrix);
+    let cursor = view.cursor(matrix);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(if view.interpolated { "Interpolated mesh (I)" } else { "Probed points (I)" });
+    let inner = block.inner(body[0]);
+    match stats {
+        Some(stats) => {
+            let lines = heatmap(matrix, stats, cursor, inner);
+            // Center the heatmap vertically
+            let top = Padding { left: 0, right: 0, top: (inner.height.saturating_sub(lines.len() as u16)) / 2, bottom: 0 };
+            f.render_widget(Paragraph::new(lines).block(block.padding(top)), body[0]);
+        },
+        None => {
+            f.render_widget(Paragraph::new("No mesh loaded, press C to probe the bed or L to load a profile").alignment(Alignment::Center).wrap(Wrap { trim: false }).block(block), body[0]);
+        },
+    }
+
+    let side = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(11), Constraint::Min(3)].as_ref())
+        .split(body[1]);
+    let bold = Style::default().fg(Color::White).add_modifier(Modifier::BOLD);
+    let mm = |v: Option<f64>| v.map(|v| format!("{:+.3}mm", v)).unwrap_or_else(|| "-".to_string());
+    let profile_name = mesh.profile_name.clone().filter(|n| !n.is_empty());
+    let mut rows = vec![
+        Row::new(vec![Line::from("Profile"), Line::from(profile_name.clone().unwrap_or_else(|| "none".to_string()))]),
+        Row::new(vec![Line::from("Points"), Line::from(format!("{} × {}", matrix.first().map_or(0, |r| r.len()), matrix.len()))]),
+        Row::new(vec![Line::from("Min"), Line::from(mm(stats.map(|s| s.min)))]),
+        Row::new(vec![Line::from("Max"), Line::from(mm(stats.map(|s| s.max)))]),
+        Row::new(vec![Line::from("Range"), Line::from(stats.map(|s| format!("{:.3}mm", s.range())).unwrap_or_else(|| "-".to_string()))]),
+        Row::new(vec![Line::from("Mean"), Line::from(mm(stats.map(|s| s.mean)))]),
+        Row::new(vec![Line::from("")]),
+    ];
+    if let Some((x, y, z)) = view.selected_point(&mesh) {
+        rows.push(Row::new(vec![Line::from("Point"), Line::from(format!("X{:.1} Y{:.1}", x, y))]));
+        rows.push(Row::new(vec![Line::from("Height"), Line::from(Span::styled(format!("{:+.3}mm", z), bold))]));
+    }
+    let table = Table::new(rows)
+        .widths(&[Constraint::Length(8), Constraint::Min(10)])
+        .block(Block::default().borders(Borders::ALL).title("Statistics"));
+    f.render_widget(table, side[0]);
+
+    let profiles: Vec<ListItem> = mesh.profiles.iter().flat_map(|p| p.keys()).map(|name| {
+        let active = profile_name.as_ref() == Some(name);
+        ListItem::new(Line::from(vec![
+            Span::raw(if active { "● " } else { "  " }),
+            Span::raw(name.clone()),
+        ]))
+    }).collect();
+    let mut state = ListState::default();
+    state.select(if profiles.is_empty() { None } else { Some(view.profile.min(profiles.len() - 1)) });
+    let list = List::new(profiles)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::default().borders(Borders::ALL).title("Profiles (PgUp/PgDn)"));
+    f.render_stateful_widget(list, side[1], &mut state);
+
+    let actions = Table::new(vec![
+        Row::new(vec![
+            Line::from(action_button(Button::new("Calibrate".to_string(), Some("C".to_string())))).alignment(Alignment::Center),
+            Line::from(action_button(Button::new("Clear".to_string(), Some("X".to_string())))).alignment(Alignment::Center),
+            Line::from(action_button(Button::new("Load".to_string(), Some("L".to_string())))).alignment(Alignment::Center),
+            Line::from(action_button(Button::new("Save".to_string(), Some("S".to_string())))).alignment(Alignment::Center),
+            Line::from(action_button(Button::new("Remove".to_string(), Some("D".to_string())))).alignment(Alignment::Center),
+            Line::from(action_button(Button::new("Leveling".to_string(), Some("T".to_string())))).alignment(Alignment::Center),
+        ]),
+    ])
+    .widths(&[
+        Constraint::Ratio(1, 6),
+        Constraint::Ratio(1, 6),
+        Constraint::Ratio(1, 6),
+        Constraint::Ratio(1, 6),
+        Constraint::Ratio(1, 6),
+        Constraint::Ratio(1, 6),
+    ]);
+    f.render_widget(actions, Rect { y: chunks[2].y + 1, height: 1, ..chunks[2] });
+
+    // Saving or removing a profile
+    if let Some(prompt) = &app.bed_mesh.prompt {
+        let (title, text) = match prompt {
+            MeshPrompt::Save(_) => ("Save mesh profile".to_string(), "Enter the name of the profile. Klipper keeps it after a SAVE_CONFIG.".to_string()),
+            MeshPrompt::Remove(name) => ("Remove mesh profile".to_string(), format!("This will remove the profile {}. Klipper forgets it after a SAVE_CONFIG.", name)),
+        };
+        let title = Paragraph::new(
+            Line::from(vec![
+                Span::styled(title, Style::default().add_modifier(Modifier::BOLD))
+            ]).alignment(Alignment::Center)
+        );
+        let text = Paragraph::new(text);
+        let input = match prompt {
+            MeshPrompt::Save(name) => Some(Paragraph::new(name.as_str())
+                .blue()
+                .block(Block::default().borders(Borders::ALL).title("Name"))),
+            MeshPrompt::Remove(_) => None,
+        };
+
+        let ok: Button = Button::new("OK".to_string(), Some("󰌑 ".to_string()));
+        let cancel = Button::new("Back".to_string(), Some("󱊷 ".to_string()));
+        let btn = Table::new(vec![
+            Row::new(vec![
+                Line::from(action_button(ok)).alignment(Alignment::Left),
+                Line::from(action_button(cancel)).alignment(Alignment::Right),
+            ])
+        ])
+        .widths(&[
+            Constraint::Percentage(50),
+            Constraint::Percentage(50),
+        ])
+        .block(Block::default()
+            .borders(Borders::NONE)
+            .padding(Padding::horizontal(2))
+        )
+        ;
+
+        let chunks = modal(f, title, text, btn, input);
+        if let MeshPrompt::Save(name) = prompt {
+            f.set_cursor(chunks[2].x + name.chars().count() as u16 + 2, chunks[2].y + 1);
+        }
+    }
+
+    let buttons = vec![
+        Button::new("Help".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Extruder".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new("Close".to_string(), Some("12".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[3], buttons);
+}
+
+
+pub fn draw_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Min(6),     // Help text
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+    let t_title = Span::styled(format!("{: ^width$}", "Bed Mesh help", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+    let p = Paragraph::new(markdown::parse(BED_MESH_HELP_TEXT))
+        .block(Block::default()
+            .title(t_title)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::NONE)
+        )
+        .wrap(Wrap { trim: false })
+        ;
+    f.render_widget(p, chunks[0]);
+
+    let buttons = vec![
+        Button::new("Close".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Extruder".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new("Bed Mesh".to_string(), Some("12".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[1], buttons);
+}
@@ -13,7 +13,7 @@ Press Enter to send the command to the printer.
 ";
 
 
-pub fn draw_tab<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -78,7 +78,7 @@ where
             f.set_cursor(
                 // Draw the cursor at the current position in the input field.
                 // This position is can be controlled via the left and right arrow key
-                div[1].x + app.console_input.cursor_position as u16 + 1,
+                div[1].x + app.console_input.cursor_position + 1,
                 // Move one line down, from the border to the input line
                 div[1].y + 1,
             )
@@ -133,7 +133,7 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Close".to_string(), Some("5".to_string())),
         Button::new("Webcam".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
     header::draw_footer(f, chunks[1], buttons);
 
@@ -142,7 +142,7 @@ where
 
 
 
-pub fn draw_help<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -175,7 +175,7 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Console".to_string(), Some("5".to_string())),
         Button::new("Webcam".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
     header::draw_footer(f, chunks[1], buttons);
 
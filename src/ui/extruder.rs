@@ -0,0 +1,148 @@
+use tui::{widgets::{Borders, Paragraph, Block, Wrap, Table, Row}, prelude::*};
+
+use super::selector;
+use crate::{ui::header, button::{Button, action_button}, markdown, app::{App, EXTRUDE_LENGTHS, EXTRUDE_SPEEDS}};
+
+const EXTRUDER_HELP_TEXT: &str = "
+# Extruder Help
+
+This screen shows the extruder temperature, its pressure advance settings and the current extrusion speed.
+Choose the length of filament with the left and right arrow keys, and the feed rate with the up and down arrow keys.
+Pressing E will extrude that length of filament, pressing R will retract it. Nothing moves until the extruder is hot enough, or while printing.
+The move is relative to the current position, and the extrusion mode of the printer is restored afterwards.
+Pressing L runs the filament load macro and pressing U the unload macro, LOAD_FILAMENT and UNLOAD_FILAMENT unless the printer profile sets others.
+";
+
+
+pub fn draw_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Min(6),     // Main content
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+    let t_title = Span::styled(format!("{: ^width$}", "Extruder", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+
+    let extruder = app.printer.extruder();
+    let can_extrude = app.can_extrude();
+    let extrude_button = Button::new("Extrude".to_string(), Some("E".to_string()));
+    let retract_button = Button::new("Retract".to_string(), Some("R".to_string()));
+    let load_button = Button::new("Load".to_string(), Some("L".to_string()));
+    let unload_button = Button::new("Unload".to_string(), Some("U".to_string()));
+    let p = Table::new(vec![
+        Row::new(vec![
+            Line::from("Temperature").alignment(Alignment::Center),
+            Line::from("Target").alignment(Alignment::Center),
+            Line::from("Pressure advance").alignment(Alignment::Center),
+            Line::from("Smooth time").alignment(Alignment::Center),
+        ]).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)),
+        Row::new(vec![
+            Line::from(Span::styled(
+                format!("{:.1}°C", extruder.temperature.unwrap_or(0.0)),
+                Style::default().fg(if can_extrude { Color::Reset } else { Color::LightBlue }),
+            )).alignment(Alignment::Center),
+            Line::from(format!("{:.0}°C", extruder.target.unwrap_or(0.0))).alignment(Alignment::Center),
+            Line::from(format!("{:.4}", extruder.pressure_advance.unwrap_or(0.0))).alignment(Alignment::Center),
+            Line::from(format!("{:.3}s", extruder.smooth_time.unwrap_or(0.0))).alignment(Alignment::Center),
+        ]),
+        Row::new(vec![Line::from("")]),
+        Row::new(vec![
+            Line::from("Velocity").alignment(Alignment::Center),
+            Line::from("Length ← →").alignment(Alignment::Center),
+            Line::from("Feed rate ↓ ↑").alignment(Alignment::Center),
+            Line::from(""),
+        ]).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)),
+        Row::new(vec![
+            Line::from(format!("{:.2}mm/s", app.printer.toolhead.extruder_velocity)).alignment(Alignment::Center),
+            selector(EXTRUDE_LENGTHS, app.extrude_length, "mm"),
+            selector(EXTRUDE_SPEEDS, app.extrude_speed, "mm/s"),
+            Line::from(""),
+        ]),
+        Row::new(vec![Line::from("")]),
+        Row::new(vec![
+            Line::from(action_button(extrude_button)).alignment(Alignment::Center),
+            Line::from(action_button(retract_button)).alignment(Alignment::Center),
+            Line::from(action_button(load_button)).alignment(Alignment::Center),
+            Line::from(action_button(unload_button)).alignment(Alignment::Center),
+        ]),
+        Row::new(vec![Line::from("")]),
+        Row::new(vec![
+            Line::from(""),
+            Line::from(if can_extrude { "" } else { "Extruder too cold to extrude" }).alignment(Alignment::Center),
+        ]).style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+    ])
+    .widths(&[
+        Constraint::Percentage(20),
+        Constraint::Percentage(35),
+        Constraint::Percentage(25),
+        Constraint::Percentage(20),
+    ])
+        .block(Block::default()
+            .title(t_title)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::NONE)
+        )
+        ;
+
+    f.render_widget(p, chunks[0]);
+
+    let buttons = vec![
+        Button::new("Help".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Close".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[1], buttons);
+
+}
+
+
+pub fn draw_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Min(6),     // Help text
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+    let t_title = Span::styled(format!("{: ^width$}", "Extruder help", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+    let p = Paragraph::new(markdown::parse(EXTRUDER_HELP_TEXT))
+        .block(Block::default()
+            .title(t_title)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::NONE)
+        )
+        .wrap(Wrap { trim: false })
+        ;
+    f.render_widget(p, chunks[0]);
+
+    let buttons = vec![
+        Button::new("Close".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Extruder".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[1], buttons);
+
+}
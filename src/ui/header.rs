@@ -1,11 +1,13 @@
 
 
+use std::time::Instant;
+
 use tui::{Frame, prelude::*, widgets::{Paragraph, Block, Borders}};
 
-use crate::{app::App, button::Button};
+use crate::{app::App, button::Button, connection::ConnectionState};
 use crate::button::footer_button;
 
-pub fn draw_header<'a, B>(frame: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_header<B>(frame: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -21,18 +23,22 @@ where
     )
     .split(area);
 
-    let mut c = " ✕ ";
-    let mut bg = Color::Red;
-    if app.printer.connected {
-    c = " ✔ ";
-    bg = Color::Green;
-    }
+    let (c, bg) = match app.connection {
+        ConnectionState::Connected => (" ✔ ".to_string(), Color::Green),
+        ConnectionState::KlippyNotReady => (" ✕ ".to_string(), Color::Red),
+        ConnectionState::Connecting => (" Connecting ".to_string(), Color::Yellow),
+        ConnectionState::Backoff { attempt, .. } => {
+            let secs = app.connection.retry_in(Instant::now()).unwrap_or_default().as_secs_f64().ceil();
+            (format!(" Retry #{} in {}s ", attempt, secs), Color::Yellow)
+        },
+        ConnectionState::AuthFailed => (" Unauthorized ".to_string(), Color::Red),
+    };
     let mut state = format!(" {} ", app.printer.status.state);
-    if app.printer.status.print_state == "printing" {
-        state = "printing".to_string();
+    if app.printer.is_printing() {
+        state = app.printer.status.print_state.clone();
     }
 
-    let (state_bg, state_fg) = match app.printer.status.state.as_str() {
+    let (state_bg, state_fg) = match state.trim() {
     "standby" => (Color::Gray, Color::LightGreen),
     "ready" => (Color::Gray, Color::LightGreen),
     "printing" => (Color::Green, Color::White),
@@ -45,28 +51,34 @@ where
     _ => (Color::Black, Color::White),
     };
     let h = app.printer.toolhead.homed.x && app.printer.toolhead.homed.y && app.printer.toolhead.homed.z;
-    let qgl = app.printer.toolhead.homed.qgl;
+    let leveled = app.printer.toolhead.homed.leveled;
     let fan = app.printer.toolhead.fan.speed;
-    let text = vec![
-        Line::from(vec![
-            Span::styled(c, Style::default().bg(bg).fg(Color::White)),
-            Span::styled(" ", Style::default().bg(Color::Black)),
-            Span::styled(format!(" {} ", state), Style::default().bg(state_bg).fg(state_fg)),
-            Span::styled(" ", Style::default().bg(Color::Black)),
-            Span::styled("Home", Style::default().fg(Color::White).bg(if h {Color::Green} else {Color::Red})),
-            Span::styled(" ", Style::default().bg(Color::Black)),
-            Span::styled("QGL", Style::default().fg(Color::White).bg(if qgl {Color::Green} else {Color::Red})),
-            Span::styled(" ", Style::default().bg(Color::Black)),
-            Span::styled("Step", Style::default().fg(Color::White).bg(if app.printer.status.stepper_enable {Color::Green} else {Color::Red})),
-            Span::styled(" ", Style::default().bg(Color::Black)),
-            Span::styled("Fil", Style::default().fg(Color::White).bg(if app.printer.status.filament_switch {Color::Green} else {Color::Red})),
-            Span::styled(" ", Style::default().bg(Color::Black)),
-            
-            Span::styled(format!("Fan {:.0}", fan*100.0), Style::default().fg(Color::White).bg(if fan < 0.3 {Color::Green} else if fan < 0.6 {Color::LightRed } else {Color::Red})),
-            
-            
-        ]),
+    let mut spans = vec![
+        Span::styled(c, Style::default().bg(bg).fg(Color::White)),
+        Span::styled(" ", Style::default().bg(Color::Black)),
+        Span::styled(format!(" {} ", state), Style::default().bg(state_bg).fg(state_fg)),
+        Span::styled(" ", Style::default().bg(Color::Black)),
+        Span::styled("Home", Style::default().fg(Color::White).bg(if h {Color::Green} else {Color::Red})),
+        Span::styled(" ", Style::default().bg(Color::Black)),
     ];
+    // Printers without gantry leveling have nothing to show
+    if let Some(leveling) = app.printer.leveling() {
+        spans.push(Span::styled(leveling.name(), Style::default().fg(Color::White).bg(if leveled {Color::Green} else {Color::Red})));
+        spans.push(Span::styled(" ", Style::default().bg(Color::Black)));
+    }
+    spans.extend([
+        Span::styled("Step", Style::default().fg(Color::White).bg(if app.printer.status.stepper_enable {Color::Green} else {Color::Red})),
+        Span::styled(" ", Style::default().bg(Color::Black)),
+        Span::styled("Fil", Style::default().fg(Color::White).bg(if app.printer.status.filament_switch {Color::Green} else {Color::Red})),
+        Span::styled(" ", Style::default().bg(Color::Black)),
+        
+        Span::styled(format!("Fan {:.0}", fan*100.0), Style::default().fg(Color::White).bg(if fan < 0.3 {Color::Green} else if fan < 0.6 {Color::LightRed } else {Color::Red})),
+    ]);
+    let mut text = vec![Line::from(spans)];
+    if let Some(notice) = &app.notice {
+        text[0].spans.push(Span::styled(" ", Style::default().bg(Color::Black)));
+        text[0].spans.push(Span::styled(format!(" {} ", notice.message), Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD)));
+    }
 
     let p = Paragraph::new(text)
         .block(Block::default()
@@ -78,7 +90,7 @@ where
     let s = vec![
         Line::from(vec![
             Span::styled(" ", Style::default().bg(Color::Black)),
-            Span::styled(app.server_url.clone(), Style::default().bg(Color::Black)),
+            Span::styled(app.server.name(), Style::default().bg(Color::Black)),
             Span::styled(" ", Style::default().bg(Color::Black)),
             Span::styled(format!(" {:.2} ", app.printer.sysload), Style::default().bg(if app.printer.sysload < 0.3 {Color::Green} else if app.printer.sysload < 0.6 {Color::LightRed } else {Color::Red}).fg(Color::White)),
         ]).alignment(Alignment::Right),
@@ -95,7 +107,7 @@ where
 
 }
 
-pub fn draw_footer<'a, B>(f: &mut Frame<B>, area: Rect, buttons: Vec<Button>)
+pub fn draw_footer<B>(f: &mut Frame<B>, area: Rect, buttons: Vec<Button>)
 where
     B: Backend,
 {
@@ -113,7 +125,7 @@ where
         .direction(Direction::Horizontal)
         .margin(0)
         .constraints(
-            constraints.as_ref(),
+            constraints.as_slice(),
         )
         .split(area);
 
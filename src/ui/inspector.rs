@@ -0,0 +1,141 @@
+use std::time::Instant;
+
+use tui::{widgets::{Borders, Paragraph, Block, Wrap, List, ListItem}, prelude::*};
+
+use crate::{ui::header, button::Button, markdown, app::App, inspector::{InspectorRow, HIGHLIGHT_DURATION}};
+
+const OBJECTS_HELP_TEXT: &str = "
+# Objects Help
+
+This tab shows every object Klipper reports, with the live value of their fields. Values that just changed are highlighted.
+Select an object with the up and down arrow keys, and press Enter to show or hide its fields.
+Press / to search objects and fields by name, Enter to stop typing and Esc to clear the search.
+";
+
+
+pub fn draw_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Max(1),     // Title
+            Constraint::Length(3),     // Search input
+            Constraint::Min(3),     // Objects
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+
+    let t_title = Span::styled(
+        format!("{: ^width$}", "Objects", width = f.size().width as usize),
+        Style::default()
+            .add_modifier(Modifier::BOLD)
+            .fg(Color::White)
+            .bg(Color::Magenta)
+    );
+    f.render_widget(Block::default().title(t_title).borders(Borders::NONE), chunks[0]);
+
+    let input = Paragraph::new(app.inspector.search.as_str())
+        .style(if app.inspector.searching { Style::default().fg(Color::Yellow) } else { Style::default() })
+        .block(Block::default().borders(Borders::ALL).title("Search (/)"));
+    f.render_widget(input, chunks[1]);
+    if app.inspector.searching {
+        f.set_cursor(chunks[1].x + app.inspector.search.chars().count() as u16 + 1, chunks[1].y + 1);
+    }
+
+    let now = Instant::now();
+    let recent = |t: &Option<Instant>| t.is_some_and(|t| now.saturating_duration_since(t) < HIGHLIGHT_DURATION);
+    let rows = app.inspector.rows(&app.printer.objects);
+    let items: Vec<ListItem> = rows.iter().enumerate().map(|(i, row)| match row {
+        InspectorRow::Object { name, expanded } => {
+            // Highlight collapsed objects when one of their fields changed
+            let changed = !expanded && app.printer.objects.get(name)
+                .and_then(|o| o.as_object())
+                .is_some_and(|fields| fields.keys().any(|field| recent(&app.printer.objects.updated_at(name, field))));
+            let style = Style::default().add_modifier(Modifier::BOLD);
+            ListItem::new(Line::from(vec![
+                Span::styled(if *expanded { "▾ " } else { "▸ " }, style),
+                Span::styled(name.clone(), if changed { style.fg(Color::Yellow) } else { style }),
+            ]))
+        },
+        InspectorRow::Field { field, value, updated_at, .. } => {
+            let last = !matches!(rows.get(i + 1), Some(InspectorRow::Field { .. }));
+            let value_style = if recent(updated_at) { Style::default().fg(Color::Black).bg(Color::Yellow) } else { Style::default() };
+            ListItem::new(Line::from(vec![
+                Span::styled(if last { "  └ " } else { "  ├ " }, Style::default().fg(Color::DarkGray)),
+                Span::styled(format!("{}: ", field), Style::default().fg(Color::Cyan)),
+                Span::styled(value.clone(), value_style),
+            ]))
+        },
+    }).collect();
+    if app.inspector.state.selected().is_some_and(|i| i >= items.len()) {
+        app.inspector.state.select(items.len().checked_sub(1));
+    }
+
+    let empty = items.is_empty();
+    let list = List::new(items)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    if empty {
+        let text = if app.inspector.search.is_empty() { "No objects yet" } else { "No object or field matches the search" };
+        f.render_widget(Paragraph::new(text).alignment(Alignment::Center), chunks[2]);
+    } else {
+        f.render_stateful_widget(list, chunks[2], &mut app.inspector.state);
+    }
+
+    let buttons = vec![
+        Button::new("Help".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Extruder".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new("Close".to_string(), Some("11".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[3], buttons);
+}
+
+
+pub fn draw_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Min(6),     // Help text
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+    let t_title = Span::styled(format!("{: ^width$}", "Objects help", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+    let p = Paragraph::new(markdown::parse(OBJECTS_HELP_TEXT))
+        .block(Block::default()
+            .title(t_title)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::NONE)
+        )
+        .wrap(Wrap { trim: false })
+        ;
+    f.render_widget(p, chunks[0]);
+
+    let buttons = vec![
+        Button::new("Close".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Extruder".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new("Objects".to_string(), Some("11".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[1], buttons);
+}
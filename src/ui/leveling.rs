@@ -0,0 +1,184 @@
+use tui::{widgets::{Borders, Paragraph, Block, Wrap, Table, Row}, prelude::*};
+
+use crate::{ui::header, button::{Button, action_button}, markdown, app::App};
+
+const LEVELING_HELP_TEXT: &str = "
+# Leveling Help
+
+This screen shows whether the gantry was leveled, and how to turn the bed screws to level the bed by hand.
+Pressing Q levels the gantry with QUAD_GANTRY_LEVEL or Z_TILT_ADJUST, depending on your printer.
+Pressing C probes the bed under each screw with SCREWS_TILT_CALCULATE, which needs a [screws_tilt_adjust] section in the Klipper config.
+
+Screws are adjusted to the base screw. CW means clockwise and CCW counter-clockwise, and 01:15 means one full turn and a quarter, like the hands of a clock.
+Turn the screws, then press C again until they are all green.
+";
+
+/// Green when the screw is close enough, red when it needs more than a quarter turn
+fn adjust_color(minutes: Option<u32>) -> Color {
+    match minutes {
+        Some(0..=5) => Color::Green,
+        Some(6..=15) => Color::Yellow,
+        Some(_) => Color::Red,
+        None => Color::Reset,
+    }
+}
+
+
+pub fn draw_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Length(4),     // Gantry
+            Constraint::Min(6),     // Screws
+            Constraint::Length(2),     // Actions
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+    let t_title = Span::styled(format!("{: ^width$}", "Leveling", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+    let bold = Style::default().fg(Color::White).add_modifier(Modifier::BOLD);
+
+    let leveling = app.printer.leveling();
+    let leveled = app.printer.toolhead.homed.leveled;
+    let gantry = Table::new(vec![
+        Row::new(vec![
+            Line::from("Gantry leveling").alignment(Alignment::Center),
+            Line::from("State").alignment(Alignment::Center),
+        ]).style(bold),
+        Row::new(vec![
+            Line::from(leveling.map_or("None", |l| l.command())).alignment(Alignment::Center),
+            match leveling {
+                Some(_) => Line::from(Span::styled(
+                    if leveled { "Applied" } else { "Not applied" },
+                    Style::default().fg(if leveled { Color::Green } else { Color::Red }),
+                )).alignment(Alignment::Center),
+                None => Line::from("-").alignment(Alignment::Center),
+            },
+        ]),
+    ])
+    .widths(&[
+        Constraint::Percentage(50),
+        Constraint::Percentage(50),
+    ])
+        .block(Block::default()
+            .title(t_title)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::NONE)
+        )
+        ;
+    f.render_widget(gantry, chunks[0]);
+
+    let screws = app.printer.screws_tilt_adjust();
+    let results = screws.results.clone().unwrap_or_default();
+    let block = Block::default().borders(Borders::ALL).title("Bed screws");
+    if !app.available_objects.is_empty() && !app.available_objects.iter().any(|o| o == "screws_tilt_adjust") {
+        f.render_widget(Paragraph::new("Add a [screws_tilt_adjust] section to the Klipper config to level the bed screws from here").alignment(Alignment::Center).wrap(Wrap { trim: false }).block(block), chunks[1]);
+    } else if results.is_empty() {
+        f.render_widget(Paragraph::new("Press C to probe the screws with SCREWS_TILT_CALCULATE").alignment(Alignment::Center).wrap(Wrap { trim: false }).block(block), chunks[1]);
+    } else {
+        let mut rows = vec![
+            Row::new(vec![
+                Line::from("Screw"),
+                Line::from("Z").alignment(Alignment::Center),
+                Line::from("Adjust").alignment(Alignment::Center),
+            ]).style(bold),
+        ];
+        for (key, screw) in results.iter() {
+            let adjust = if screw.is_base == Some(true) {
+                Span::styled("base", Style::default().add_modifier(Modifier::ITALIC))
+            } else {
+                Span::styled(screw.turn(), Style::default().fg(adjust_color(screw.minutes())).add_modifier(Modifier::BOLD))
+            };
+            rows.push(Row::new(vec![
+                Line::from(screw.name.clone().unwrap_or_else(|| key.clone())),
+                Line::from(screw.z.map(|z| format!("{:.4}mm", z)).unwrap_or_default()).alignment(Alignment::Center),
+                Line::from(adjust).alignment(Alignment::Center),
+            ]));
+        }
+        if screws.error == Some(true) {
+            rows.push(Row::new(vec![Line::from("")]));
+            rows.push(Row::new(vec![
+                Line::from(format!("A screw is off by more than {:.3}mm", screws.max_deviation.unwrap_or_default())),
+            ]).style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)));
+        }
+        let table = Table::new(rows)
+            .widths(&[
+                Constraint::Percentage(40),
+                Constraint::Percentage(30),
+                Constraint::Percentage(30),
+            ])
+            .block(block);
+        f.render_widget(table, chunks[1]);
+    }
+
+    let mut actions = vec![
+        Line::from(action_button(Button::new("Screws tilt".to_string(), Some("C".to_string())))).alignment(Alignment::Center),
+    ];
+    if let Some(leveling) = leveling {
+        actions.push(Line::from(action_button(Button::new(leveling.name().to_string(), Some("Q".to_string())))).alignment(Alignment::Center));
+    }
+    let actions = Table::new(vec![Row::new(actions)])
+        .widths(&[
+            Constraint::Percentage(50),
+            Constraint::Percentage(50),
+        ]);
+    f.render_widget(actions, Rect { y: chunks[2].y + 1, height: 1, ..chunks[2] });
+
+    let buttons = vec![
+        Button::new("Help".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Extruder".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new("Bed Mesh".to_string(), Some("12".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[3], buttons);
+}
+
+
+pub fn draw_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Min(6),     // Help text
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+    let t_title = Span::styled(format!("{: ^width$}", "Leveling help", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+    let p = Paragraph::new(markdown::parse(LEVELING_HELP_TEXT))
+        .block(Block::default()
+            .title(t_title)
+            .title_alignment(Alignment::Center)
+            .borders(Borders::NONE)
+        )
+        .wrap(Wrap { trim: false })
+        ;
+    f.render_widget(p, chunks[0]);
+
+    let buttons = vec![
+        Button::new("Close".to_string(), Some("1".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Toolhead".to_string(), Some("3".to_string())),
+        Button::new("Extruder".to_string(), Some("4".to_string())),
+        Button::new("Console".to_string(), Some("5".to_string())),
+        Button::new("Webcam".to_string(), Some("6".to_string())),
+        Button::new("Bed Mesh".to_string(), Some("12".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+    ];
+    header::draw_footer(f, chunks[1], buttons);
+}
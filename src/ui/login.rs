@@ -0,0 +1,67 @@
+use tui::{widgets::{Paragraph, Block, Borders, Table, Row, Padding, Wrap}, prelude::*};
+
+use crate::{app::App, auth::LoginField, button::{Button, action_button}};
+use super::modal;
+
+/// Dialog asking for the Moonraker user and password
+pub fn draw_login_modal<B>(f: &mut Frame<B>, app: &mut App)
+where
+    B: Backend,
+{
+    let form = match &app.login {
+        Some(form) => form,
+        None => return,
+    };
+
+    let title = Paragraph::new(
+        Line::from(vec![
+            Span::styled("Login to ", Style::default()),
+            Span::styled(app.server.name(), Style::default().add_modifier(Modifier::BOLD)),
+        ]).alignment(Alignment::Center)
+    ).wrap(Wrap {trim: false});
+
+    let mut lines = vec![
+        Line::from("Moonraker requires a login for this printer."),
+    ];
+    if form.field == LoginField::Password {
+        lines.push(Line::from(vec![
+            Span::styled("User: ", Style::default()),
+            Span::styled(form.username.value.clone(), Style::default().add_modifier(Modifier::BOLD)),
+        ]));
+    }
+    if let Some(error) = &form.error {
+        lines.push(Line::from(Span::styled(error.clone(), Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))));
+    }
+    let text = Paragraph::new(lines);
+
+    let (label, value, cursor) = match form.field {
+        LoginField::Username => ("Username", form.username.value.clone(), form.username.cursor_position),
+        LoginField::Password => ("Password", "*".repeat(form.password.value.chars().count()), form.password.cursor_position),
+    };
+    let input = Paragraph::new(value)
+        .style(Style::default().fg(Color::Blue))
+        .block(Block::default().borders(Borders::ALL).title(label));
+
+    let ok: Button = Button::new("Login".to_string(), Some("󰌑 ".to_string()));
+    let next = Button::new(if form.field == LoginField::Username { "Password".to_string() } else { "User".to_string() }, Some("󰌒 ".to_string()));
+    let btn = Table::new(vec![
+        Row::new(vec![
+            Line::from(action_button(ok)).alignment(Alignment::Left),
+            Line::from(action_button(next)).alignment(Alignment::Right),
+        ])
+    ])
+    .widths(&[
+        Constraint::Percentage(50),
+        Constraint::Percentage(50),
+    ])
+    .block(Block::default()
+        .borders(Borders::NONE)
+        .padding(Padding::horizontal(2))
+    );
+
+    let chunks = modal(f, title, text, btn, Some(input));
+    f.set_cursor(
+        chunks[2].x + cursor + 1,
+        chunks[2].y + 1,
+    );
+}
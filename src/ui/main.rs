@@ -5,7 +5,7 @@ use chrono::{DateTime, Local};
 use tui::{Frame, prelude::*, widgets::{Paragraph, Block, Borders, Wrap, ListItem, List, Table, Row, Padding}};
 
 
-use crate::{app::{App, InputMode, HistoryItem}, button::{Button, action_button}, printer::{Heater, HeaterType}};
+use crate::{app::{App, InputMode, HistoryItem, PrintAction}, button::{Button, action_button}, printer::{Heater, HeaterType}};
 use crate::markdown;
 use crate::ui::header;
 use viuer::{print_from_file, Config};
@@ -30,13 +30,29 @@ Pressing <Enter> on a selected heater will open a dialog to set the target tempe
 The panel displayed while printing, shows the name of the current file, the print progress and an estimation of the ETA for the print.
 The current layer, the toolhead speed, the filament used and the flow are also displayed.
 You will also be able to see the print preview if one is available for the file being printed.
+Press P to pause the print, R to resume it and C to cancel it. Each of them asks for confirmation first.
+
+## Tuning
+While printing, [ and ] lower and raise the speed factor by 5%, { and } the flow factor by 1%.
+Press - and + to move the nozzle down and up by 0.01mm, and O to store the resulting Z offset in the probe or endstop config, and run SAVE_CONFIG from the console to keep it.
 
 You can press F10 at any time to trigger an emergency stop. This will stop the print and disconnect from the printer.
+
+## Several printers
+When krui is connected to several printers, a line for each of them at the top shows its state, progress and temperatures. Press F7 and F8 to switch to the previous or next printer.
+F9 opens the overview of all printers, with their progress and ETA. Select a printer with the arrow keys and press Enter to open it.
+
+## Objects
+F11 opens a live view of every object Klipper reports, such as `gcode_move`, `idle_timeout` or the MCUs, with search. Handy when debugging macros and configs.
+
+## Bed mesh
+F12 shows the bed mesh as a heatmap, and lets you probe the bed and manage the mesh profiles.
+Press T there to level the gantry and see how to turn the bed screws, from SCREWS_TILT_CALCULATE.
 ";
 
 
 
-pub fn draw_main_help<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_main_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -70,14 +86,14 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Console".to_string(), Some("5".to_string())),
         Button::new("Webcam".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
     header::draw_footer(f, chunks[1], buttons);
 
 }
 
 
-pub fn draw_main_tab<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_main_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -97,8 +113,10 @@ where
         )
         .split(area);
 
+    let paused = app.printer.status.print_state == "paused";
+    let printing = paused || app.printer.status.print_state == "printing";
     // only show history if not printing
-    if app.printer.status.print_state != "printing" {
+    if !printing {
         let mut v = app.history.items.clone();
         v.sort_by(|a, b| { b.end_time.total_cmp(&a.end_time)});
 
@@ -120,9 +138,9 @@ where
         f.render_stateful_widget(p, chunks[0], &mut app.history.state);
     } else {
         let fl = if app.printer.toolhead.extruder_velocity > 0.0 { app.printer.toolhead.extruder_velocity } else { 0.0 };
-        let flow = fl * (1.75/2.0)*(1.75/2.0) * 3.14159;
-        let mut layer = 0;
-        let mut total_layers = 0;
+        let flow = fl * (app.filament_diameter/2.0)*(app.filament_diameter/2.0) * std::f64::consts::PI;
+        let mut layer = None;
+        let mut total_layers = None;
 
 
         let mut total_duration = 0.0;
@@ -134,14 +152,15 @@ where
         let mut eta = SystemTime::now();
         let mut speed = 0.0;
 
-        // TODO handle print paused
         if let Some(current_print) = &app.printer.current_print {
-            layer = if current_print.current_layer > 0 { current_print.current_layer } else { 
-                ((app.printer.toolhead.position.z - current_print.file.first_layer_height as f64) / current_print.file.layer_height as f64 + 1.0).ceil() as i64
-            };
-            total_layers = if current_print.total_layers > 0 { current_print.total_layers } else { 
-                ((current_print.file.object_height - current_print.file.first_layer_height as f64) / current_print.file.layer_height as f64 + 1.0).ceil() as i64
-            };
+            // Without a layer height from the slicer the layers can't be computed
+            let layer_height = current_print.file.layer_height;
+            layer = if current_print.current_layer > 0 { Some(current_print.current_layer) } else if layer_height > 0.0 {
+                Some(((app.printer.toolhead.position.z - current_print.file.first_layer_height) / layer_height + 1.0).ceil() as i64)
+            } else { None };
+            total_layers = if current_print.total_layers > 0 { Some(current_print.total_layers) } else if layer_height > 0.0 {
+                Some(((current_print.file.object_height - current_print.file.first_layer_height) / layer_height + 1.0).ceil() as i64)
+            } else { None };
 
             speed = app.printer.toolhead.speed;
 
@@ -164,17 +183,30 @@ where
                 transparent: false,
                 ..Default::default()
             };
-        
-        
-            match print_from_file(current_print.image.clone(), &conf) {
-                Ok(_) => {},
-                Err(_) => {},
-            };
+
+            // The thumbnail is only known once downloaded
+            if !current_print.image.is_empty() {
+                if let Err(e) = print_from_file(current_print.image.as_str(), &conf) {
+                    log::error!("Error showing thumbnail {:?}", e);
+                }
+            }
         }
         let datetime: DateTime<Local> = eta.into();
 
 
-        let t_title = Span::styled(format!("{: ^width$}", format!("Printing {} ({:.0}%)", filename, progress*100.0), width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
+        let t_title = if paused {
+            Span::styled(format!("{: ^width$}", format!("Paused {} ({:.0}%)", filename, progress*100.0), width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::Black).bg(Color::LightCyan))
+        } else {
+            Span::styled(format!("{: ^width$}", format!("Printing {} ({:.0}%)", filename, progress*100.0), width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta))
+        };
+        let pause_button = if paused {
+            Button::new("Resume".to_string(), Some("R".to_string()))
+        } else {
+            Button::new("Pause".to_string(), Some("P".to_string()))
+        };
+        let cancel_button = Button::new("Cancel".to_string(), Some("C".to_string()));
+        let save_button = Button::new("Save Z".to_string(), Some("O".to_string()));
+        let gcode_move = app.printer.gcode_move();
 
 
         
@@ -186,7 +218,10 @@ where
                 Line::from("Flow").alignment(Alignment::Center),
             ]).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)),
             Row::new(vec![
-                Line::from(format!("{}/{} ", layer, total_layers)).alignment(Alignment::Center),
+                Line::from(match (layer, total_layers) {
+                    (Some(layer), Some(total_layers)) => format!("{}/{} ", layer, total_layers),
+                    _ => "-".to_string(),
+                }).alignment(Alignment::Center),
                 Line::from(format!("{:.0}mm/s", speed)).alignment(Alignment::Center),
                 Line::from(format!("{:.0}mm", filament_used)).alignment(Alignment::Center),
                 Line::from(format!("{:.1}mm3/s", flow)).alignment(Alignment::Center),
@@ -199,10 +234,29 @@ where
                 Line::from("ETA").alignment(Alignment::Center),
             ]).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)),
             Row::new(vec![
-                Line::from(format!("{}", time_string_from_seconds(estimate.round() as i64))).alignment(Alignment::Center),
-                Line::from(format!("{}", time_string_from_seconds(slicer_estimate.round() as i64))).alignment(Alignment::Center),
-                Line::from(format!("{}", time_string_from_seconds(total_duration.round() as i64))).alignment(Alignment::Center),
-                Line::from(format!("{}", datetime.format("%H:%M"))).alignment(Alignment::Center),
+                Line::from(time_string_from_seconds(estimate.round() as i64).to_string()).alignment(Alignment::Center),
+                Line::from(time_string_from_seconds(slicer_estimate.round() as i64).to_string()).alignment(Alignment::Center),
+                Line::from(time_string_from_seconds(total_duration.round() as i64).to_string()).alignment(Alignment::Center),
+                Line::from(if paused { "-".to_string() } else { format!("{}", datetime.format("%H:%M")) }).alignment(Alignment::Center),
+            ]),
+            Row::new(vec![
+                Line::from("Speed [ ]").alignment(Alignment::Center),
+                Line::from("Flow { }").alignment(Alignment::Center),
+                Line::from("Z offset - +").alignment(Alignment::Center),
+                Line::from(""),
+            ]).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)),
+            Row::new(vec![
+                Line::from(format!("{:.0}%", gcode_move.speed_factor.unwrap_or(1.0) * 100.0)).alignment(Alignment::Center),
+                Line::from(format!("{:.0}%", gcode_move.extrude_factor.unwrap_or(1.0) * 100.0)).alignment(Alignment::Center),
+                Line::from(format!("{:+.3}mm", gcode_move.z_offset())).alignment(Alignment::Center),
+                Line::from(action_button(save_button)).alignment(Alignment::Center),
+            ]),
+            Row::new(vec![Line::from("")]),
+            Row::new(vec![
+                Line::from(""),
+                Line::from(action_button(pause_button)).alignment(Alignment::Center),
+                Line::from(action_button(cancel_button)).alignment(Alignment::Center),
+                Line::from(""),
             ]),
         ])
         .widths(&[
@@ -310,7 +364,7 @@ where
             InputMode::Normal => {}
             InputMode::Editing => {
                 f.set_cursor(
-                    chunks[2].x + app.temperature_input.cursor_position as u16 + 1,
+                    chunks[2].x + app.temperature_input.cursor_position + 1,
                     chunks[2].y + 1,
                 )
             }
@@ -319,7 +373,7 @@ where
 
 
     // Starting a print
-    if app.printer.will_print_file != None && app.printer.status.print_state != "printing".to_string() {
+    if app.printer.will_print_file.is_some() && !printing {
         let title = Paragraph::new(
             Line::from(vec![
                 Span::styled("Confirm print start", Style::default().add_modifier(Modifier::BOLD))
@@ -336,7 +390,7 @@ where
             ]),
             Line::from(vec![
                 Span::styled("Estimated time is ", Style::default()),
-                Span::styled(format!("{}", time_string_from_seconds(app.printer.will_print_file.clone().unwrap().estimated_time.round() as i64)), Style::default().add_modifier(Modifier::BOLD)),
+                Span::styled(time_string_from_seconds(app.printer.will_print_file.clone().unwrap().estimated_time.round() as i64).to_string(), Style::default().add_modifier(Modifier::BOLD)),
             ]),
         ]
         );
@@ -362,6 +416,43 @@ where
         modal(f, title, text, btn, None);
     }
 
+    // Pausing, resuming or cancelling the print
+    if let Some(action) = app.print_action.filter(|a| a.allowed(app.printer.status.print_state.as_str())) {
+        let filename = app.printer.current_print.as_ref().map(|p| p.filename.clone()).unwrap_or_default();
+        let title = Paragraph::new(
+            Line::from(vec![
+                Span::styled(format!("Confirm print {}", action.name()), Style::default().add_modifier(Modifier::BOLD))
+            ]).alignment(Alignment::Center)
+        );
+        let text = Paragraph::new(vec![
+            Line::from(vec![
+                Span::styled(format!("This will {} the print of ", action.name()), Style::default()),
+                Span::styled(filename, Style::default().add_modifier(Modifier::BOLD)),
+            ]),
+            Line::from(if action == PrintAction::Cancel { "It can't be resumed afterwards." } else { "" }),
+        ]);
+
+        let ok: Button = Button::new("OK".to_string(), Some("󰌑 ".to_string()));
+        let cancel = Button::new("Back".to_string(), Some("󱊷 ".to_string()));
+        let btn = Table::new(vec![
+            Row::new(vec![
+                Line::from(action_button(ok)).alignment(Alignment::Left),
+                Line::from(action_button(cancel)).alignment(Alignment::Right),
+            ])
+        ])
+        .widths(&[
+            Constraint::Percentage(50),
+            Constraint::Percentage(50),
+        ])
+        .block(Block::default()
+            .borders(Borders::NONE)
+            .padding(Padding::horizontal(2))
+        )
+        ;
+
+        modal(f, title, text, btn, None);
+    }
+
     let buttons = vec![
         Button::new("Help".to_string(), Some("1".to_string())),
         Button::new("Quit".to_string(), Some("2".to_string())),
@@ -369,7 +460,7 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Console".to_string(), Some("5".to_string())),
         Button::new("Webcam".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
     header::draw_footer(f, chunks[3], buttons);
     
@@ -401,9 +492,9 @@ fn render_history<'a>(i: usize, item: &HistoryItem, area: Rect, app: &mut App) -
         vec![
             Line::from(
                 vec![
-                    Span::styled(format!("{}", item.filename), Style::default().add_modifier(Modifier::BOLD).fg(fg).bg(bg)),
+                    Span::styled(item.filename.to_string(), Style::default().add_modifier(Modifier::BOLD).fg(fg).bg(bg)),
                     Span::styled(" ".repeat(area.width as usize - 3 - item.filename.len()), Style::default().fg(fg).bg(bg)),
-                    Span::styled(format!("{}", status), Style::default().bg(status_bg).fg(Color::White)),
+                    Span::styled(status.to_string(), Style::default().bg(status_bg).fg(Color::White)),
                 ]
             ),
             Line::from(
@@ -415,7 +506,7 @@ fn render_history<'a>(i: usize, item: &HistoryItem, area: Rect, app: &mut App) -
     )
 }
 
-fn time_string_from_seconds(seconds: i64) -> String {
+pub fn time_string_from_seconds(seconds: i64) -> String {
     let (hours, remainder) = (seconds / (60*60), seconds % (60*60));
     let (minutes, seconds) = (remainder / 60, remainder % 60);
     let mut time_str = format!("{}m{:0>2}s", minutes, seconds);
@@ -442,8 +533,8 @@ fn heater_text<'a>(heater: Heater, selected: bool, width: u16) -> ListItem<'a> {
         Line::from(vec![
             Span::styled("[", Style::default().fg(fg).bg(bg).add_modifier(Modifier::BOLD)),
             Span::styled(format!("{: <15.15}", "|".repeat(pow)), Style::default().add_modifier(Modifier::BOLD).fg(Color::Indexed(40)).bg(bg)),
-            Span::styled(format!("{: <15.15}", "|".repeat(if pow > 15 {pow-15} else {0})), Style::default().fg(Color::Indexed(214)).bg(bg).add_modifier(Modifier::BOLD)),
-            Span::styled(format!("{: <10.10}", "|".repeat(if pow > 30 {pow-30} else {0})), Style::default().fg(Color::Indexed(196)).bg(bg).add_modifier(Modifier::BOLD)),
+            Span::styled(format!("{: <15.15}", "|".repeat(pow.saturating_sub(15))), Style::default().fg(Color::Indexed(214)).bg(bg).add_modifier(Modifier::BOLD)),
+            Span::styled(format!("{: <10.10}", "|".repeat(pow.saturating_sub(30))), Style::default().fg(Color::Indexed(196)).bg(bg).add_modifier(Modifier::BOLD)),
             Span::styled("]", Style::default().fg(fg).bg(bg).add_modifier(Modifier::BOLD)),
         ]).alignment(Alignment::Center),
     ]);
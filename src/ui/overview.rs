@@ -0,0 +1,108 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use tui::{Frame, prelude::*, widgets::{Block, Borders, Row, Table}};
+
+use crate::{button::Button, farm::Farm, ui::header};
+use super::main::time_string_from_seconds;
+use super::printers::{heater_label, state_label};
+
+/// Width of the progress bars, in characters
+const BAR_WIDTH: usize = 20;
+
+/// Every printer of the farm, with the state of its print
+pub fn draw_tab<B>(f: &mut Frame<B>, farm: &mut Farm, area: Rect)
+where
+    B: Backend,
+{
+    let chunks = Layout::default()
+    .direction(Direction::Vertical)
+    .margin(0)
+    .constraints(
+        [
+            Constraint::Min(6),     // Tab content
+            Constraint::Max(1),     // Tab Footer
+        ]
+        .as_ref(),
+    )
+    .split(area);
+
+    let mut title = format!("Farm ({} printers)", farm.apps.len());
+    // Alerts from the printers would go unnoticed otherwise
+    if let Some(notice) = &farm.current().notice {
+        title = format!("{} - {}", title, notice.message);
+    }
+    let t_title = Span::styled(
+        format!("{: ^width$}", title, width = f.size().width as usize),
+        Style::default()
+            .add_modifier(Modifier::BOLD)
+            .fg(Color::White)
+            .bg(if farm.current().notice.is_some() { Color::Red } else { Color::Magenta })
+    );
+
+    let header_row = Row::new(vec!["", "Printer", "State", "File", "Progress", "ETA", "Extruder", "Bed"])
+        .style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD));
+
+    let rows: Vec<Row> = farm.apps.iter().enumerate().map(|(i, app)| {
+        let (state, color) = state_label(app);
+        let (filename, progress, eta) = match &app.printer.current_print {
+            Some(p) => {
+                let eta: DateTime<Local> = (SystemTime::now() + std::time::Duration::from_secs(p.time_left().round() as u64)).into();
+                (
+                    p.filename.clone(),
+                    progress_bar(p.progress),
+                    format!("{} ({})", eta.format("%H:%M"), time_string_from_seconds(p.time_left().round() as i64)),
+                )
+            },
+            None => ("".to_string(), "".to_string(), "".to_string()),
+        };
+        let style = if i == farm.current {
+            Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        Row::new(vec![
+            Line::from(format!(" {}", i + 1)),
+            Line::from(app.name.clone()),
+            Line::from(Span::styled(state, Style::default().fg(color))),
+            Line::from(filename),
+            Line::from(Span::styled(progress, Style::default().fg(Color::Green))),
+            Line::from(eta),
+            Line::from(heater_label(app, "extruder")),
+            Line::from(heater_label(app, "heater_bed")),
+        ]).style(style).height(2)
+    }).collect();
+
+    let table = Table::new(rows)
+        .header(header_row.bottom_margin(1))
+        .widths(&[
+            Constraint::Length(3),
+            Constraint::Percentage(20),
+            Constraint::Length(12),
+            Constraint::Percentage(25),
+            Constraint::Length(BAR_WIDTH as u16 + 5),
+            Constraint::Length(15),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ])
+        .block(Block::default()
+            .title(t_title)
+            .borders(Borders::NONE)
+        );
+
+    f.render_widget(table, chunks[0]);
+
+    let buttons = vec![
+        Button::new("Open".to_string(), Some("󰌑 ".to_string())),
+        Button::new("Quit".to_string(), Some("2".to_string())),
+        Button::new("Close".to_string(), Some("9".to_string())),
+    ];
+    header::draw_footer(f, chunks[1], buttons);
+}
+
+/// Text progress bar followed by the percentage, like `██████░░░░ 60%`
+pub fn progress_bar(progress: f64) -> String {
+    let progress = progress.clamp(0.0, 1.0);
+    let done = (progress * BAR_WIDTH as f64).round() as usize;
+    format!("{}{} {:.0}%", "█".repeat(done), "░".repeat(BAR_WIDTH - done), progress * 100.0)
+}
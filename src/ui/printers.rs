@@ -0,0 +1,75 @@
+use tui::{Frame, prelude::*, widgets::{Row, Table}};
+
+use crate::{app::App, connection::ConnectionState, farm::Farm};
+
+/// State of a printer in a few words, and the color to show it with
+pub fn state_label(app: &App) -> (String, Color) {
+    match app.connection {
+        ConnectionState::Connecting => ("connecting".to_string(), Color::Yellow),
+        ConnectionState::Backoff { .. } => ("offline".to_string(), Color::Red),
+        ConnectionState::AuthFailed => ("unauthorized".to_string(), Color::Red),
+        _ => {
+            let status = &app.printer.status;
+            match status.print_state.as_str() {
+                "printing" => ("printing".to_string(), Color::Green),
+                "paused" => ("paused".to_string(), Color::LightCyan),
+                "error" => ("error".to_string(), Color::Red),
+                _ if status.state == "shutdown" || status.state == "error" => (status.state.clone(), Color::Red),
+                _ => (status.state.clone(), Color::Gray),
+            }
+        },
+    }
+}
+
+/// Current and target temperature of the heater called `name`, as `215/215°`
+pub fn heater_label(app: &App, name: &str) -> String {
+    match app.printer.status.heaters.items.iter().find(|h| h.name == name) {
+        Some(h) => format!("{:.0}/{:.0}°", h.temperature, h.target),
+        None => "-".to_string(),
+    }
+}
+
+/// Print progress in percent, when printing
+pub fn progress_label(app: &App) -> String {
+    match &app.printer.current_print {
+        Some(p) => format!("{:.0}%", p.progress * 100.0),
+        None => "".to_string(),
+    }
+}
+
+/// One line per printer, to keep an eye on the others
+pub fn draw_printer_rows<B>(f: &mut Frame<B>, farm: &Farm, area: Rect)
+where
+    B: Backend,
+{
+    if area.height == 0 {
+        return;
+    }
+    let rows: Vec<Row> = farm.apps.iter().enumerate().map(|(i, app)| {
+        let (state, color) = state_label(app);
+        let style = if i == farm.current {
+            Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        Row::new(vec![
+            Line::from(format!(" {} ", i + 1)),
+            Line::from(app.name.clone()),
+            Line::from(Span::styled(state, Style::default().fg(color))),
+            Line::from(progress_label(app)).alignment(Alignment::Right),
+            Line::from(format!("E {}", heater_label(app, "extruder"))),
+            Line::from(format!("B {}", heater_label(app, "heater_bed"))),
+        ]).style(style)
+    }).collect();
+
+    let table = Table::new(rows)
+        .widths(&[
+            Constraint::Length(3),
+            Constraint::Min(20),
+            Constraint::Length(12),
+            Constraint::Length(5),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ]);
+    f.render_widget(table, area);
+}
@@ -1,6 +1,9 @@
-use tui::{widgets::{Borders, Paragraph, Block, Wrap, Table, Row}, prelude::*};
+use std::time::Instant;
 
-use crate::{ui::header, button::{Button, action_button}, markdown, app::App};
+use tui::{widgets::{Borders, Paragraph, Block, Wrap, Table, Row, Padding, canvas::{Canvas, Rectangle, Line as CanvasLine}}, prelude::*, symbols::Marker};
+
+use super::{modal, selector};
+use crate::{ui::header, button::{Button, action_button}, markdown, app::{App, ToolheadAction, JOG_STEPS, JOG_SPEEDS}, klipper, printer::TRAIL_DURATION};
 
 const TOOLHEAD_HELP_TEXT: &str = "
 # Toolhead Help
@@ -8,12 +11,20 @@ const TOOLHEAD_HELP_TEXT: &str = "
 This screen presents a list of all axis present on the printer.
 Pressing X will home the X axis, pressing Y will home the Y axis, and pressing Z will home the Z axis.
 Pressing A will home all axes.
-Pressing Q will trigger a quad gantry leveling operation if available on your printer.
+Pressing Q will level the gantry with QUAD_GANTRY_LEVEL or Z_TILT_ADJUST, depending on your printer.
+
+Move the toolhead with the arrow keys for X and Y, and Page Up and Page Down for Z. Homed axes only move, and never past their limits.
+Pressing S changes the distance of each move and pressing F the feed rate, in mm/s.
+Pressing M turns the motors off with M84, the axes must be homed again before moving.
+Pressing P resets the position: G92 makes the current position X0 Y0 Z0 of the gcode coordinates. It only sets an offset, the axes are not homed again.
+Both ask for a confirmation first, and the toolhead can't be moved while printing.
+
+The bed map below shows where the nozzle is, with its path over the last few seconds, and the gauge on its right the height of the Z axis.
 
 ";
 
 
-pub fn draw_toolhead_tab<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_toolhead_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -23,7 +34,8 @@ where
     .margin(0)
     .constraints(
         [
-            Constraint::Min(6),     // Main content
+            Constraint::Length(9),     // Axes
+            Constraint::Min(6),     // Bed map
             Constraint::Max(1),     // Tab Footer
         ]
         .as_ref(),
@@ -31,43 +43,74 @@ where
     .split(area);
     let t_title = Span::styled(format!("{: ^width$}", "Toolhead", width = f.size().width as usize), Style::default().add_modifier(Modifier::BOLD).fg(Color::White).bg(Color::Magenta));
         
-    let x_button = Button::new("Home".to_string(), Some("X".to_string()));
-    let y_button = Button::new("Home".to_string(), Some("Y".to_string()));
-    let z_button = Button::new("Home".to_string(), Some("Z".to_string()));
-    let p = Table::new(vec![
+    let toolhead = app.printer.objects.parse::<klipper::Toolhead>("toolhead").unwrap_or_default();
+    let gcode_position = app.printer.gcode_move().gcode_position.unwrap_or_default();
+    let limits = |axis: usize| match (toolhead.axis_minimum.as_ref().and_then(|m| m.get(axis)), toolhead.axis_maximum.as_ref().and_then(|m| m.get(axis))) {
+        (Some(min), Some(max)) => format!("{:.0} to {:.0}mm", min, max),
+        _ => "-".to_string(),
+    };
+    let position = &app.printer.toolhead.position;
+    let homed = &app.printer.toolhead.homed;
+    let axes = [
+        ("X", position.x, homed.x, "←/→"),
+        ("Y", position.y, homed.y, "↓/↑"),
+        ("Z", position.z, homed.z, "PgDn/PgUp"),
+    ];
+
+    let mut rows = vec![
         Row::new(vec![
             Line::from("Axis").alignment(Alignment::Center),
             Line::from("Position").alignment(Alignment::Center),
+            Line::from("Gcode position").alignment(Alignment::Center),
+            Line::from("Limits").alignment(Alignment::Center),
             Line::from("Homed").alignment(Alignment::Center),
+            Line::from("Jog").alignment(Alignment::Center),
             Line::from("").alignment(Alignment::Center),
         ]).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)),
+    ];
+    for (i, (name, position, homed, keys)) in axes.iter().enumerate() {
+        let home_button = Button::new("Home".to_string(), Some(name.to_string()));
+        rows.push(Row::new(vec![
+            Line::from(Span::styled(*name, Style::default().fg(Color::White).add_modifier(Modifier::BOLD))).alignment(Alignment::Center),
+            Line::from(if i == 2 { format!("{:.3}mm", position) } else { format!("{:.2}mm", position) }).alignment(Alignment::Center),
+            Line::from(gcode_position.get(i).map(|p| format!("{:.2}mm", p)).unwrap_or_default()).alignment(Alignment::Center),
+            Line::from(limits(i)).alignment(Alignment::Center),
+            Line::from(format!("{}", homed)).alignment(Alignment::Center),
+            Line::from(*keys).alignment(Alignment::Center),
+            Line::from(action_button(home_button)).alignment(Alignment::Center),
+        ]).style(Style::default()));
+    }
+    rows.push(Row::new(vec![Line::from("")]));
+    rows.push(Row::new(vec![
+        Line::from(""),
+        Line::from("Step (S)").alignment(Alignment::Center),
+        selector(JOG_STEPS, app.jog_step, "mm"),
+        Line::from(""),
+        Line::from("Feed rate (F)").alignment(Alignment::Center),
+        selector(JOG_SPEEDS, app.jog_speed, ""),
+    ]).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)));
+    rows.push(Row::new(vec![Line::from("")]));
+    rows.push(Row::new(vec![
+        Line::from(""),
+        Line::from(action_button(Button::new("Home all".to_string(), Some("A".to_string())))).alignment(Alignment::Center),
+        match app.printer.leveling() {
+            Some(leveling) => Line::from(action_button(Button::new(leveling.name().to_string(), Some("Q".to_string())))).alignment(Alignment::Center),
+            None => Line::from(""),
+        },
+        Line::from(""),
+        Line::from(action_button(Button::new("Motors off".to_string(), Some("M".to_string())))).alignment(Alignment::Center),
+        Line::from(action_button(Button::new("Reset position".to_string(), Some("P".to_string())))).alignment(Alignment::Center),
+    ]));
 
-        Row::new(vec![
-            Line::from(Span::styled("X", Style::default().fg(Color::White).add_modifier(Modifier::BOLD))).alignment(Alignment::Center),
-            Line::from(format!("{:.2}mm", app.printer.toolhead.position.x)).alignment(Alignment::Center),
-            Line::from(format!("{}", app.printer.toolhead.homed.x)).alignment(Alignment::Center),
-            Line::from(action_button(x_button)).alignment(Alignment::Center),
-        ]).style(Style::default()),
-        Row::new(vec![
-            Line::from(Span::styled("Y", Style::default().fg(Color::White).add_modifier(Modifier::BOLD))).alignment(Alignment::Center),
-            Line::from(format!("{:.2}mm", app.printer.toolhead.position.y)).alignment(Alignment::Center),
-            Line::from(format!("{}", app.printer.toolhead.homed.y)).alignment(Alignment::Center),
-            Line::from(action_button(y_button)).alignment(Alignment::Center),
-        ]).style(Style::default()),
-        Row::new(vec![
-            Line::from(Span::styled("Z", Style::default().fg(Color::White).add_modifier(Modifier::BOLD))).alignment(Alignment::Center),
-            Line::from(format!("{:.3}mm", app.printer.toolhead.position.z)).alignment(Alignment::Center),
-            Line::from(format!("{}", app.printer.toolhead.homed.z)).alignment(Alignment::Center),
-            Line::from(action_button(z_button)).alignment(Alignment::Center),
-        ]).style(Style::default()),
-        
-    ])
+    let p = Table::new(rows)
     .widths(&[
-        Constraint::Percentage(25),
-        Constraint::Percentage(25),
-        Constraint::Percentage(25),
-        Constraint::Percentage(25),
-
+        Constraint::Percentage(8),
+        Constraint::Percentage(14),
+        Constraint::Percentage(22),
+        Constraint::Percentage(14),
+        Constraint::Percentage(14),
+        Constraint::Percentage(16),
+        Constraint::Percentage(12),
     ])
         .block(Block::default()
             .title(t_title)
@@ -78,6 +121,40 @@ where
         ;
 
     f.render_widget(p, chunks[0]);
+    draw_bed_map(f, app, &toolhead, chunks[1]);
+
+    // Turning the motors off or resetting the position
+    if let Some(action) = app.toolhead_action {
+        let title = Paragraph::new(
+            Line::from(vec![
+                Span::styled(format!("Confirm {}", action.name()), Style::default().add_modifier(Modifier::BOLD))
+            ]).alignment(Alignment::Center)
+        );
+        let text = Paragraph::new(match action {
+            ToolheadAction::MotorsOff => "This turns the motors off with M84. The axes must be homed again before moving.",
+            ToolheadAction::ResetPosition => "This makes the current position X0 Y0 Z0 with G92. It only offsets the gcode coordinates, the axes are not homed again.",
+        });
+
+        let ok: Button = Button::new("OK".to_string(), Some("󰌑 ".to_string()));
+        let cancel = Button::new("Back".to_string(), Some("󱊷 ".to_string()));
+        let btn = Table::new(vec![
+            Row::new(vec![
+                Line::from(action_button(ok)).alignment(Alignment::Left),
+                Line::from(action_button(cancel)).alignment(Alignment::Right),
+            ])
+        ])
+        .widths(&[
+            Constraint::Percentage(50),
+            Constraint::Percentage(50),
+        ])
+        .block(Block::default()
+            .borders(Borders::NONE)
+            .padding(Padding::horizontal(2))
+        )
+        ;
+
+        modal(f, title, text, btn, None);
+    }
 
     let buttons = vec![
         Button::new("Help".to_string(), Some("1".to_string())),
@@ -86,16 +163,87 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Console".to_string(), Some("5".to_string())),
         Button::new("Webcam".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
-    header::draw_footer(f, chunks[1], buttons);
+    header::draw_footer(f, chunks[2], buttons);
 
 }
 
+/// Top view of the bed with the nozzle and its recent path, and the height of Z on the side
+fn draw_bed_map<B>(f: &mut Frame<B>, app: &App, toolhead: &klipper::Toolhead, area: Rect)
+where
+    B: Backend,
+{
+    let limit = |limits: &Option<Vec<f64>>, axis: usize| limits.as_ref().and_then(|l| l.get(axis)).copied();
+    let (min_x, min_y, min_z) = (limit(&toolhead.axis_minimum, 0), limit(&toolhead.axis_minimum, 1), limit(&toolhead.axis_minimum, 2));
+    let (max_x, max_y, max_z) = (limit(&toolhead.axis_maximum, 0), limit(&toolhead.axis_maximum, 1), limit(&toolhead.axis_maximum, 2));
+    let (min_x, min_y, max_x, max_y) = match (min_x, min_y, max_x, max_y) {
+        (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) if max_x > min_x && max_y > min_y => (min_x, min_y, max_x, max_y),
+        _ => {
+            f.render_widget(Paragraph::new("Waiting for the axis limits").alignment(Alignment::Center), area);
+            return;
+        }
+    };
 
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(10), Constraint::Length(12)].as_ref())
+        .split(area);
+
+    // Terminal cells are about twice as high as they are wide, keep the bed proportions
+    let map = chunks[0];
+    let width = ((map.height.saturating_sub(2) as f64 * 2.0 * (max_x - min_x) / (max_y - min_y)) as u16 + 2).min(map.width);
+    let map = Rect { x: map.x + (map.width - width) / 2, width, ..map };
+
+    let position = &app.printer.toolhead.position;
+    let now = Instant::now();
+    let trail: Vec<(f64, f64)> = app.printer.toolhead.trail.iter()
+        .filter(|(t, _)| now.saturating_duration_since(*t) < TRAIL_DURATION)
+        .map(|(_, p)| (p.x, p.y))
+        .chain(std::iter::once((position.x, position.y)))
+        .collect();
+    // A cross twenty times smaller than the bed
+    let (dx, dy) = ((max_x - min_x) / 40.0, (max_y - min_y) / 40.0);
+    let canvas = Canvas::default()
+        .block(Block::default().borders(Borders::ALL).title("Bed"))
+        .marker(Marker::Braille)
+        .x_bounds([min_x, max_x])
+        .y_bounds([min_y, max_y])
+        .paint(move |ctx| {
+            ctx.draw(&Rectangle { x: min_x, y: min_y, width: max_x - min_x, height: max_y - min_y, color: Color::DarkGray });
+            for segment in trail.windows(2) {
+                ctx.draw(&CanvasLine { x1: segment[0].0, y1: segment[0].1, x2: segment[1].0, y2: segment[1].1, color: Color::Yellow });
+            }
+            ctx.layer();
+            ctx.draw(&CanvasLine { x1: position.x - dx, y1: position.y, x2: position.x + dx, y2: position.y, color: Color::LightRed });
+            ctx.draw(&CanvasLine { x1: position.x, y1: position.y - dy, x2: position.x, y2: position.y + dy, color: Color::LightRed });
+        });
+    f.render_widget(canvas, map);
+
+    let block = Block::default().borders(Borders::ALL).title("Z");
+    let inner = block.inner(chunks[1]);
+    f.render_widget(block, chunks[1]);
+    let lines = z_gauge(position.z, min_z.unwrap_or(0.0), max_z.unwrap_or(0.0), inner.height.saturating_sub(1) as usize, inner.width as usize);
+    f.render_widget(Paragraph::new(lines), inner);
+}
+
+/// Lines of a vertical gauge filled up to `z`, with the height printed below
+fn z_gauge(z: f64, min: f64, max: f64, height: usize, width: usize) -> Vec<Line<'static>> {
+    let ratio = if max > min { ((z - min) / (max - min)).clamp(0.0, 1.0) } else { 0.0 };
+    let filled = (ratio * height as f64).round() as usize;
+    let mut lines: Vec<Line> = (0..height).rev().map(|row| {
+        if row < filled {
+            Line::from(Span::styled(" ".repeat(width), Style::default().bg(Color::Cyan)))
+        } else {
+            Line::from(Span::styled(" ".repeat(width), Style::default().bg(Color::DarkGray)))
+        }
+    }).collect();
+    lines.push(Line::from(format!("{:.2}mm", z)).alignment(Alignment::Center));
+    lines
+}
 
 
-pub fn draw_toolhead_help<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_toolhead_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -129,7 +277,7 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Console".to_string(), Some("5".to_string())),
         Button::new("Webcam".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
     header::draw_footer(f, chunks[1], buttons);
 
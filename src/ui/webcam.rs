@@ -1,11 +1,9 @@
-use std::{fs::{self, File}, io::Write};
+use std::fs;
 
-use chrono::Timelike;
-use curl::easy::Easy;
-use tui::{widgets::{Borders, Paragraph, Block, Wrap, Scrollbar, ScrollbarOrientation}, prelude::*};
+use tui::{widgets::{Borders, Paragraph, Block, Wrap}, prelude::*};
 use viuer::{print_from_file, Config};
 
-use crate::{ui::header, button::Button, markdown, app::{App, InputMode}, printer::GCodeLine};
+use crate::{ui::header, button::Button, markdown, app::App};
 
 const TAB_HELP_TEXT: &str = "
 # Webcam Help
@@ -17,7 +15,7 @@ Press Enter to send the command to the printer.
 ";
 
 
-pub fn draw_tab<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_tab<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -52,32 +50,20 @@ where
 
     f.render_widget(p, chunks[0]);
     // Download and render stream snapshot
-    let mut easy = Easy::new();
     //log::info!("webcams {:?}", app.printer.webcams);
-    if let Some(w) = app.printer.webcams.get(0) {
-        let mut wc = w.clone();
-        if wc.render % 10 == 0 {
-            easy.url(format!("http://{}{}", app.server_url.clone(), wc.snapshot_url.clone()).as_str()).unwrap();
-
-            let _ = fs::create_dir_all("cache/stream/");
-            let filepath = "cache/stream/1.jpg";
-            let file = match File::create(filepath.clone()) {
-                Ok(f) => Some(f),
-                Err(e) => {
-                    log::error!("Error creating file {:?}", e);
-                    None
-                }
-            };
-            if let Some(mut handle) = file {
-                let mut transfer = easy.transfer();
-                transfer.write_function(|data| {
-                    // write data to a temporary file
-                    //log::info!("Writing {} bytes to file {:?}", data.len(), filepath);
-                    handle.write_all(data).unwrap();
-                    
-                    Ok(data.len())
-                }).unwrap();
-                transfer.perform().unwrap();
+    let index = match &app.webcam {
+        Some(name) => app.printer.webcams.iter().position(|w| &w.name == name),
+        None if !app.printer.webcams.is_empty() => Some(0),
+        None => None,
+    };
+    if let Some(i) = index {
+        let mut wc = app.printer.webcams[i].clone();
+        if wc.render.is_multiple_of(10) {
+            let dir = app.cache_dir.join("stream");
+            let _ = fs::create_dir_all(&dir);
+            let filepath = dir.join(format!("{}.jpg", app.name.replace(|c: char| !c.is_alphanumeric(), "_")));
+            if let Err(e) = app.server.download(wc.snapshot_url.as_str(), &filepath) {
+                log::error!("Error downloading snapshot {:?}", e);
             }
             let max_height = chunks[0].height as u32 - 5;
             let w = max_height * 4 / 3;
@@ -89,16 +75,15 @@ where
 
                 ..Default::default()
             };
-        
-            match print_from_file(filepath, &conf) {
-                Ok(_) => {},
-                Err(_) => {},
-            };
+
+            if let Err(e) = print_from_file(filepath, &conf) {
+                log::error!("Error showing snapshot {:?}", e);
+            }
         }
 
         wc.render = wc.render.wrapping_add(1);
 
-        app.printer.webcams[0] = wc;
+        app.printer.webcams[i] = wc;
     }
 
 
@@ -114,7 +99,7 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Console".to_string(), Some("5".to_string())),
         Button::new("Close".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
     header::draw_footer(f, chunks[1], buttons);
 
@@ -123,7 +108,7 @@ where
 
 
 
-pub fn draw_help<'a, B>(f: &mut Frame<B>, app: &mut App, area: Rect)
+pub fn draw_help<B>(f: &mut Frame<B>, app: &mut App, area: Rect)
 where
     B: Backend,
 {
@@ -156,7 +141,7 @@ where
         Button::new("Extruder".to_string(), Some("4".to_string())),
         Button::new("Console".to_string(), Some("5".to_string())),
         Button::new("Webcam".to_string(), Some("6".to_string())),
-        Button::new(if app.printer.connected {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
+        Button::new(if app.is_ready() {"STOP".to_string()} else {"Restart".to_string()}, Some("10".to_string())),
     ];
     header::draw_footer(f, chunks[1], buttons);
 
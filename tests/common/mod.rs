@@ -0,0 +1,519 @@
+//! In-process Moonraker, to test krui without a printer.
+//!
+//! The websocket and the http endpoints are served on the same port, like Moonraker
+//! does, and JSON-RPC is also served on a unix socket. Requests are answered from
+//! [`Fixtures`], and the tests push notifications with [`MockMoonraker::notify`] and
+//! [`MockMoonraker::update_status`].
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use flume::{Receiver, Sender};
+use serde_json::{Map, Value, json};
+use websocket::sync::server::IntoWs;
+use websocket::{CloseData, OwnedMessage};
+
+use krui::app::App;
+use krui::server::Server;
+use krui::transport::{Transport, UnixTransport, WebsocketTransport};
+
+/// Oneshot token handed out when an API key is required
+const TOKEN: &str = "mock-oneshot-token";
+/// Longest time [`wait_for`] waits for a condition
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the mock Moonraker knows about its printer.
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    /// Klipper objects, by name, as returned by `printer.objects.query`
+    pub status: Map<String, Value>,
+    pub klippy_state: String,
+    /// Jobs returned by `server.history.list`
+    pub jobs: Vec<Value>,
+    pub webcams: Vec<Value>,
+    /// Results of `server.files.metadata`, by filename
+    pub metadata: HashMap<String, Value>,
+    /// Files served over http, by path relative to the gcodes directory
+    pub files: HashMap<String, Vec<u8>>,
+    /// When set, http requests need this `X-Api-Key` and the websocket a oneshot token
+    pub api_key: Option<String>,
+}
+
+impl Default for Fixtures {
+    fn default() -> Self {
+        let mut status: Map<String, Value> = serde_json::from_str(include_str!("../../data.json")).unwrap();
+        // data.json was captured without the objects krui subscribes to first
+        let extra = json!({
+            "webhooks": {"state": "ready", "state_message": "Printer is ready"},
+            "heaters": {
+                "available_heaters": ["extruder", "heater_bed"],
+                "available_sensors": ["extruder", "heater_bed", "temperature_fan exhaust_fan"],
+            },
+            "print_stats": {"state": "standby", "filename": "", "print_duration": 0.0, "total_duration": 0.0, "filament_used": 0.0},
+            "virtual_sdcard": {"progress": 0.0, "is_active": false},
+            "display_status": {"progress": 0.0, "message": null},
+            "fan": {"speed": 0.0},
+        });
+        for (name, object) in extra.as_object().unwrap() {
+            status.insert(name.clone(), object.clone());
+        }
+        Fixtures {
+            status,
+            klippy_state: "ready".to_string(),
+            jobs: vec![json!({
+                "job_id": "000001",
+                "filename": "cube.gcode",
+                "status": "completed",
+                "start_time": 1_690_000_000.0,
+                "end_time": 1_690_003_600.0,
+                "total_duration": 3600.0,
+                "print_duration": 3500.0,
+                "filament_used": 1234.5,
+                "metadata": {"estimated_time": 3400.0},
+            })],
+            webcams: vec![json!({
+                "name": "nozzle",
+                "stream_url": "/webcam/?action=stream",
+                "snapshot_url": "/webcam/?action=snapshot",
+                "flip_horizontal": false,
+                "flip_vertical": false,
+            })],
+            metadata: HashMap::from([("benchy.gcode".to_string(), json!({
+                "filename": "benchy.gcode",
+                "size": 2_048_000,
+                "slicer": "PrusaSlicer",
+                "layer_height": 0.2,
+                "first_layer_height": 0.25,
+                "object_height": 48.0,
+                "filament_total": 4500.0,
+                "estimated_time": 5400.0,
+                "thumbnails": [{"width": 32, "height": 32, "size": 9, "relative_path": ".thumbs/benchy-32x32.png"}],
+            }))]),
+            files: HashMap::from([(".thumbs/benchy-32x32.png".to_string(), b"not a png".to_vec())]),
+            api_key: None,
+        }
+    }
+}
+
+enum Event {
+    Send(String),
+    Drop,
+}
+
+#[derive(Default)]
+struct Shared {
+    fixtures: Mutex<Fixtures>,
+    /// One sender per open connection
+    clients: Mutex<Vec<Sender<Event>>>,
+    /// Every JSON-RPC request received
+    requests: Mutex<Vec<Value>>,
+    connections: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    fn broadcast(&self, method: &str, params: Value) {
+        let text = json!({"jsonrpc": "2.0", "method": method, "params": params}).to_string();
+        self.clients.lock().unwrap().retain(|c| c.send(Event::Send(text.clone())).is_ok());
+    }
+
+    /// Merges `patch` in the Klipper objects and notifies the subscribers
+    fn update_status(&self, patch: Value) {
+        {
+            let mut fixtures = self.fixtures.lock().unwrap();
+            for (name, fields) in patch.as_object().expect("status patch must be an object") {
+                let object = fixtures.status.entry(name.clone()).or_insert_with(|| json!({}));
+                for (field, value) in fields.as_object().expect("object patch must be an object") {
+                    object[field] = value.clone();
+                }
+            }
+        }
+        self.broadcast("notify_status_update", json!([patch, 1.0]));
+    }
+
+    fn set_klippy_state(&self, state: &str) {
+        self.fixtures.lock().unwrap().klippy_state = state.to_string();
+        self.update_status(json!({"webhooks": {"state": state}}));
+        match state {
+            "ready" => self.broadcast("notify_klippy_ready", json!([])),
+            "shutdown" => self.broadcast("notify_klippy_shutdown", json!([])),
+            _ => self.broadcast("notify_klippy_disconnected", json!([])),
+        }
+    }
+}
+
+/// Moonraker listening on a random local port until dropped.
+pub struct MockMoonraker {
+    pub port: u16,
+    shared: Arc<Shared>,
+}
+
+impl MockMoonraker {
+    pub fn start() -> MockMoonraker {
+        MockMoonraker::with_fixtures(Fixtures::default())
+    }
+
+    pub fn with_fixtures(fixtures: Fixtures) -> MockMoonraker {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Laid out like Moonraker's data path, files are read from there over the unix socket
+        let data_path = data_path(port);
+        std::fs::create_dir_all(data_path.join("comms")).unwrap();
+        for (file, content) in fixtures.files.iter() {
+            let path = data_path.join("gcodes").join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        let _ = std::fs::remove_file(data_path.join("comms/moonraker.sock"));
+        let unix_listener = UnixListener::bind(data_path.join("comms/moonraker.sock")).unwrap();
+        unix_listener.set_nonblocking(true).unwrap();
+
+        let shared = Arc::new(Shared { fixtures: Mutex::new(fixtures), ..Shared::default() });
+        let s = shared.clone();
+        thread::spawn(move || {
+            while !s.stopped.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let s = s.clone();
+                        thread::spawn(move || serve(stream, s));
+                    },
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                    Err(e) => panic!("mock moonraker accept failed: {}", e),
+                }
+                match unix_listener.accept() {
+                    Ok((stream, _)) => {
+                        let s = s.clone();
+                        stream.set_nonblocking(false).unwrap();
+                        stream.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+                        thread::spawn(move || serve_jsonrpc(UnixTransport::new(stream), s));
+                    },
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                    Err(e) => panic!("mock moonraker accept failed: {}", e),
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+        MockMoonraker { port, shared }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    pub fn socket_url(&self) -> String {
+        format!("unix://{}", data_path(self.port).join("comms/moonraker.sock").display())
+    }
+
+    /// App connecting to this Moonraker, saving its files in a directory of its own
+    pub fn app(&self, api_key: Option<&str>) -> App {
+        self.app_for(self.url().as_str(), api_key)
+    }
+
+    /// App connecting through the unix socket
+    pub fn local_app(&self) -> App {
+        self.app_for(self.socket_url().as_str(), None)
+    }
+
+    fn app_for(&self, url: &str, api_key: Option<&str>) -> App {
+        let mut app = App::new(Server::parse(url, api_key.map(String::from)).unwrap());
+        app.cache_dir = cache_dir(self.port);
+        app
+    }
+
+    /// Sends a `notify_*` event to every connected client
+    pub fn notify(&self, method: &str, params: Value) {
+        self.shared.broadcast(method, params);
+    }
+
+    /// Changes Klipper objects, like Klipper would while printing
+    pub fn update_status(&self, patch: Value) {
+        self.shared.update_status(patch);
+    }
+
+    /// Klippy becomes `ready`, `shutdown` or `disconnected`
+    pub fn set_klippy_state(&self, state: &str) {
+        self.shared.set_klippy_state(state);
+    }
+
+    /// Current value of a Klipper object
+    pub fn status(&self, object: &str) -> Value {
+        self.shared.fixtures.lock().unwrap().status.get(object).cloned().unwrap_or(Value::Null)
+    }
+
+    /// Closes every connection, as if the network went down
+    pub fn drop_connections(&self) {
+        self.shared.clients.lock().unwrap().retain(|c| c.send(Event::Drop).is_ok());
+    }
+
+    /// Number of websocket and unix socket connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::Relaxed)
+    }
+
+    /// Params of the requests received for `method`
+    pub fn requests(&self, method: &str) -> Vec<Value> {
+        self.shared.requests.lock().unwrap().iter()
+            .filter(|r| r["method"] == method)
+            .map(|r| r.get("params").cloned().unwrap_or(Value::Null))
+            .collect()
+    }
+}
+
+impl Drop for MockMoonraker {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        self.drop_connections();
+        let _ = std::fs::remove_dir_all(cache_dir(self.port));
+        let _ = std::fs::remove_dir_all(data_path(self.port));
+    }
+}
+
+fn cache_dir(port: u16) -> PathBuf {
+    std::env::temp_dir().join(format!("krui-test-{}", port))
+}
+
+fn data_path(port: u16) -> PathBuf {
+    std::env::temp_dir().join(format!("krui-mock-{}", port))
+}
+
+/// Ticks `app` until `condition` holds, or [`WAIT_TIMEOUT`] has passed
+pub fn wait_for(app: &mut App, condition: impl Fn(&App) -> bool) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < WAIT_TIMEOUT {
+        app.tick();
+        if condition(app) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+/// Request line and headers of an http request, lowercased header names
+struct Head {
+    path: String,
+    query: String,
+    headers: HashMap<String, String>,
+    len: usize,
+}
+
+/// Reads the request head without consuming it, so the websocket handshake can parse it again
+fn peek_head(stream: &TcpStream) -> Option<Head> {
+    let mut buf = [0u8; 8192];
+    let start = Instant::now();
+    let (text, len) = loop {
+        let n = stream.peek(&mut buf).ok()?;
+        let text = String::from_utf8_lossy(&buf[..n]).to_string();
+        if let Some(end) = text.find("\r\n\r\n") {
+            break (text[..end].to_string(), end + 4);
+        }
+        if n == 0 || start.elapsed() > WAIT_TIMEOUT {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    let mut lines = text.lines();
+    let target = lines.next()?.split(' ').nth(1)?.to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target.as_str(), ""));
+    let headers = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    Some(Head { path: path.to_string(), query: query.to_string(), headers, len })
+}
+
+fn serve(stream: TcpStream, shared: Arc<Shared>) {
+    stream.set_nonblocking(false).unwrap();
+    let head = match peek_head(&stream) {
+        Some(h) => h,
+        None => return,
+    };
+    let api_key = shared.fixtures.lock().unwrap().api_key.clone();
+    if head.path == "/websocket" {
+        if api_key.is_some() && !head.query.split('&').any(|p| p == format!("token={}", TOKEN)) {
+            respond(stream, &head, 401, b"Unauthorized");
+            return;
+        }
+        let client = match stream.into_ws() {
+            Ok(upgrade) => upgrade.accept().map_err(|(_, e)| e).unwrap(),
+            Err(_) => return,
+        };
+        let _ = client.stream_ref().set_read_timeout(Some(Duration::from_millis(10)));
+        serve_jsonrpc(WebsocketTransport::server(client), shared);
+        return;
+    }
+    if api_key.is_some() && head.headers.get("x-api-key") != api_key.as_ref() {
+        respond(stream, &head, 401, b"Unauthorized");
+        return;
+    }
+    if head.path == "/access/oneshot_token" {
+        let body = json!({"result": TOKEN}).to_string();
+        respond(stream, &head, 200, body.as_bytes());
+    } else if let Some(file) = head.path.strip_prefix("/server/files/gcodes/") {
+        let content = shared.fixtures.lock().unwrap().files.get(file).cloned();
+        match content {
+            Some(c) => respond(stream, &head, 200, &c),
+            None => respond(stream, &head, 404, b"Not Found"),
+        }
+    } else {
+        respond(stream, &head, 404, b"Not Found");
+    }
+}
+
+fn respond(mut stream: TcpStream, head: &Head, code: u16, body: &[u8]) {
+    // Read the request first, closing a socket with unread data resets the connection
+    let mut request = vec![0u8; head.len];
+    let _ = stream.read_exact(&mut request);
+    let reason = match code {
+        200 => "OK",
+        401 => "Unauthorized",
+        _ => "Not Found",
+    };
+    let _ = write!(stream, "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", code, reason, body.len());
+    let _ = stream.write_all(body);
+}
+
+fn serve_jsonrpc(mut client: impl Transport, shared: Arc<Shared>) {
+    let (tx, rx): (Sender<Event>, Receiver<Event>) = flume::unbounded();
+    shared.clients.lock().unwrap().push(tx);
+    let connection_id = shared.connections.fetch_add(1, Ordering::Relaxed) + 1;
+    loop {
+        for event in rx.try_iter() {
+            match event {
+                Event::Send(text) => {
+                    let _ = client.send(&OwnedMessage::Text(text));
+                },
+                Event::Drop => {
+                    let _ = client.send(&OwnedMessage::Close(Some(CloseData::new(1001, "going away".to_string()))));
+                    return;
+                },
+            }
+        }
+        let text = match client.recv() {
+            Ok(OwnedMessage::Text(t)) => t,
+            Ok(OwnedMessage::Close(_)) => return,
+            // krui pings a quiet connection to know that it is still up
+            Ok(OwnedMessage::Ping(data)) => {
+                let _ = client.send(&OwnedMessage::Pong(data));
+                continue;
+            },
+            Ok(_) => continue,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::InvalidData) => continue,
+            Err(_) => return,
+        };
+        let request: Value = match serde_json::from_str(text.as_str()) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        shared.requests.lock().unwrap().push(request.clone());
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let mut response = json!({"jsonrpc": "2.0", "id": request["id"]});
+        match answer(&shared, request["method"].as_str().unwrap_or_default(), &params, connection_id) {
+            Ok(result) => response["result"] = result,
+            Err((code, message)) => response["error"] = json!({"code": code, "message": message}),
+        }
+        let _ = client.send(&OwnedMessage::Text(response.to_string()));
+    }
+}
+
+/// Result of a JSON-RPC request, or its error code and message
+fn answer(shared: &Shared, method: &str, params: &Value, connection_id: usize) -> Result<Value, (i64, String)> {
+    let fixtures = shared.fixtures.lock().unwrap().clone();
+    let ready = fixtures.klippy_state == "ready";
+    if method.starts_with("printer.") && method != "printer.emergency_stop" && !ready {
+        return Err((503, format!("Klippy not ready ({})", fixtures.klippy_state)));
+    }
+    match method {
+        "server.connection.identify" => Ok(json!({"connection_id": connection_id})),
+        "server.info" => Ok(json!({
+            "klippy_connected": fixtures.klippy_state != "disconnected",
+            "klippy_state": fixtures.klippy_state,
+            "components": ["history", "webcam"],
+            "failed_components": [],
+            "registered_directories": ["config", "gcodes"],
+            "warnings": [],
+            "websocket_count": shared.clients.lock().unwrap().len(),
+            "moonraker_version": "mock",
+        })),
+        "printer.objects.list" => Ok(json!({"objects": fixtures.status.keys().collect::<Vec<_>>()})),
+        "printer.objects.query" | "printer.objects.subscribe" => {
+            let mut status = Map::new();
+            if let Some(objects) = params.get("objects").and_then(|o| o.as_object()) {
+                for (name, fields) in objects {
+                    let object = match fixtures.status.get(name) {
+                        Some(o) => o,
+                        // Klipper leaves out unknown objects
+                        None => continue,
+                    };
+                    let object = match fields.as_array() {
+                        Some(fields) => Value::Object(fields.iter()
+                            .filter_map(|f| f.as_str())
+                            .filter_map(|f| object.get(f).map(|v| (f.to_string(), v.clone())))
+                            .collect()),
+                        None => object.clone(),
+                    };
+                    status.insert(name.clone(), object);
+                }
+            }
+            Ok(json!({"eventtime": 1.0, "status": status}))
+        },
+        "server.history.list" => {
+            let limit = params.get("limit").and_then(|l| l.as_u64()).unwrap_or(50) as usize;
+            Ok(json!({"count": fixtures.jobs.len(), "jobs": fixtures.jobs.iter().take(limit).collect::<Vec<_>>()}))
+        },
+        "server.webcams.list" => Ok(json!({"webcams": fixtures.webcams})),
+        "server.files.metadata" => {
+            let filename = params.get("filename").and_then(|f| f.as_str()).unwrap_or_default();
+            fixtures.metadata.get(filename).cloned()
+                .ok_or_else(|| (404, format!("Metadata not available for <{}>", filename)))
+        },
+        "printer.gcode.script" => {
+            let script = params.get("script").and_then(|s| s.as_str()).unwrap_or_default();
+            shared.broadcast("notify_gcode_response", json!([script]));
+            Ok(json!("ok"))
+        },
+        "printer.print.start" => {
+            let filename = params.get("filename").and_then(|f| f.as_str()).unwrap_or_default();
+            if !fixtures.metadata.contains_key(filename) {
+                return Err((400, format!("File not found: {}", filename)));
+            }
+            shared.update_status(json!({
+                "print_stats": {"state": "printing", "filename": filename, "print_duration": 0.0},
+                "virtual_sdcard": {"progress": 0.0, "is_active": true},
+            }));
+            Ok(json!("ok"))
+        },
+        "printer.print.pause" => print_state(shared, &fixtures, "printing", "paused"),
+        "printer.print.resume" => print_state(shared, &fixtures, "paused", "printing"),
+        "printer.print.cancel" => print_state(shared, &fixtures, "printing", "cancelled")
+            .or_else(|_| print_state(shared, &fixtures, "paused", "cancelled")),
+        "printer.emergency_stop" => {
+            shared.set_klippy_state("shutdown");
+            Ok(json!("ok"))
+        },
+        "printer.restart" | "printer.firmware_restart" => {
+            shared.set_klippy_state("ready");
+            Ok(json!("ok"))
+        },
+        _ => Err((-32601, "Method not found".to_string())),
+    }
+}
+
+/// Moves the print from `from` to `to`, like the pause, resume and cancel macros
+fn print_state(shared: &Shared, fixtures: &Fixtures, from: &str, to: &str) -> Result<Value, (i64, String)> {
+    let state = fixtures.status["print_stats"]["state"].as_str().unwrap_or_default();
+    if state != from {
+        return Err((400, format!("Print is not {}", from)));
+    }
+    shared.update_status(json!({"print_stats": {"state": to}}));
+    Ok(json!("ok"))
+}
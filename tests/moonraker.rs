@@ -0,0 +1,182 @@
+mod common;
+
+use std::time::Duration;
+
+use serde_json::json;
+
+use common::{Fixtures, MockMoonraker, wait_for};
+use krui::app::Tab;
+use krui::cli::{self, Command};
+use krui::connection::ConnectionState;
+
+fn heater_temperature(app: &krui::app::App, name: &str) -> Option<f64> {
+    app.printer.status.heaters.items.iter().find(|h| h.name == name).map(|h| h.temperature)
+}
+
+#[test]
+fn test_connects_and_loads_the_printer_state() {
+    let moonraker = MockMoonraker::start();
+    let mut app = moonraker.app(None);
+    app.init();
+    assert!(wait_for(&mut app, |a| a.is_ready() && a.printer.status.print_state != "unknown"));
+    assert!(wait_for(&mut app, |a| !a.printer.webcams.is_empty() && !a.history.items.is_empty()));
+
+    assert_eq!(app.printer.status.state, "ready");
+    assert_eq!(app.printer.status.print_state, "standby");
+    assert_eq!(heater_temperature(&app, "extruder"), Some(27.04));
+    assert_eq!(heater_temperature(&app, "heater_bed"), Some(26.46));
+    assert_eq!(app.history.items[0].filename, "cube.gcode");
+    assert_eq!(app.printer.webcams[0].name, "nozzle");
+    assert_eq!(moonraker.requests("server.connection.identify").len(), 1);
+    assert_eq!(moonraker.requests("printer.objects.subscribe").len(), 1);
+    assert!(app.notice.is_none());
+}
+
+#[test]
+fn test_status_notifications_update_the_printer() {
+    let moonraker = MockMoonraker::start();
+    let mut app = moonraker.app(None);
+    app.init();
+    assert!(wait_for(&mut app, |a| a.is_ready() && a.printer.status.print_state != "unknown"));
+
+    moonraker.update_status(json!({"extruder": {"temperature": 212.5, "target": 215.0}}));
+    assert!(wait_for(&mut app, |a| heater_temperature(a, "extruder") == Some(212.5)));
+    // The raw objects are kept as well
+    assert_eq!(app.printer.objects.field("extruder", "target"), Some(&json!(215.0)));
+    assert!(app.printer.objects.get("webhooks").is_some());
+
+    moonraker.notify("notify_gcode_response", json!(["// probe at 10,10 is z=0.012"]));
+    assert!(wait_for(&mut app, |a| a.printer.status.gcodes.iter().any(|l| l.content.contains("z=0.012"))));
+}
+
+#[test]
+fn test_reconnects_when_the_connection_drops() {
+    let moonraker = MockMoonraker::start();
+    let mut app = moonraker.app(None);
+    app.init();
+    assert!(wait_for(&mut app, |a| a.is_ready()));
+
+    moonraker.drop_connections();
+    assert!(wait_for(&mut app, |a| !a.is_ready()));
+    assert!(wait_for(&mut app, |a| a.is_ready()));
+    assert_eq!(moonraker.connections(), 2);
+    // State changes while away are picked up by the new subscription
+    moonraker.update_status(json!({"heater_bed": {"temperature": 60.0}}));
+    assert!(wait_for(&mut app, |a| heater_temperature(a, "heater_bed") == Some(60.0)));
+}
+
+#[test]
+fn test_subscription_follows_the_tab() {
+    let moonraker = MockMoonraker::start();
+    let mut app = moonraker.app(None);
+    app.init();
+    assert!(wait_for(&mut app, |a| a.is_ready() && a.printer.objects.get("extruder").is_some()));
+    let subscribed = moonraker.requests("printer.objects.subscribe");
+    let objects = subscribed[0]["objects"].as_object().unwrap();
+    assert!(objects.contains_key("extruder") && objects.contains_key("print_stats"));
+    assert!(!objects.contains_key("idle_timeout"));
+    assert!(app.printer.objects.get("idle_timeout").is_none());
+
+    // The inspector shows every object
+    app.current_tab = Tab::Objects;
+    assert!(wait_for(&mut app, |a| a.printer.objects.get("idle_timeout").is_some()));
+    assert_eq!(moonraker.requests("printer.objects.subscribe").len(), 2);
+    app.current_tab = Tab::Main;
+    assert!(wait_for(&mut app, |_| moonraker.requests("printer.objects.subscribe").len() == 3));
+}
+
+#[test]
+fn test_klippy_shutdown_and_restart() {
+    let moonraker = MockMoonraker::start();
+    let mut app = moonraker.app(None);
+    app.init();
+    assert!(wait_for(&mut app, |a| a.is_ready()));
+
+    moonraker.set_klippy_state("shutdown");
+    assert!(wait_for(&mut app, |a| a.connection == ConnectionState::KlippyNotReady));
+    assert_eq!(app.alerts, vec!["Klippy shutdown".to_string()]);
+
+    moonraker.set_klippy_state("ready");
+    assert!(wait_for(&mut app, |a| a.is_ready() && a.printer.status.state == "ready"));
+}
+
+#[test]
+fn test_klippy_not_ready_on_connection() {
+    let moonraker = MockMoonraker::with_fixtures(Fixtures { klippy_state: "startup".to_string(), ..Fixtures::default() });
+    let mut app = moonraker.app(None);
+    app.init();
+    assert!(wait_for(&mut app, |a| a.printer.status.state == "startup"));
+    assert_eq!(app.connection, ConnectionState::KlippyNotReady);
+    // printer.objects.list is refused until Klippy is ready
+    assert!(wait_for(&mut app, |a| a.notice.is_some()));
+}
+
+#[test]
+fn test_current_print_metadata_is_fetched() {
+    let moonraker = MockMoonraker::start();
+    moonraker.update_status(json!({
+        "print_stats": {"state": "printing", "filename": "benchy.gcode", "print_duration": 600.0},
+        "virtual_sdcard": {"progress": 0.25},
+    }));
+    let mut app = moonraker.app(None);
+    app.init();
+    assert!(wait_for(&mut app, |a| a.printer.current_print.as_ref().is_some_and(|p| p.file.estimated_time > 0.0)));
+
+    let print = app.printer.current_print.clone().unwrap();
+    assert_eq!(print.filename, "benchy.gcode");
+    assert_eq!(print.file.slicer, "PrusaSlicer");
+    assert_eq!(std::fs::read(print.image).unwrap(), b"not a png");
+
+    app.fetch_file_metadata("missing.gcode");
+    assert!(wait_for(&mut app, |a| a.notice.as_ref().is_some_and(|n| n.message.contains("missing.gcode"))));
+}
+
+#[test]
+fn test_api_key_is_required() {
+    let fixtures = Fixtures { api_key: Some("secret".to_string()), ..Fixtures::default() };
+    let moonraker = MockMoonraker::with_fixtures(fixtures);
+
+    let mut app = moonraker.app(Some("wrong"));
+    app.init();
+    assert!(wait_for(&mut app, |a| a.connection == ConnectionState::AuthFailed));
+
+    let mut app = moonraker.app(Some("secret"));
+    app.init();
+    assert!(wait_for(&mut app, |a| a.is_ready()));
+}
+
+#[test]
+fn test_headless_commands() {
+    let moonraker = MockMoonraker::start();
+    let mut app = moonraker.app(None);
+
+    assert_eq!(cli::run(&mut app, &Command::Print("benchy.gcode".to_string())), cli::EXIT_OK);
+    assert_eq!(moonraker.status("print_stats")["state"], "printing");
+    assert_eq!(cli::run(&mut app, &Command::Wait { until: "printing".to_string(), timeout: Some(Duration::from_secs(5)) }), cli::EXIT_OK);
+    assert_eq!(cli::run(&mut app, &Command::Resume), cli::EXIT_FAILED);
+    assert_eq!(cli::run(&mut app, &Command::Cancel), cli::EXIT_OK);
+    assert_eq!(cli::run(&mut app, &Command::Wait { until: "complete".to_string(), timeout: None }), cli::EXIT_FAILED);
+    assert_eq!(cli::run(&mut app, &Command::Gcode("M117 hello".to_string())), cli::EXIT_OK);
+    assert_eq!(moonraker.requests("printer.gcode.script"), vec![json!({"script": "M117 hello"})]);
+}
+
+#[test]
+fn test_unix_socket_behaves_like_the_websocket() {
+    let moonraker = MockMoonraker::start();
+    moonraker.update_status(json!({"print_stats": {"state": "printing", "filename": "benchy.gcode"}}));
+    let mut app = moonraker.local_app();
+    app.init();
+    assert!(wait_for(&mut app, |a| a.is_ready() && a.printer.current_print.as_ref().is_some_and(|p| p.file.estimated_time > 0.0)));
+    assert_eq!(heater_temperature(&app, "extruder"), Some(27.04));
+    // Thumbnails are read from Moonraker's data path
+    let print = app.printer.current_print.clone().unwrap();
+    assert_eq!(std::fs::read(print.image).unwrap(), b"not a png");
+
+    moonraker.update_status(json!({"extruder": {"temperature": 150.0}}));
+    assert!(wait_for(&mut app, |a| heater_temperature(a, "extruder") == Some(150.0)));
+
+    moonraker.drop_connections();
+    assert!(wait_for(&mut app, |a| !a.is_ready()));
+    assert!(wait_for(&mut app, |a| a.is_ready()));
+    assert_eq!(moonraker.connections(), 2);
+}
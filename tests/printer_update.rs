@@ -0,0 +1,114 @@
+use proptest::prelude::*;
+use serde_json::{Map, Value, json};
+
+use krui::printer::Printer;
+
+/// Objects that `Printer::update` reads, and some of their fields
+const OBJECTS: &[&str] = &[
+    "webhooks", "heaters", "print_stats", "virtual_sdcard", "motion_report", "toolhead",
+    "quad_gantry_level", "fan", "system_stats", "stepper_enable", "extruder", "heater_bed",
+    "temperature_fan chamber", "filament_switch_sensor runout", "z_tilt", "screws_tilt_adjust",
+];
+const FIELDS: &[&str] = &[
+    "state", "state_message", "available_heaters", "available_sensors", "filename", "total_duration",
+    "print_duration", "filament_used", "info", "total_layer", "current_layer", "progress",
+    "live_position", "live_velocity", "live_extruder_velocity", "homed_axes", "applied", "speed",
+    "sysload", "steppers", "temperature", "target", "power", "filament_detected", "results", "adjust",
+];
+
+fn key(names: &'static [&'static str]) -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => proptest::sample::select(names).prop_map(str::to_string),
+        1 => "[a-z_ ]{0,12}",
+    ]
+}
+
+/// Any JSON value, with keys that are mostly ones krui looks for
+fn json_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<f64>().prop_map(|f| json!(f)),
+        proptest::sample::select(&["printing", "paused", "complete", "ready", "shutdown", "xyz", "extruder", ""][..]).prop_map(Value::from),
+        ".{0,8}".prop_map(Value::from),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 0..6).prop_map(Value::Array),
+        prop::collection::btree_map(key(FIELDS), inner, 0..6).prop_map(|m| Value::Object(m.into_iter().collect())),
+    ])
+}
+
+/// A status update made of any values
+fn status_update() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        9 => prop::collection::btree_map(key(OBJECTS), json_value(), 0..8).prop_map(|m| Value::Object(m.into_iter().collect())),
+        1 => json_value(),
+    ]
+}
+
+/// A printer that went through a realistic update first
+fn printer() -> Printer {
+    let mut printer = Printer::new();
+    printer.update(json!({
+        "webhooks": {"state": "ready", "state_message": "Printer is ready"},
+        "heaters": {"available_heaters": ["extruder", "heater_bed"], "available_sensors": ["temperature_fan chamber"]},
+        "extruder": {"temperature": 210.0, "target": 215.0, "power": 0.5},
+        "heater_bed": {"temperature": 60.0, "target": 60.0, "power": 0.3},
+        "print_stats": {"state": "printing", "filename": "benchy.gcode", "print_duration": 60.0, "info": {"total_layer": 100, "current_layer": 3}},
+        "motion_report": {"live_position": [10.0, 20.0, 0.6, 1.0], "live_velocity": 100.0},
+        "toolhead": {"homed_axes": "xyz"},
+        "fan": {"speed": 1.0},
+        "system_stats": {"sysload": 0.4},
+    }));
+    printer
+}
+
+fn heaters(printer: &Printer) -> Vec<(String, f64, f64, f64)> {
+    printer.status.heaters.items.iter().map(|h| (h.name.clone(), h.temperature, h.target, h.power)).collect()
+}
+
+proptest! {
+    #[test]
+    fn update_never_panics(updates in prop::collection::vec(status_update(), 1..4)) {
+        let mut printer = printer();
+        for update in updates {
+            printer.update(update);
+        }
+    }
+
+    #[test]
+    fn objects_only_change_their_fields(name in proptest::sample::select(&["fan", "system_stats", "motion_report"][..]), value in json_value()) {
+        let mut printer = printer();
+        let before = printer.clone();
+        let mut update = Map::new();
+        update.insert(name.to_string(), value);
+        printer.update(Value::Object(update));
+
+        prop_assert_eq!(heaters(&printer), heaters(&before));
+        prop_assert_eq!(&printer.status.state, &before.status.state);
+        prop_assert_eq!(&printer.status.print_state, &before.status.print_state);
+        prop_assert_eq!(&printer.current_print, &before.current_print);
+        prop_assert_eq!(&printer.toolhead.homed, &before.toolhead.homed);
+        if name != "fan" {
+            prop_assert_eq!(&printer.toolhead.fan, &before.toolhead.fan);
+        }
+        if name != "system_stats" {
+            prop_assert_eq!(printer.sysload, before.sysload);
+        }
+        if name != "motion_report" {
+            prop_assert_eq!(&printer.toolhead.position, &before.toolhead.position);
+        }
+    }
+
+    #[test]
+    fn partial_heater_updates_are_merged(temperature in -50.0..400.0f64, target in 0.0..300.0f64) {
+        let mut printer = printer();
+        printer.update(json!({"extruder": {"temperature": temperature}}));
+        printer.update(json!({"extruder": {"target": target}}));
+        let extruder = &printer.status.heaters.items[0];
+        prop_assert_eq!(extruder.temperature, temperature);
+        prop_assert_eq!(extruder.target, target);
+        prop_assert_eq!(extruder.power, 0.5);
+    }
+}
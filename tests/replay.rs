@@ -0,0 +1,33 @@
+mod common;
+
+use serde_json::json;
+
+use common::{MockMoonraker, wait_for};
+use krui::app::App;
+use krui::record::{self, Recorder};
+
+#[test]
+fn test_recorded_session_is_replayed_without_network() {
+    let path = std::env::temp_dir().join(format!("krui-replay-{}.jsonl", std::process::id()));
+    {
+        let moonraker = MockMoonraker::start();
+        let mut app = moonraker.app(None);
+        app.recorder = Some(Recorder::create(&path).unwrap());
+        app.init();
+        assert!(wait_for(&mut app, |a| a.is_ready() && !a.history.items.is_empty()));
+        moonraker.update_status(json!({"extruder": {"temperature": 180.0, "target": 215.0}}));
+        assert!(wait_for(&mut app, |a| a.printer.status.heaters.items.iter().any(|h| h.temperature == 180.0)));
+    }
+
+    let frames = record::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(frames.iter().any(|f| f.sent));
+
+    let mut app = App::default();
+    app.replay(frames, 20.0);
+    assert!(wait_for(&mut app, |a| a.printer.status.heaters.items.iter().any(|h| h.name == "extruder" && h.temperature == 180.0)));
+    assert!(app.is_ready());
+    assert_eq!(app.printer.status.print_state, "standby");
+    assert_eq!(app.history.items[0].filename, "cube.gcode");
+    assert_eq!(app.printer.webcams.len(), 1);
+}